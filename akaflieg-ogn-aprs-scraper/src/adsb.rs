@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Sender;
+use tokio::time::{interval, sleep, MissedTickBehavior};
+
+use tracing::{debug, error, info, warn};
+
+use ogn_aprs_parser::model::ogn_object_position::OGNObjectPosition;
+use ogn_aprs_parser::model::ogn_object_velocity::OGNObjectVelocity;
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+use ogn_aprs_parser::ogn::aprs_message_types::APRSMessageType;
+use ogn_aprs_parser::ogn::ogn_address_type::AddressType;
+use ogn_aprs_parser::ogn::ogn_aircraft_types::AircraftType;
+use ogn_aprs_parser::ogn::utils::feet_to_m;
+
+use crate::emitter::EmitterCategory;
+use crate::influx_schema::receive_time;
+use crate::metrics::SOURCE_RECONNECTS;
+use crate::record::Packet;
+use crate::source::{Source, SourceError, RECONNECT_DELAY};
+
+// forget the speed and track of aircraft not heard for this long.
+const FORGET_AFTER: chrono::Duration = chrono::Duration::minutes(10);
+
+// Aircraft only seen on ADS-B, read from a local dump1090 or readsb, e.g. tow planes and motor
+// gliders without FLARM. Their positions go to the sinks as parsed messages with an ICAO address,
+// see `Packet::position`, so they're stored and merge with the OGN positions of the same address.
+// They have no APRS line, so neither the archive nor the aprs-is server get them. Beast output
+// isn't decoded, use the SBS or json output instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdsbPosition {
+    // the ICAO address as 6 upper case hex digits.
+    pub address: String,
+    pub aircraft_type: AircraftType,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude_ft: i32,
+    // whether `altitude_ft` is barometric, and so off from GPS altitudes by the QNH difference.
+    pub barometric: bool,
+    pub track: Option<f64>,
+    pub speed_kt: Option<f64>,
+    pub vertical_fpm: Option<i32>,
+}
+
+impl AdsbPosition {
+    // the position as parsed message, from the source `source` at `time`. The path is `ADSB,<source>`,
+    // which tells the positions apart from OGN ones in storage, or `ADSB-BARO,<source>` if the
+    // altitude is barometric rather than a GPS altitude like those of OGN.
+    pub fn message(&self, source: &str, time: DateTime<Utc>) -> OGNStatusMessage {
+        OGNStatusMessage {
+            aircraft_id: Some(self.address.clone()),
+            timestamp: time,
+            aprs_callsign: format!("ICA{}", self.address),
+            aprs_path: format!("{},{}", if self.barometric { "ADSB-BARO" } else { "ADSB" }, source),
+            aprs_type: APRSMessageType::PositionWithTimestamp,
+            position: OGNObjectPosition {
+                latitude: self.latitude as f32,
+                longitude: self.longitude as f32,
+                heading: self.track.unwrap_or_default() as f32,
+                altitude: feet_to_m(self.altitude_ft as f32),
+            },
+            velocity: OGNObjectVelocity::new(
+                self.speed_kt.map_or(0, |speed| speed.round().max(0.0) as u32),
+                self.vertical_fpm,
+                None,
+            ),
+            aircraft_type: self.aircraft_type.clone(),
+            ogn_flags: None,
+            address_type: Some(AddressType::ICAO),
+            reception: None,
+            bit_errors: None,
+            frequency_offset: None,
+            gps_resolution: None,
+            position_precision: None,
+        }
+    }
+}
+
+// ICAO addresses only, readsb marks others, e.g. TIS-B, with a `~`.
+fn icao_address(hex: &str) -> Option<String> {
+    (hex.len() == 6 && hex.bytes().all(|byte| byte.is_ascii_hexdigit())).then(|| hex.to_uppercase())
+}
+
+#[derive(Debug, Default)]
+struct SbsAircraft {
+    altitude_ft: Option<i32>,
+    track: Option<f64>,
+    speed_kt: Option<f64>,
+    vertical_fpm: Option<i32>,
+    last_heard: DateTime<Utc>,
+}
+
+// BaseStation (SBS-1) lines, as served by dump1090 and readsb on port 30003. Positions, velocities
+// and altitudes come in separate `MSG` lines, so the last of each is kept per aircraft and a
+// position is made of every line with a latitude and longitude. Altitudes are barometric, dump1090
+// doesn't pass geometric ones on in SBS.
+#[derive(Debug, Default)]
+pub struct SbsDecoder {
+    aircraft: HashMap<String, SbsAircraft>,
+}
+
+impl SbsDecoder {
+    pub fn decode(&mut self, line: &str, now: DateTime<Utc>) -> Option<AdsbPosition> {
+        let fields: Vec<&str> = line.trim_end().split(',').collect();
+        if fields.len() < 17 || fields[0] != "MSG" {
+            return None;
+        }
+        let address = icao_address(fields[4])?;
+        let field = |i: usize| fields[i].trim().parse::<f64>().ok();
+
+        if !self.aircraft.contains_key(&address) {
+            self.aircraft.retain(|_, aircraft| now - aircraft.last_heard < FORGET_AFTER);
+        }
+        let aircraft = self.aircraft.entry(address.clone()).or_default();
+        aircraft.last_heard = now;
+        aircraft.altitude_ft = field(11).map(|altitude| altitude.round() as i32).or(aircraft.altitude_ft);
+        aircraft.speed_kt = field(12).or(aircraft.speed_kt);
+        aircraft.track = field(13).or(aircraft.track);
+        aircraft.vertical_fpm = field(16).map(|climb| climb.round() as i32).or(aircraft.vertical_fpm);
+
+        Some(AdsbPosition {
+            address,
+            aircraft_type: AircraftType::Unknown,
+            latitude: field(14)?,
+            longitude: field(15)?,
+            altitude_ft: aircraft.altitude_ft?,
+            barometric: true,
+            track: aircraft.track,
+            speed_kt: aircraft.speed_kt,
+            vertical_fpm: aircraft.vertical_fpm,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ReadsbJson {
+    now: f64,
+    aircraft: Vec<ReadsbJsonAircraft>,
+}
+
+#[derive(Deserialize)]
+struct ReadsbJsonAircraft {
+    hex: String,
+    category: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    // a number, or "ground".
+    alt_baro: Option<serde_json::Value>,
+    alt_geom: Option<f64>,
+    gs: Option<f64>,
+    track: Option<f64>,
+    geom_rate: Option<f64>,
+    baro_rate: Option<f64>,
+    seen_pos: Option<f64>,
+}
+
+// The aircraft.json of readsb, tar1090 or dump1090-fa. Every poll has all aircraft, those whose
+// position wasn't updated since the last poll are left out. Geometric altitudes are preferred, as
+// OGN altitudes are GPS altitudes; aircraft that only report a barometric one are marked as such.
+#[derive(Debug, Default)]
+pub struct ReadsbJsonDecoder {
+    // the time of the last position of each aircraft.
+    positions: HashMap<String, DateTime<Utc>>,
+}
+
+impl ReadsbJsonDecoder {
+    pub fn decode(&mut self, json: &[u8]) -> serde_json::Result<Vec<(DateTime<Utc>, AdsbPosition)>> {
+        let json: ReadsbJson = serde_json::from_slice(json)?;
+        let mut positions = HashMap::new();
+        let mut new = Vec::new();
+        for aircraft in json.aircraft {
+            let (Some(address), Some(latitude), Some(longitude), Some(seen_pos)) =
+                (icao_address(&aircraft.hex), aircraft.lat, aircraft.lon, aircraft.seen_pos)
+            else {
+                continue;
+            };
+            let (altitude, barometric) = match (aircraft.alt_geom, aircraft.alt_baro.as_ref().and_then(serde_json::Value::as_f64)) {
+                (Some(altitude), _) => (altitude, false),
+                (None, Some(altitude)) => (altitude, true),
+                (None, None) => continue,
+            };
+
+            let Some(time) = Utc.timestamp_millis_opt(((json.now - seen_pos) * 1000.0) as i64).single() else {
+                continue;
+            };
+            if self.positions.get(&address).is_none_or(|last| *last < time) {
+                let aircraft_type = aircraft
+                    .category
+                    .as_deref()
+                    .and_then(EmitterCategory::from_name)
+                    .map_or(AircraftType::Unknown, EmitterCategory::aircraft_type);
+                new.push((
+                    time,
+                    AdsbPosition {
+                        address: address.clone(),
+                        aircraft_type,
+                        latitude,
+                        longitude,
+                        altitude_ft: altitude.round() as i32,
+                        barometric,
+                        track: aircraft.track,
+                        speed_kt: aircraft.gs,
+                        vertical_fpm: aircraft.geom_rate.or(aircraft.baro_rate).map(|climb| climb.round() as i32),
+                    },
+                ));
+            }
+            positions.insert(address, time);
+        }
+        // aircraft no longer in the json are forgotten.
+        self.positions = positions;
+        Ok(new)
+    }
+}
+
+async fn send(name: &Arc<str>, position: &AdsbPosition, time: DateTime<Utc>, aprs_tx: &Sender<Packet>) -> Result<(), SourceError> {
+    debug!("[{}] (adsb) {:?}", name, position);
+    let packet = Packet { received: receive_time(), source: name.clone(), line: String::new(), position: Some(position.message(name, time)) };
+    aprs_tx.send(packet).await?;
+    Ok(())
+}
+
+// The SBS output of dump1090 or readsb, usually port 30003.
+pub struct SbsSource {
+    pub name: Arc<str>,
+    pub addr: String,
+}
+
+impl SbsSource {
+    async fn read(&self, stream: TcpStream, aprs_tx: &Sender<Packet>) -> Result<(), SourceError> {
+        let mut decoder = SbsDecoder::default();
+        let mut lines = BufReader::new(stream).lines();
+        while let Some(line) = lines.next_line().await? {
+            let received = receive_time();
+            if let Some(position) = decoder.decode(&line, received) {
+                send(&self.name, &position, received, aprs_tx).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Source for SbsSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&mut self, aprs_tx: Sender<Packet>) -> Result<(), SourceError> {
+        loop {
+            let result = match TcpStream::connect(&self.addr).await {
+                Ok(stream) => {
+                    info!("[{}] connected to {:?}", self.name, self.addr);
+                    self.read(stream, &aprs_tx).await
+                }
+                Err(err) => Err(err.into()),
+            };
+            if aprs_tx.is_closed() {
+                return Ok(());
+            }
+            if let Err(err) = result {
+                error!("[{}] {:?}", self.name, err);
+            }
+            warn!("[{}] disconnected, reconnecting in {:?}", self.name, RECONNECT_DELAY);
+            SOURCE_RECONNECTS.with_label_values(&[&self.name]).inc();
+            sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+async fn fetch(url: &str) -> reqwest::Result<Bytes> {
+    reqwest::get(url).await?.error_for_status()?.bytes().await
+}
+
+// The aircraft.json of readsb or tar1090, polled every `interval`.
+pub struct ReadsbJsonSource {
+    pub name: Arc<str>,
+    pub url: String,
+    pub interval: Duration,
+}
+
+#[async_trait]
+impl Source for ReadsbJsonSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&mut self, aprs_tx: Sender<Packet>) -> Result<(), SourceError> {
+        let mut decoder = ReadsbJsonDecoder::default();
+        let mut tick = interval(self.interval);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        info!("[{}] polling {:?}", self.name, self.url);
+        loop {
+            tick.tick().await;
+            // a failed poll, even one that broke off mid-transfer, is retried with the next one.
+            let json = match fetch(&self.url).await {
+                Ok(json) => json,
+                Err(err) => {
+                    warn!("[{}] error polling {}: {:?}", self.name, self.url, err);
+                    continue;
+                }
+            };
+            match decoder.decode(&json) {
+                Ok(positions) => {
+                    for (time, position) in positions {
+                        send(&self.name, &position, time, &aprs_tx).await?;
+                    }
+                }
+                Err(err) => warn!("[{}] invalid aircraft json: {:?}", self.name, err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // port 30003 of dump1090: identification, velocity and position in separate lines.
+    const SBS: [&str; 3] = [
+        "MSG,1,1,1,3E6DBA,1,2023/02/19,11:24:36.972,2023/02/19,11:24:37.001,DEKLA   ,,,,,,,,0,0,0,0",
+        "MSG,4,1,1,3E6DBA,1,2023/02/19,11:24:37.118,2023/02/19,11:24:37.152,,,80,206,,,-320,,0,0,0,0",
+        "MSG,3,1,1,3E6DBA,1,2023/02/19,11:24:37.503,2023/02/19,11:24:37.539,,3500,,,48.54095,8.06425,,,0,0,0,0",
+    ];
+
+    #[test]
+    fn sbs_messages_become_ogn_positions() {
+        let time = Utc.with_ymd_and_hms(2023, 2, 19, 11, 24, 38).unwrap();
+        let mut decoder = SbsDecoder::default();
+        assert_eq!(decoder.decode(SBS[0], time), None);
+        assert_eq!(decoder.decode(SBS[1], time), None);
+        let position = decoder.decode(SBS[2], time).unwrap();
+        assert_eq!(
+            position,
+            AdsbPosition {
+                address: "3E6DBA".to_owned(),
+                aircraft_type: AircraftType::Unknown,
+                latitude: 48.54095,
+                longitude: 8.06425,
+                altitude_ft: 3500,
+                barometric: true,
+                track: Some(206.0),
+                speed_kt: Some(80.0),
+                vertical_fpm: Some(-320),
+            }
+        );
+
+        let msg = position.message("adsb", time);
+        assert_eq!((msg.aircraft_id.as_deref(), msg.address_type), (Some("3E6DBA"), Some(AddressType::ICAO)));
+        assert_eq!((msg.aprs_callsign.as_str(), msg.aprs_path.as_str()), ("ICA3E6DBA", "ADSB-BARO,adsb"));
+        assert_eq!(msg.timestamp, time);
+        assert!((msg.position.altitude - 1066.8).abs() < 0.01 && msg.position.heading == 206.0);
+        assert!((msg.velocity.horizontal - 41.16).abs() < 0.01);
+
+        // not an ICAO address.
+        assert_eq!(decoder.decode("MSG,3,1,1,~3E6DB,1,,,,,,3500,,,48.5,8.0,,,0,0,0,0", time), None);
+    }
+
+    #[test]
+    fn new_readsb_json_positions_become_ogn_positions() {
+        // the glider's position is from 15:40:06.
+        let json = |now: f64| {
+            format!(
+                r#"{{"now":{},"messages":100,"aircraft":[
+                    {{"hex":"3e6dba","category":"B1","lat":48.968334,"lon":8.348166,"alt_baro":1100,"alt_geom":1145,"gs":50.0,"track":301.0,"geom_rate":64,"seen_pos":{},"seen":0.1}},
+                    {{"hex":"4b1805","alt_baro":"ground","lat":48.97,"lon":8.35,"seen_pos":1.0}},
+                    {{"hex":"4b1806","alt_baro":2500,"lat":48.97,"lon":8.35,"seen_pos":{}}},
+                    {{"hex":"~df153a","lat":48.97,"lon":8.35,"alt_baro":2000,"seen_pos":1.0}},
+                    {{"hex":"3c4b26","alt_baro":35000,"seen":1.0}}
+                ]}}"#,
+                now,
+                now - 1676821206.0,
+                now - 1676821206.0
+            )
+        };
+        let mut decoder = ReadsbJsonDecoder::default();
+        let positions = decoder.decode(json(1676821206.5).as_bytes()).unwrap();
+        assert_eq!(positions.len(), 2);
+        let (time, glider) = &positions[0];
+        assert_eq!(*time, Utc.with_ymd_and_hms(2023, 2, 19, 15, 40, 6).unwrap());
+        assert_eq!((glider.altitude_ft, glider.barometric, glider.vertical_fpm), (1145, false, Some(64)));
+        let msg = glider.message("adsb", *time);
+        assert_eq!((msg.aircraft_type, msg.address_type), (AircraftType::Glider, Some(AddressType::ICAO)));
+        assert_eq!(msg.aprs_path, "ADSB,adsb");
+        // without a geometric altitude, the barometric one is marked.
+        let (time, baro) = &positions[1];
+        assert_eq!((baro.altitude_ft, baro.barometric), (2500, true));
+        assert_eq!(baro.message("adsb", *time).aprs_path, "ADSB-BARO,adsb");
+
+        // the same position again isn't repeated.
+        assert!(decoder.decode(json(1676821207.5).as_bytes()).unwrap().is_empty());
+
+        // nor are positions whose time is out of range.
+        let json = br#"{"now":1e20,"aircraft":[{"hex":"3e6dba","lat":48.97,"lon":8.35,"alt_geom":1145,"seen_pos":1.0}]}"#;
+        assert!(ReadsbJsonDecoder::default().decode(json).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn readsb_json_polls_go_on_after_a_broken_transfer() {
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::TcpListener;
+        use tokio::sync::mpsc;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/data/aircraft.json", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let json = r#"{"now":1676821206.5,"aircraft":[{"hex":"3e6dba","lat":48.97,"lon":8.35,"alt_geom":1145,"seen_pos":0.5}]}"#;
+            // the first response breaks off in the middle of the body, the second is complete.
+            for body in [&json[..10], json] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = BufReader::new(&mut stream).lines();
+                while request.next_line().await.unwrap().is_some_and(|line| !line.is_empty()) {}
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", json.len(), body);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let mut source = ReadsbJsonSource { name: "adsb".into(), url, interval: Duration::from_millis(10) };
+        let (tx, mut rx) = mpsc::channel(1);
+        let run = tokio::spawn(async move { source.run(tx).await });
+        let packet = rx.recv().await.unwrap();
+        assert_eq!(packet.position.unwrap().aircraft_id.as_deref(), Some("3E6DBA"));
+        assert!(!run.is_finished());
+        run.abort();
+    }
+}
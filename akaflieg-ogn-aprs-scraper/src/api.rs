@@ -0,0 +1,384 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::error::Error;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Duration, Utc};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+
+use tracing::info;
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+use ogn_fleet_state::{Aircraft, BoundingBox, TrackPoint};
+
+use crate::live::{receiver_name, ReceiverInfo, SharedFleet};
+use crate::metrics::PARSED_LINES;
+use crate::readsb::{self, AircraftJson};
+
+// The json api for the live fleet, e.g. for the laptop on the flight line:
+//
+//   GET /api/aircraft                              every aircraft with its last position
+//   GET /api/aircraft/<address>/track?minutes=30   positions of one aircraft since then
+//   GET /api/receivers                             receivers and what they relayed
+//   GET /api/stream                                new positions as server-sent events
+//   GET /data/aircraft.json                        the fleet for tar1090, see `readsb`
+//
+// The stream starts with a `snapshot` event holding the last message of every aircraft, followed by
+// a `position` event per message. Clients choose what they get with `aircraft=DF153A,DD1234`,
+// `bbox=<south>,<west>,<north>,<east>` and `types=Glider,TowPlane`; a client that falls more than
+// `live::UPDATES_BUFFER` positions behind gets a `dropped` event and is disconnected.
+//
+// With a token, requests must carry it as `Authorization: Bearer <token>`, or as `?token=<token>`
+// for clients that can't set headers, like tar1090 and browsers' EventSource.
+pub struct ApiConfig {
+    pub addr: String,
+    pub token: Option<String>,
+}
+
+// track length when the request doesn't ask for one.
+const DEFAULT_TRACK_MINUTES: i64 = 30;
+
+#[derive(Debug, Serialize)]
+struct AircraftSummary {
+    address: String,
+    callsign: String,
+    aircraft_type: String,
+    latitude: f64,
+    longitude: f64,
+    // meters above sea level.
+    altitude: f64,
+    heading: f64,
+    // meters per second, like the parsed messages.
+    speed: f64,
+    climb: Option<f64>,
+    timestamp: DateTime<Utc>,
+    last_heard: DateTime<Utc>,
+    age_secs: i64,
+    stale: bool,
+    receiver: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Track {
+    address: String,
+    points: Vec<TrackPoint>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrackQuery {
+    minutes: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StreamQuery {
+    aircraft: Option<String>,
+    bbox: Option<String>,
+    types: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct StreamFilter {
+    aircraft: Option<HashSet<String>>,
+    bbox: Option<BoundingBox>,
+    // names of `AircraftType` variants.
+    types: Option<HashSet<String>>,
+}
+
+impl StreamFilter {
+    fn parse(query: StreamQuery) -> Result<Self, String> {
+        let list = |s: String| s.split(',').map(str::to_owned).collect::<HashSet<_>>();
+        let bbox = match query.bbox {
+            Some(bbox) => {
+                let edges: Result<Vec<f64>, _> = bbox.split(',').map(str::parse).collect();
+                match edges.as_deref() {
+                    Ok(&[south, west, north, east]) => Some(BoundingBox { south, west, north, east }),
+                    _ => return Err(format!("invalid bbox: {}", bbox)),
+                }
+            }
+            None => None,
+        };
+        Ok(StreamFilter { aircraft: query.aircraft.map(list), bbox, types: query.types.map(list) })
+    }
+
+    fn matches(&self, msg: &OGNStatusMessage) -> bool {
+        let address = msg.aircraft_id.as_deref().unwrap_or(&msg.aprs_callsign);
+        let (latitude, longitude) = (msg.position.latitude as f64, msg.position.longitude as f64);
+        self.aircraft.as_ref().is_none_or(|aircraft| aircraft.contains(address))
+            && self.bbox.is_none_or(|bbox| bbox.contains(latitude, longitude))
+            && self.types.as_ref().is_none_or(|types| types.contains(&format!("{:?}", msg.aircraft_type)))
+    }
+}
+
+pub fn router(fleet: SharedFleet, token: Option<String>) -> Router {
+    Router::new()
+        .route("/api/aircraft", get(list_aircraft))
+        .route("/api/aircraft/:address/track", get(track))
+        .route("/api/receivers", get(list_receivers))
+        .route("/api/stream", get(stream))
+        .route("/data/aircraft.json", get(aircraft_json))
+        .route_layer(middleware::from_fn_with_state(token.map(Arc::<str>::from), authorize))
+        .with_state(fleet)
+}
+
+pub async fn serve(config: ApiConfig, fleet: SharedFleet) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let listener = TcpListener::bind(&config.addr).await?;
+    info!("serving the fleet api on {:?}", config.addr);
+    axum::serve(listener, router(fleet, config.token)).await?;
+    Ok(())
+}
+
+async fn authorize(State(token): State<Option<Arc<str>>>, request: Request, next: Next) -> Response {
+    if let Some(token) = token {
+        let header = request.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok());
+        let query = Query::<TokenQuery>::try_from_uri(request.uri()).ok().and_then(|query| query.0.token);
+        let given = header.and_then(|value| value.strip_prefix("Bearer ")).or(query.as_deref());
+        if given != Some(&*token) {
+            return (StatusCode::UNAUTHORIZED, "missing or invalid token\n").into_response();
+        }
+    }
+    next.run(request).await
+}
+
+fn summary(aircraft: &Aircraft, now: DateTime<Utc>, stale_after: Duration) -> AircraftSummary {
+    let last = &aircraft.last;
+    AircraftSummary {
+        address: aircraft.address.clone(),
+        callsign: last.aprs_callsign.clone(),
+        aircraft_type: format!("{:?}", last.aircraft_type),
+        latitude: aircraft.latitude(),
+        longitude: aircraft.longitude(),
+        altitude: last.position.altitude as f64,
+        heading: last.position.heading as f64,
+        speed: last.velocity.horizontal as f64,
+        climb: last.velocity.vertical.map(|climb| climb as f64),
+        timestamp: last.timestamp,
+        last_heard: aircraft.last_heard,
+        age_secs: aircraft.age(now).num_seconds(),
+        stale: aircraft.age(now) > stale_after,
+        receiver: receiver_name(&last.aprs_path).map(str::to_owned),
+    }
+}
+
+async fn list_aircraft(State(fleet): State<SharedFleet>) -> Json<Vec<AircraftSummary>> {
+    let live = fleet.read().unwrap();
+    let now = live.clock.now();
+    let stale_after = live.fleet.config().stale_after;
+    let mut aircraft: Vec<_> = live.fleet.aircraft().map(|aircraft| summary(aircraft, now, stale_after)).collect();
+    aircraft.sort_by(|a, b| a.address.cmp(&b.address));
+    Json(aircraft)
+}
+
+async fn track(
+    State(fleet): State<SharedFleet>,
+    Path(address): Path<String>,
+    Query(query): Query<TrackQuery>,
+) -> Result<Json<Track>, (StatusCode, String)> {
+    let minutes = query.minutes.unwrap_or(DEFAULT_TRACK_MINUTES);
+    let live = fleet.read().unwrap();
+    let since = Duration::try_minutes(minutes)
+        .and_then(|duration| live.clock.now().checked_sub_signed(duration))
+        .ok_or((StatusCode::BAD_REQUEST, format!("invalid minutes {}\n", minutes)))?;
+    let aircraft = live.fleet.get(&address).ok_or((StatusCode::NOT_FOUND, format!("unknown aircraft {}\n", address)))?;
+    let points = aircraft.history.iter().filter(|point| point.timestamp >= since).cloned().collect();
+    Ok(Json(Track { address, points }))
+}
+
+async fn list_receivers(State(fleet): State<SharedFleet>) -> Json<Vec<ReceiverInfo>> {
+    let mut receivers: Vec<_> = fleet.read().unwrap().receivers.values().cloned().collect();
+    receivers.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(receivers)
+}
+
+async fn aircraft_json(State(fleet): State<SharedFleet>) -> Json<AircraftJson> {
+    let live = fleet.read().unwrap();
+    Json(readsb::aircraft_json(&live.fleet, live.clock.now(), PARSED_LINES.get()))
+}
+
+async fn stream(
+    State(fleet): State<SharedFleet>,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let filter = StreamFilter::parse(query).map_err(|err| (StatusCode::BAD_REQUEST, err + "\n"))?;
+
+    // subscribe while holding the lock, so no position falls between the snapshot and the stream.
+    let (snapshot, mut updates) = {
+        let live = fleet.read().unwrap();
+        let snapshot: Vec<_> = live.fleet.aircraft().map(|aircraft| &aircraft.last).filter(|msg| filter.matches(msg)).cloned().collect();
+        (snapshot, live.updates.subscribe())
+    };
+
+    let events = async_stream::stream! {
+        yield Ok(json_event("snapshot", &snapshot));
+        loop {
+            match updates.recv().await {
+                Ok(msg) if filter.matches(&msg) => yield Ok(json_event("position", &msg)),
+                Ok(_) => {}
+                // the client doesn't read fast enough, rather than buffering for it without bound
+                // tell it what it missed and let it reconnect for a fresh snapshot.
+                Err(RecvError::Lagged(skipped)) => {
+                    let notice = format!("client too slow, {} positions skipped", skipped);
+                    yield Ok(Event::default().event("dropped").data(notice));
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+fn json_event<T: Serialize>(name: &str, data: &T) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .unwrap_or_else(|err| Event::default().event("error").data(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::RwLock;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use futures::StreamExt;
+    use ogn_fleet_state::{FleetConfig, FleetState};
+    use tower::ServiceExt;
+
+    use crate::live::{LiveFleet, UPDATES_BUFFER};
+
+    // a position of DF153A received `ago` before now.
+    fn update(live: &mut LiveFleet, ago: Duration, position: &str) {
+        update_aircraft(live, "DF153A", ago, position);
+    }
+
+    fn update_aircraft(live: &mut LiveFleet, id: &str, ago: Duration, position: &str) {
+        let time = Utc::now() - ago;
+        let line = format!(
+            "FLR{}>APRS,qAS,RHST:/{}h{}^301/050/A=001145 !W71! id06{} +693fpm",
+            id,
+            time.format("%H%M%S"),
+            position,
+            id
+        );
+        live.update(OGNStatusMessage::from_str(&line, Some(time)).unwrap(), time);
+    }
+
+    async fn get(app: &Router, uri: &str, token: Option<&str>) -> (StatusCode, serde_json::Value) {
+        let mut request = Request::get(uri);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn serves_positions_tracks_and_receivers() {
+        let mut live = LiveFleet::new(FleetState::new(FleetConfig::default()));
+        update(&mut live, Duration::minutes(20), "4858.00N\\00820.00E");
+        update(&mut live, Duration::minutes(2), "4858.10N\\00820.89E");
+        update(&mut live, Duration::minutes(1), "4858.20N\\00821.00E");
+        let app = router(Arc::new(RwLock::new(live)), Some("secret".to_owned()));
+
+        assert_eq!(get(&app, "/api/aircraft", None).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(get(&app, "/api/aircraft", Some("wrong")).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(get(&app, "/api/aircraft?token=wrong", None).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(get(&app, "/api/aircraft?token=secret", None).await.0, StatusCode::OK);
+
+        let (status, aircraft) = get(&app, "/api/aircraft", Some("secret")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(aircraft[0]["address"], "DF153A");
+        assert_eq!(aircraft[0]["aircraft_type"], "Glider");
+        assert_eq!(aircraft[0]["stale"], false);
+        assert!((aircraft[0]["climb"].as_f64().unwrap() - 3.52).abs() < 0.01);
+
+        let (_, track) = get(&app, "/api/aircraft/DF153A/track?minutes=5", Some("secret")).await;
+        assert_eq!(track["points"].as_array().unwrap().len(), 2);
+        let (_, track) = get(&app, "/api/aircraft/DF153A/track", Some("secret")).await;
+        assert_eq!(track["points"].as_array().unwrap().len(), 3);
+        assert_eq!(get(&app, "/api/aircraft/DD1234/track", Some("secret")).await.0, StatusCode::NOT_FOUND);
+        for minutes in [i64::MAX, i64::MIN, -(1 << 40)] {
+            let uri = format!("/api/aircraft/DF153A/track?minutes={}", minutes);
+            assert_eq!(get(&app, &uri, Some("secret")).await.0, StatusCode::BAD_REQUEST);
+        }
+
+        let (_, readsb) = get(&app, "/data/aircraft.json", Some("secret")).await;
+        assert_eq!(readsb["aircraft"][0]["hex"], "~df153a");
+        // tar1090 fetches aircraft.json without headers.
+        let (status, readsb) = get(&app, "/data/aircraft.json?token=secret", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(readsb["aircraft"][0]["hex"], "~df153a");
+
+        let (_, receivers) = get(&app, "/api/receivers", Some("secret")).await;
+        assert_eq!(receivers[0]["name"], "RHST");
+        assert_eq!(receivers[0]["positions"], 3);
+    }
+
+    #[tokio::test]
+    async fn streams_a_snapshot_then_matching_positions() {
+        let mut live = LiveFleet::new(FleetState::new(FleetConfig::default()));
+        update(&mut live, Duration::minutes(1), "4858.10N\\00820.89E");
+        update_aircraft(&mut live, "DD1234", Duration::minutes(1), "4808.00N\\01134.00E");
+        let fleet = Arc::new(RwLock::new(live));
+        let app = router(fleet.clone(), None);
+
+        let request = Request::get("/api/stream?bbox=48.5,8.0,49.5,9.0").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+        let snapshot = String::from_utf8(body.next().await.unwrap().unwrap().to_vec()).unwrap();
+        assert!(snapshot.starts_with("event: snapshot\n"), "{}", snapshot);
+        assert!(snapshot.contains("DF153A") && !snapshot.contains("DD1234"));
+
+        update_aircraft(&mut fleet.write().unwrap(), "DD1234", Duration::seconds(30), "4808.10N\\01134.00E");
+        update(&mut fleet.write().unwrap(), Duration::seconds(30), "4858.20N\\00821.00E");
+        let position = String::from_utf8(body.next().await.unwrap().unwrap().to_vec()).unwrap();
+        assert!(position.starts_with("event: position\n") && position.contains("DF153A"), "{}", position);
+
+        let request = Request::get("/api/stream?bbox=north").body(Body::empty()).unwrap();
+        assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+        // EventSource can't set headers, so the token comes in the query.
+        let app = router(fleet, Some("secret".to_owned()));
+        let request = Request::get("/api/stream?bbox=48.5,8.0,49.5,9.0&token=secret").body(Body::empty()).unwrap();
+        assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+        let request = Request::get("/api/stream?bbox=48.5,8.0,49.5,9.0").body(Body::empty()).unwrap();
+        assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn drops_slow_stream_clients() {
+        let fleet = Arc::new(RwLock::new(LiveFleet::new(FleetState::new(FleetConfig::default()))));
+        let request = Request::get("/api/stream?types=Glider").body(Body::empty()).unwrap();
+        let response = router(fleet.clone(), None).oneshot(request).await.unwrap();
+        let mut body = response.into_body().into_data_stream();
+        body.next().await.unwrap().unwrap();
+
+        for _ in 0..UPDATES_BUFFER + 10 {
+            update(&mut fleet.write().unwrap(), Duration::seconds(30), "4858.20N\\00821.00E");
+        }
+        let notice = String::from_utf8(body.next().await.unwrap().unwrap().to_vec()).unwrap();
+        assert!(notice.starts_with("event: dropped\ndata: client too slow, 10 positions skipped"), "{}", notice);
+        assert!(body.next().await.is_none());
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use flate2::read::MultiGzDecoder;
+
+// A line from a recorded APRS log, in the format of `ogn-aprs-parser/assets/APRS*.log`:
+// one APRS message per line, interleaved with `#` server comments. Logs written by the archive
+// sink also carry a `# received <time>` comment before every line.
+#[derive(Debug, PartialEq)]
+pub struct LogLine {
+    // the time of the most recent dated comment before this line, if any.
+    pub time: Option<DateTime<Utc>>,
+    pub line: String,
+}
+
+impl LogLine {
+    pub fn is_comment(&self) -> bool {
+        self.line.starts_with('#')
+    }
+}
+
+// read all lines of a log file, dating each by the comments preceding it.
+// `.gz` and `.zst` files are decompressed.
+pub fn read_log(path: &Path) -> io::Result<impl Iterator<Item = io::Result<LogLine>>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read + Send> = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Box::new(MultiGzDecoder::new(file)),
+        Some("zst") => Box::new(zstd::Decoder::new(file)?),
+        _ => Box::new(file),
+    };
+    Ok(date_lines(BufReader::new(reader).lines()))
+}
+
+pub fn date_lines(
+    lines: impl Iterator<Item = io::Result<String>>,
+) -> impl Iterator<Item = io::Result<LogLine>> {
+    let mut time = None;
+    lines.map(move |line| {
+        // older logs hold the escaped line endings of the raw TCP reads.
+        let line = line?.trim_end_matches("\\r\\n").to_owned();
+        if let Some(comment_time) = received_comment_time(&line).or_else(|| server_comment_time(&line)) {
+            time = Some(comment_time);
+        }
+        Ok(LogLine { time, line })
+    })
+}
+
+// `# received 2023-02-19T11:24:38.123456789Z`, see `archive`.
+pub fn received_comment_time(line: &str) -> Option<DateTime<Utc>> {
+    let time = line.strip_prefix("# received ")?;
+    DateTime::parse_from_rfc3339(time).ok().map(|time| time.to_utc())
+}
+
+// aprsc sends a keepalive comment with the current server time every 20 seconds, e.g.
+// `# aprsc 2.1.5-g8af3cdc 19 Feb 2023 11:18:57 GMT GLIDERN2 51.68.189.96:14580`
+pub fn server_comment_time(line: &str) -> Option<DateTime<Utc>> {
+    let rest = line.strip_prefix("# aprsc ")?;
+    let mut words = rest.split_whitespace().skip(1);
+    let date: Vec<&str> = words.by_ref().take(5).collect();
+    if date.len() != 5 || date[4] != "GMT" {
+        return None;
+    }
+    NaiveDateTime::parse_from_str(&date[..4].join(" "), "%d %b %Y %H:%M:%S")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn server_comment_time_is_parsed() {
+        assert_eq!(
+            server_comment_time(
+                "# aprsc 2.1.5-g8af3cdc 19 Feb 2023 11:18:57 GMT GLIDERN2 51.68.189.96:14580"
+            ),
+            Some(Utc.with_ymd_and_hms(2023, 2, 19, 11, 18, 57).unwrap())
+        );
+        assert_eq!(server_comment_time("# aprsc 2.1.5-g8af3cdc"), None);
+        assert_eq!(
+            server_comment_time("# logresp TESTAPP unverified, server GLIDERN2"),
+            None
+        );
+    }
+
+    #[test]
+    fn recorded_log_lines_are_dated() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../ogn-aprs-parser/assets/APRS2.log");
+        let lines: Vec<LogLine> = read_log(&path).unwrap().map(Result::unwrap).collect();
+
+        // the login banner comes before the first dated comment.
+        assert_eq!(lines[0].time, None);
+        assert!(lines[0].is_comment());
+        let first_dated = lines.iter().find(|l| !l.is_comment() && l.time.is_some()).unwrap();
+        assert_eq!(
+            first_dated.time,
+            Some(Utc.with_ymd_and_hms(2023, 2, 19, 11, 18, 57).unwrap())
+        );
+    }
+
+    #[test]
+    fn escaped_line_endings_are_removed() {
+        let lines = vec![Ok("FLRDF153A>APRS,qAS,RHST:/154006h gps2x2\\r\\n".to_owned())];
+        let dated: Vec<LogLine> = date_lines(lines.into_iter()).map(Result::unwrap).collect();
+        assert_eq!(dated[0].line, "FLRDF153A>APRS,qAS,RHST:/154006h gps2x2");
+    }
+}
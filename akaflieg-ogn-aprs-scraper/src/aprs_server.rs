@@ -0,0 +1,208 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use aprs_is_client::{ServerComment, ServerSession};
+
+use crate::record::Packet;
+
+// lines buffered for each client; a client that falls further behind misses lines.
+const CLIENT_BUFFER: usize = 4096;
+
+// A small APRS-IS server, so local tools can share the scraper's upstream connections instead of
+// each opening their own. Clients log in as with any APRS-IS server, e.g.
+//
+//   user N0CALL pass -1 vers xcsoar 7.0 filter r/48.97/8.35/50
+//
+// and get the lines of all sources that pass their filter, see `aprs_is_client::Filter`; `#filter`
+// changes it later on. Logins and filters are handled like the mock server's, see
+// `aprs_is_client::ServerSession`. The server is read-only, anything else clients send is ignored.
+//
+// Comments describing the upstream connections, i.e. their login responses and keepalives, aren't
+// passed on; clients get those of this server instead. Other server comments reach every client.
+pub struct AprsServerConfig {
+    pub addr: String,
+    // the name in login responses and keepalives, like `GLIDERN2` of the glidernet servers.
+    pub server_name: String,
+    pub keepalive_interval: Duration,
+}
+
+pub fn channel() -> broadcast::Sender<Arc<str>> {
+    broadcast::channel(CLIENT_BUFFER).0
+}
+
+// the sink: pass every line on to the connected clients.
+pub async fn write_aprs(lines: broadcast::Sender<Arc<str>>, mut rx: Receiver<Packet>) {
+    while let Some(packet) = rx.recv().await {
+        // positions from other feeds, e.g. ADS-B, have no APRS line to pass on.
+        if packet.line.is_empty() {
+            continue;
+        }
+        if packet.line.starts_with('#') && !matches!(ServerComment::from_line(&packet.line), ServerComment::Other(_)) {
+            continue;
+        }
+        // no clients connected is fine.
+        lines.send(packet.line.into()).ok();
+    }
+    info!("closed aprs server sink");
+}
+
+pub async fn serve(config: AprsServerConfig, lines: broadcast::Sender<Arc<str>>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let listener = TcpListener::bind(&config.addr).await?;
+    info!("serving aprs-is on {:?}", config.addr);
+    accept(listener, Arc::new(config), lines).await?;
+    Ok(())
+}
+
+async fn accept(listener: TcpListener, config: Arc<AprsServerConfig>, lines: broadcast::Sender<Arc<str>>) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let (config, lines) = (config.clone(), lines.clone());
+        tokio::spawn(
+            async move {
+                match handle_client(stream, config, lines).await {
+                    Ok(()) => info!("aprs-is client disconnected"),
+                    Err(err) => debug!("aprs-is client gone: {:?}", err),
+                }
+            }
+            .instrument(info_span!("aprs-is", client = %peer)),
+        );
+    }
+}
+
+async fn handle_client(stream: TcpStream, config: Arc<AprsServerConfig>, lines: broadcast::Sender<Arc<str>>) -> std::io::Result<()> {
+    let software = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let Some(mut session) = ServerSession::accept(stream, &software, &config.server_name).await? else {
+        return Ok(());
+    };
+
+    let mut lines = lines.subscribe();
+    let mut keepalive = interval_at(Instant::now() + config.keepalive_interval, config.keepalive_interval);
+    keepalive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            line = lines.recv() => match line {
+                Ok(line) if session.filter.borrow().matches(&line) => session.send(&line).await?,
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => warn!("client too slow, skipped {} lines", skipped),
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            reply = session.replies.recv() => match reply {
+                Some(reply) => session.send(&reply).await?,
+                // the client closed the connection.
+                None => return Ok(()),
+            },
+            _ = keepalive.tick() => {
+                let line = session.keepalive.line();
+                session.send(&line).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::SocketAddr;
+
+    use aprs_is_client::passcode::passcode;
+    use aprs_is_client::server::send;
+    use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::sync::mpsc;
+
+    use crate::source;
+
+    const FLARM: &str = "FLRDF153A>APRS,qAS,RHST:/154006h4858.10N\\00820.89E^301/050/A=001145 !W71! id22DF153A";
+    const ICAO: &str = "ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\\00803.85E^206/080/A=003503 !W75! id213E6DBA";
+
+    async fn start(lines: broadcast::Sender<Arc<str>>) -> SocketAddr {
+        let config = AprsServerConfig {
+            addr: String::new(),
+            server_name: "SCRAPER".to_owned(),
+            keepalive_interval: Duration::from_millis(200),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(accept(listener, Arc::new(config), lines));
+        addr
+    }
+
+    async fn login(addr: SocketAddr, login: &str) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+        let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(reader).lines();
+        assert!(reader.next_line().await.unwrap().unwrap().starts_with("# akaflieg-ogn-aprs-scraper "));
+        send(&mut writer, login).await.unwrap();
+        (reader, writer)
+    }
+
+    // as the aprs-is source reads it.
+    fn packet(line: &str) -> Packet {
+        source::packet(&"aprs-is".into(), line.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn clients_get_what_passes_their_filter() {
+        let lines = channel();
+        let addr = start(lines.clone()).await;
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(write_aprs(lines.clone(), rx));
+
+        let (mut reader, mut writer) = login(addr, &format!("user N0CALL pass {} vers test 1.0 filter p/FLR", passcode("N0CALL"))).await;
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), "# logresp N0CALL verified, server SCRAPER");
+        while lines.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        for line in [
+            "# logresp SCRAPER unverified, server GLIDERN2",
+            "# aprsc 2.1.5-g8af3cdc 19 Feb 2023 11:18:57 GMT GLIDERN2 51.68.189.96:14580",
+            ICAO,
+            "# server restarting soon",
+            FLARM,
+        ] {
+            tx.send(packet(line)).await.unwrap();
+        }
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), "# server restarting soon");
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), FLARM);
+
+        // the filter can be changed, and the server keeps the connection alive meanwhile.
+        send(&mut writer, "#filter p/ICA").await.unwrap();
+        let keepalive = reader.next_line().await.unwrap().unwrap();
+        assert!(matches!(ServerComment::from_line(&keepalive), ServerComment::Keepalive { server, .. } if server == "SCRAPER"));
+        tx.send(packet(FLARM)).await.unwrap();
+        tx.send(packet(ICAO)).await.unwrap();
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), ICAO);
+
+        // a filter we can't parse is refused, the last one stays.
+        send(&mut writer, "#filter x/1").await.unwrap();
+        assert!(reader.next_line().await.unwrap().unwrap().starts_with("# invalid filter: "));
+        tx.send(packet(FLARM)).await.unwrap();
+        tx.send(packet(ICAO)).await.unwrap();
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), ICAO);
+    }
+
+    #[tokio::test]
+    async fn invalid_logins_are_refused() {
+        let addr = start(channel()).await;
+        let (mut reader, _writer) = login(addr, "hello").await;
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), "# invalid login");
+        assert_eq!(reader.next_line().await.unwrap(), None);
+
+        // rather than the full feed.
+        let (mut reader, _writer) = login(addr, "user N0CALL pass -1 vers test 1.0 filter r/48.9").await;
+        assert!(reader.next_line().await.unwrap().unwrap().starts_with("# invalid filter: "));
+        assert_eq!(reader.next_line().await.unwrap(), None);
+
+        let (mut reader, _writer) = login(addr, "user N0CALL pass -1 vers test 1.0").await;
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), "# logresp N0CALL unverified, server SCRAPER");
+    }
+}
@@ -0,0 +1,295 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use flate2::write::GzEncoder;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::timeout;
+
+use tracing::{error, info, Span};
+
+use crate::metrics::{SINK_ERRORS, SINK_WRITE_SECONDS};
+use crate::record::Packet;
+use crate::util::format_for_display;
+
+// Every received line is archived as it was received, in the format of
+// `ogn-aprs-parser/assets/APRS.log`, one file per UTC day named `aprs-<yyyy-mm-dd>.log.<gz|zst>`.
+// Each line is preceded by a comment holding its receive time, e.g.
+//
+//   # received 2023-02-19T11:24:38.123456789Z
+//   ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\00803.85E^206/080/A=003503 ...
+//
+// Both gzip and zstd allow concatenating compressed streams, so a restart appends a new stream to
+// the current day's file, and a crash only loses what was written since the last flush.
+pub struct ArchiveConfig {
+    pub dir: PathBuf,
+    pub compression: Compression,
+    // delete files older than this many days, keep everything if unset.
+    pub retention_days: Option<u32>,
+    pub flush_interval: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gzip" | "gz" => Some(Compression::Gzip),
+            "zstd" | "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+pub async fn write_aprs(config: ArchiveConfig, rx: Receiver<Packet>) {
+    // file and compression I/O blocks, so the archive is written from a thread of its own.
+    let (runtime, span) = (Handle::current(), Span::current());
+    let archiving = tokio::task::spawn_blocking(move || span.in_scope(|| archive_blocking(config, rx, runtime)));
+    if let Err(err) = archiving.await {
+        error!("archive thread failed: {:?}", err);
+    }
+    info!("closed archive sink");
+}
+
+fn archive_blocking(config: ArchiveConfig, mut rx: Receiver<Packet>, runtime: Handle) {
+    let flush_interval = config.flush_interval;
+    let mut archive = Archive::new(config);
+    let mut last_flush = Instant::now();
+
+    // loop while channel is still alive, flushing the current file every `flush_interval`.
+    loop {
+        let wait = flush_interval.saturating_sub(last_flush.elapsed());
+        match runtime.block_on(timeout(wait, rx.recv())) {
+            // the archive is of the raw feed, positions from other feeds, e.g. ADS-B, aren't in it.
+            Ok(Some(packet)) if packet.line.is_empty() => {}
+            Ok(Some(packet)) => {
+                let _timer = SINK_WRITE_SECONDS.with_label_values(&["archive"]).start_timer();
+                if let Err(err) = archive.write(&packet) {
+                    error!("error archiving aprs message <{}>: {:?}", format_for_display(packet.line.as_bytes()), err);
+                    SINK_ERRORS.with_label_values(&["archive"]).inc();
+                }
+            }
+            Ok(None) => break,
+            Err(_) => {}
+        }
+        if last_flush.elapsed() >= flush_interval {
+            if let Err(err) = archive.flush() {
+                error!("error flushing archive: {:?}", err);
+                SINK_ERRORS.with_label_values(&["archive"]).inc();
+            }
+            last_flush = Instant::now();
+        }
+    }
+
+    if let Err(err) = archive.close() {
+        error!("error closing archive: {:?}", err);
+    }
+}
+
+pub struct Archive {
+    config: ArchiveConfig,
+    current: Option<(NaiveDate, Encoder)>,
+}
+
+impl Archive {
+    pub fn new(config: ArchiveConfig) -> Self {
+        Archive { config, current: None }
+    }
+
+    pub fn write(&mut self, packet: &Packet) -> io::Result<()> {
+        let day = packet.received.date_naive();
+        if self.current.as_ref().is_none_or(|(current, _)| *current != day) {
+            self.rotate(day)?;
+        }
+        let (_, encoder) = self.current.as_mut().expect("archive file was just opened");
+        writeln!(encoder, "{}", received_comment(packet.received))?;
+        writeln!(encoder, "{}", packet.line)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((_, encoder)) => encoder.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn close(&mut self) -> io::Result<()> {
+        match self.current.take() {
+            Some((_, encoder)) => encoder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // finish the current file and start the one for `day`, then apply the retention.
+    fn rotate(&mut self, day: NaiveDate) -> io::Result<()> {
+        self.close()?;
+        fs::create_dir_all(&self.config.dir)?;
+
+        let path = self.config.dir.join(archive_file_name(day, self.config.compression));
+        info!("archiving to {:?}", path);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        self.current = Some((day, Encoder::new(file, self.config.compression)?));
+
+        if let Some(days) = self.config.retention_days {
+            delete_expired(&self.config.dir, day - chrono::Duration::days(days.into()))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn archive_file_name(day: NaiveDate, compression: Compression) -> String {
+    format!("aprs-{}.log.{}", day.format("%Y-%m-%d"), compression.extension())
+}
+
+// the day an archive file holds, taken from its name.
+pub fn archive_file_day(path: &Path) -> Option<NaiveDate> {
+    let name = path.file_name()?.to_str()?.strip_prefix("aprs-")?;
+    NaiveDate::parse_from_str(name.get(..10)?, "%Y-%m-%d").ok()
+}
+
+pub fn received_comment(received: DateTime<Utc>) -> String {
+    format!("# received {}", received.to_rfc3339_opts(SecondsFormat::Nanos, true))
+}
+
+// delete all archive files from before `oldest`.
+fn delete_expired(dir: &Path, oldest: NaiveDate) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if archive_file_day(&path).is_some_and(|day| day < oldest) {
+            info!("deleting expired archive {:?}", path);
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+enum Encoder {
+    Gzip(GzEncoder<File>),
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl Encoder {
+    fn new(file: File, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(file, 0)?),
+        })
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish()?.sync_all(),
+            Encoder::Zstd(encoder) => encoder.finish()?.sync_all(),
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    use crate::aprs_log::{read_log, LogLine};
+
+    const MSG: &str = "ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\\00803.85E^206/080/A=003503 !W75! id213E6DBA";
+
+    fn packet(received: DateTime<Utc>) -> Packet {
+        Packet { received, source: "test".into(), line: MSG.to_owned(), position: None }
+    }
+
+    fn config(dir: &Path, compression: Compression) -> ArchiveConfig {
+        ArchiveConfig {
+            dir: dir.to_owned(),
+            compression,
+            retention_days: Some(2),
+            flush_interval: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn archive_is_readable_across_restarts() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let dir = tempfile::tempdir().unwrap();
+            let received = Utc.with_ymd_and_hms(2023, 2, 19, 11, 24, 38).unwrap();
+
+            // the second run appends to the file of the first.
+            for offset in 0..2 {
+                let mut archive = Archive::new(config(dir.path(), compression));
+                archive.write(&packet(received + chrono::Duration::seconds(offset))).unwrap();
+                archive.close().unwrap();
+            }
+
+            let path = dir.path().join(archive_file_name(received.date_naive(), compression));
+            let lines: Vec<LogLine> = read_log(&path).unwrap().map(Result::unwrap).collect();
+            assert_eq!(lines.len(), 4);
+            assert_eq!(lines[0].line, "# received 2023-02-19T11:24:38.000000000Z");
+            assert_eq!(lines[1], LogLine { time: Some(received), line: MSG.to_owned() });
+            assert_eq!(lines[3].time, Some(received + chrono::Duration::seconds(1)));
+        }
+    }
+
+    #[tokio::test]
+    async fn sink_archives_lines_as_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        let sink = tokio::spawn(write_aprs(config(dir.path(), Compression::Gzip), rx));
+        let packet = crate::source::packet(&"test".into(), MSG.as_bytes());
+        tx.send(packet.clone()).await.unwrap();
+        drop(tx);
+        sink.await.unwrap();
+
+        let path = dir.path().join(archive_file_name(packet.received.date_naive(), Compression::Gzip));
+        let lines: Vec<LogLine> = read_log(&path).unwrap().map(Result::unwrap).collect();
+        assert_eq!(lines[1], LogLine { time: Some(packet.received), line: MSG.to_owned() });
+    }
+
+    #[test]
+    fn files_rotate_daily_and_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = Archive::new(config(dir.path(), Compression::Zstd));
+        for day in 1..=4 {
+            archive
+                .write(&packet(Utc.with_ymd_and_hms(2023, 2, day, 12, 0, 0).unwrap()))
+                .unwrap();
+        }
+        archive.close().unwrap();
+
+        let mut names: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["aprs-2023-02-02.log.zst", "aprs-2023-02-03.log.zst", "aprs-2023-02-04.log.zst"]);
+    }
+}
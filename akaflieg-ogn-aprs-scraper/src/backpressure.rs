@@ -0,0 +1,424 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::{DateTime, SecondsFormat};
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::sync::Notify;
+
+use tracing::{error, warn};
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+
+use crate::metrics::{SINK_QUEUE_OVERFLOWS, SINK_SPILLED_PACKETS};
+use crate::record::Packet;
+use crate::spool::Spool;
+
+// spilled packets are written to the spool in segments of this many.
+const SPILL_BATCH: usize = 256;
+
+// What to do with a packet for a sink whose queue is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    // wait for the sink, holding up the fan out and with it every source and sink.
+    Block,
+    // make room by dropping the oldest queued packet.
+    DropOldest,
+    // drop the packet that doesn't fit.
+    DropNew,
+    // queue further packets on disk, until the sink caught up.
+    Spill,
+}
+
+impl Policy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "block" => Some(Policy::Block),
+            "drop-oldest" => Some(Policy::DropOldest),
+            "drop-new" => Some(Policy::DropNew),
+            "spill" => Some(Policy::Spill),
+            _ => None,
+        }
+    }
+}
+
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub policy: Policy,
+    // every sink spills to its own subdirectory.
+    pub spill_dir: PathBuf,
+    pub spill_max_bytes: u64,
+}
+
+// The queue in front of a sink: the fan out pushes into it without waiting for the sink (unless
+// the policy is `Block`), and a task forwards the packets into the sink's channel.
+pub fn sink_channel(name: &'static str, config: &QueueConfig) -> (QueueSender, Receiver<Packet>) {
+    let (queue_tx, mut queue_rx) = queue(name, config);
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        while let Some(packet) = queue_rx.recv().await {
+            if tx.send(packet).await.is_err() {
+                break;
+            }
+        }
+    });
+    (queue_tx, rx)
+}
+
+pub fn queue(name: &'static str, config: &QueueConfig) -> (QueueSender, QueueReceiver) {
+    let spill = match config.policy {
+        Policy::Spill => match Spool::open(config.spill_dir.join(name), config.spill_max_bytes) {
+            Ok(spool) => Some(Spill { spool, pending: Vec::new(), unspooled: VecDeque::new() }),
+            Err(err) => {
+                error!(
+                    "error opening spill directory for {} at {:?}, dropping the oldest packets instead: {:?}",
+                    name, config.spill_dir, err
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+    let state = State { queue: VecDeque::with_capacity(config.capacity), spill, closed: false, receiver_gone: false };
+    let shared = Arc::new(Shared {
+        name,
+        capacity: config.capacity,
+        policy: config.policy,
+        state: Mutex::new(state),
+        packets: Notify::new(),
+        space: Notify::new(),
+    });
+    if let Some(spill) = &shared.lock().spill {
+        SINK_SPILLED_PACKETS.with_label_values(&[name]).set(spill.len() as i64);
+    }
+    (QueueSender { shared: shared.clone() }, QueueReceiver { shared })
+}
+
+struct Shared {
+    name: &'static str,
+    capacity: usize,
+    policy: Policy,
+    state: Mutex<State>,
+    // signalled when a packet was queued or the sender is gone.
+    packets: Notify,
+    // signalled when a packet was taken or the receiver is gone.
+    space: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn overflow(&self, action: &str, packets: u64) {
+        SINK_QUEUE_OVERFLOWS.with_label_values(&[self.name, action]).inc_by(packets);
+    }
+}
+
+struct State {
+    queue: VecDeque<Packet>,
+    spill: Option<Spill>,
+    closed: bool,
+    receiver_gone: bool,
+}
+
+// Packets spilled to disk. They're all newer than the ones in the queue: in order, those read
+// back from the spool, the spool itself, and those not yet written to it.
+struct Spill {
+    spool: Spool,
+    pending: Vec<Packet>,
+    unspooled: VecDeque<Packet>,
+}
+
+impl Spill {
+    fn len(&self) -> usize {
+        self.unspooled.len() + self.spool.records() as usize + self.pending.len()
+    }
+
+    // returns the number of packets the spool dropped to stay within its size.
+    fn push(&mut self, packet: Packet) -> u64 {
+        self.pending.push(packet);
+        if self.pending.len() < SPILL_BATCH {
+            return 0;
+        }
+        let data: String = self.pending.iter().map(encode).collect();
+        let records = self.pending.len() as u64;
+        match self.spool.push(data.as_bytes(), records) {
+            Ok(dropped) => {
+                self.pending.clear();
+                dropped
+            }
+            Err(err) => {
+                error!("error spilling packets, dropping them: {:?}", err);
+                self.pending.clear();
+                records
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<Packet> {
+        while self.unspooled.is_empty() {
+            match self.spool.front() {
+                Some(Ok(data)) => self.unspooled.extend(String::from_utf8_lossy(&data).lines().filter_map(decode)),
+                Some(Err(err)) => error!("error reading spilled packets, dropping them: {:?}", err),
+                None => break,
+            }
+            if let Err(err) = self.spool.pop_front() {
+                error!("error removing spilled packets: {:?}", err);
+                break;
+            }
+        }
+        self.unspooled.pop_front().or_else(|| {
+            // only packets not yet written are left, they're the newest.
+            (!self.pending.is_empty()).then(|| self.pending.remove(0))
+        })
+    }
+}
+
+// one spilled packet per line: receive time, source and the line itself, separated by tabs. A
+// position without a line follows the empty line as json, lines are parsed again when read back.
+fn encode(packet: &Packet) -> String {
+    let line = match &packet.position {
+        Some(position) if packet.line.is_empty() => format!("\t{}", serde_json::to_string(position).unwrap_or_default()),
+        _ => packet.line.clone(),
+    };
+    format!(
+        "{}\t{}\t{}\n",
+        packet.received.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        packet.source,
+        line
+    )
+}
+
+fn decode(line: &str) -> Option<Packet> {
+    let mut parts = line.splitn(3, '\t');
+    let received = DateTime::parse_from_rfc3339(parts.next()?).ok()?.to_utc();
+    let source = parts.next()?.into();
+    let line = parts.next()?;
+    match line.strip_prefix('\t') {
+        Some(position) => Some(Packet { received, source, line: String::new(), position: serde_json::from_str(position).ok()? }),
+        None => Some(Packet {
+            received,
+            source,
+            line: line.to_owned(),
+            position: OGNStatusMessage::from_str(line, Some(received)).ok(),
+        }),
+    }
+}
+
+pub struct QueueSender {
+    shared: Arc<Shared>,
+}
+
+impl QueueSender {
+    pub fn name(&self) -> &'static str {
+        self.shared.name
+    }
+
+    // queue a packet according to the policy; fails only once the receiver is gone.
+    pub async fn send(&self, mut packet: Packet) -> Result<(), Packet> {
+        let shared = &self.shared;
+        loop {
+            {
+                let mut state = shared.lock();
+                if state.receiver_gone {
+                    return Err(packet);
+                }
+                match state.push(shared, packet) {
+                    None => {
+                        shared.packets.notify_one();
+                        return Ok(());
+                    }
+                    Some(blocked) => packet = blocked,
+                }
+            }
+            shared.overflow("blocked", 1);
+            shared.space.notified().await;
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.lock().receiver_gone
+    }
+
+    // packets waiting for the sink, in memory and spilled.
+    pub fn len(&self) -> usize {
+        let state = self.shared.lock();
+        state.queue.len() + state.spill.as_ref().map_or(0, Spill::len)
+    }
+}
+
+impl State {
+    // returns the packet if it has to wait for space.
+    fn push(&mut self, shared: &Shared, packet: Packet) -> Option<Packet> {
+        // once spilling, everything goes to disk until the sink caught up, to keep the order.
+        let spilling = self.spill.as_ref().is_some_and(|spill| spill.len() > 0);
+        if !spilling && self.queue.len() < shared.capacity {
+            self.queue.push_back(packet);
+            return None;
+        }
+
+        match (shared.policy, &mut self.spill) {
+            (Policy::Block, _) => return Some(packet),
+            (Policy::DropNew, _) => shared.overflow("dropped_new", 1),
+            (Policy::Spill, Some(spill)) => {
+                shared.overflow("spilled", 1);
+                let dropped = spill.push(packet);
+                if dropped > 0 {
+                    warn!("spill for {} is full, dropped {} packets", shared.name, dropped);
+                    shared.overflow("dropped_spilled", dropped);
+                }
+                SINK_SPILLED_PACKETS.with_label_values(&[shared.name]).set(spill.len() as i64);
+            }
+            // without a usable spill directory, spilling falls back to dropping the oldest.
+            (Policy::DropOldest, _) | (Policy::Spill, None) => {
+                self.queue.pop_front();
+                self.queue.push_back(packet);
+                shared.overflow("dropped_oldest", 1);
+            }
+        }
+        None
+    }
+
+    fn pop(&mut self, shared: &Shared) -> Option<Packet> {
+        if let Some(packet) = self.queue.pop_front() {
+            return Some(packet);
+        }
+        let spill = self.spill.as_mut()?;
+        let packet = spill.pop();
+        SINK_SPILLED_PACKETS.with_label_values(&[shared.name]).set(spill.len() as i64);
+        packet
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.packets.notify_one();
+    }
+}
+
+pub struct QueueReceiver {
+    shared: Arc<Shared>,
+}
+
+impl QueueReceiver {
+    // the next packet, or None once the sender is gone and everything was taken.
+    pub async fn recv(&mut self) -> Option<Packet> {
+        let shared = &self.shared;
+        loop {
+            {
+                let mut state = shared.lock();
+                if let Some(packet) = state.pop(shared) {
+                    shared.space.notify_one();
+                    return Some(packet);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            shared.packets.notified().await;
+        }
+    }
+}
+
+impl Drop for QueueReceiver {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_gone = true;
+        self.shared.space.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use tokio::time::timeout;
+
+    fn packet(i: usize) -> Packet {
+        Packet { received: Utc::now(), source: "test".into(), line: format!("line {}", i), position: None }
+    }
+
+    fn config(policy: Policy, spill_dir: PathBuf) -> QueueConfig {
+        QueueConfig { capacity: 4, policy, spill_dir, spill_max_bytes: 1 << 20 }
+    }
+
+    async fn send_all(tx: &QueueSender, count: usize) {
+        for i in 0..count {
+            tx.send(packet(i)).await.unwrap();
+        }
+    }
+
+    async fn received(mut rx: QueueReceiver) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Some(packet) = rx.recv().await {
+            lines.push(packet.line);
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn drop_policies_keep_the_queue_bounded() {
+        let (tx, rx) = queue("drop-oldest", &config(Policy::DropOldest, PathBuf::new()));
+        send_all(&tx, 6).await;
+        drop(tx);
+        assert_eq!(received(rx).await, ["line 2", "line 3", "line 4", "line 5"]);
+
+        let (tx, rx) = queue("drop-new", &config(Policy::DropNew, PathBuf::new()));
+        send_all(&tx, 6).await;
+        drop(tx);
+        assert_eq!(received(rx).await, ["line 0", "line 1", "line 2", "line 3"]);
+        assert_eq!(SINK_QUEUE_OVERFLOWS.with_label_values(&["drop-new", "dropped_new"]).get(), 2);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_the_receiver() {
+        let (tx, mut rx) = queue("block", &config(Policy::Block, PathBuf::new()));
+        send_all(&tx, 4).await;
+        assert!(timeout(Duration::from_millis(50), tx.send(packet(4))).await.is_err());
+
+        assert_eq!(rx.recv().await.unwrap().line, "line 0");
+        tx.send(packet(4)).await.unwrap();
+        drop(tx);
+        assert_eq!(received(rx).await, ["line 1", "line 2", "line 3", "line 4"]);
+    }
+
+    #[tokio::test]
+    async fn spilled_packets_are_delivered_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, rx) = queue("spill", &config(Policy::Spill, dir.path().into()));
+        let count = 4 + 2 * SPILL_BATCH + 10;
+        send_all(&tx, count).await;
+        assert_eq!(tx.len(), count);
+        assert_eq!(std::fs::read_dir(dir.path().join("spill")).unwrap().count(), 2);
+        drop(tx);
+
+        let expected: Vec<String> = (0..count).map(|i| format!("line {}", i)).collect();
+        assert_eq!(received(rx).await, expected);
+        assert_eq!(std::fs::read_dir(dir.path().join("spill")).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn sender_fails_once_the_receiver_is_gone() {
+        let (tx, rx) = queue("gone", &config(Policy::Block, PathBuf::new()));
+        drop(rx);
+        assert!(tx.is_closed());
+        assert!(tx.send(packet(0)).await.is_err());
+    }
+
+    #[test]
+    fn spilled_packets_round_trip() {
+        let mut packet = packet(0);
+        packet.line = "with\ttab".to_owned();
+        assert_eq!(decode(encode(&packet).trim_end()), Some(packet.clone()));
+
+        let line = "ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\\00803.85E^206/080/A=003503 !W75! id213E6DBA";
+        packet.position = OGNStatusMessage::from_str(line, Some(packet.received)).ok();
+        packet.line = String::new();
+        assert_eq!(decode(encode(&packet).trim_end()), Some(packet));
+    }
+}
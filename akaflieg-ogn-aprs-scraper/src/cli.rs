@@ -0,0 +1,49 @@
+use std::error::Error;
+
+use chrono::{DateTime, Utc};
+
+// the value following `--name` on the command line, if given.
+pub fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+pub fn arg_flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+// an RFC 3339 timestamp following `--name`, e.g. `--from 2023-02-19T00:00:00Z`.
+pub fn arg_time(args: &[String], name: &str) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+    match arg_value(args, name) {
+        Some(value) => Ok(Some(
+            DateTime::parse_from_rfc3339(value)
+                .map_err(|err| format!("invalid {} <{}>: {}", name, value, err))?
+                .with_timezone(&Utc),
+        )),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn arguments_are_found_by_name() {
+        let args: Vec<String> = ["--delete-old", "--from", "2023-02-19T11:00:00Z"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(arg_flag(&args, "--delete-old"));
+        assert_eq!(arg_value(&args, "--to"), None);
+        assert_eq!(
+            arg_time(&args, "--from").unwrap(),
+            Some(Utc.with_ymd_and_hms(2023, 2, 19, 11, 0, 0).unwrap())
+        );
+        assert!(arg_time(&["--from".to_owned(), "yesterday".to_owned()], "--from").is_err());
+    }
+}
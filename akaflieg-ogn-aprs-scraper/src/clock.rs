@@ -0,0 +1,46 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::influx_schema::nanos;
+
+// The current time as the pipeline should see it. Live, this is the system clock; while replaying
+// a recorded log it's the receive time of the last replayed line, so anything downstream that
+// compares against "now" (e.g. to expire stale aircraft) behaves as it did when recording.
+//
+// Clones share their time, so the replay source advances the clock the sinks read.
+#[derive(Debug, Clone, Default)]
+pub struct Clock {
+    // nanoseconds since the epoch of the replayed timeline, 0 while running live.
+    virtual_now: Arc<AtomicI64>,
+}
+
+impl Clock {
+    pub fn now(&self) -> DateTime<Utc> {
+        match self.virtual_now.load(Ordering::SeqCst) {
+            0 => Utc::now(),
+            virtual_now => Utc.timestamp_nanos(virtual_now),
+        }
+    }
+
+    // advance the virtual clock, used by the replay source.
+    pub fn set_virtual(&self, time: DateTime<Utc>) {
+        self.virtual_now.store(nanos(time), Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtual_time_is_shared_by_clones() {
+        let recorded = Utc.with_ymd_and_hms(2023, 2, 19, 11, 18, 57).unwrap();
+        let clock = Clock::default();
+        let replayed = clock.clone();
+        replayed.set_virtual(recorded);
+        assert_eq!(clock.now(), recorded);
+        assert!(Clock::default().now() > recorded + chrono::Duration::days(365));
+    }
+}
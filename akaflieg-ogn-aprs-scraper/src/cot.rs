@@ -0,0 +1,210 @@
+use std::error::Error;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{interval, sleep, MissedTickBehavior};
+
+use tracing::{debug, info, warn};
+
+use ogn_aprs_parser::ogn::ogn_aircraft_types::AircraftType;
+use ogn_fleet_state::{Aircraft, FleetState};
+
+use crate::ddb::{Registry, SharedRegistry};
+use crate::live::SharedFleet;
+
+// Every tracked aircraft as a Cursor-on-Target event, for ATAK and other TAK clients, e.g.
+//
+//   <event version="2.0" uid="OGN-DF153A" type="a-n-A-C-F" how="m-g" time=".." start=".." stale="..">
+//     <point lat="48.968334" lon="8.348166" hae="349.0" ce="10.0" le="10.0"/>
+//     <detail><contact callsign="D-1234"/><track course="301.0" speed="25.7"/><remarks>..</remarks></detail>
+//   </event>
+//
+// An event goes stale when the fleet would consider the aircraft stale, i.e. `stale_after` after it
+// was last heard. Aircraft whose owners opted out of tracking in the ogn ddb never reach the fleet,
+// see `LiveFleet::update`; those that don't want to be identified are named by their address.
+pub struct CotConfig {
+    pub target: CotTarget,
+    pub interval: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CotTarget {
+    // one event per datagram, e.g. to ATAK's default multicast group 239.2.3.1:6969.
+    Udp(String),
+    // a stream of events, e.g. to a TAK server on port 8087.
+    Tcp(String),
+}
+
+impl CotTarget {
+    // `udp://<host>:<port>` or `tcp://<host>:<port>`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        match spec.split_once("://")? {
+            ("udp", addr) => Some(CotTarget::Udp(addr.to_owned())),
+            ("tcp", addr) => Some(CotTarget::Tcp(addr.to_owned())),
+            _ => None,
+        }
+    }
+}
+
+// the 2525 type of an aircraft, all of neutral affiliation and civil.
+pub fn cot_type(aircraft_type: &AircraftType) -> &'static str {
+    use AircraftType::*;
+    match aircraft_type {
+        Glider | TowPlane | DropPlane | PoweredAircraft | JetAircraft | HangGlider | ParaGlider => "a-n-A-C-F",
+        Helicopter => "a-n-A-C-H",
+        Balloon | Airship => "a-n-A-C-L",
+        GroundSupport => "a-n-G-E-V",
+        StaticObject => "a-n-G",
+        Parachute | UAV | UFO | Other | Unknown => "a-n-A",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+pub fn event(aircraft: &Aircraft, registry: &Registry, now: DateTime<Utc>, stale_after: chrono::Duration) -> String {
+    let last = &aircraft.last;
+    let callsign = registry.callsign(&aircraft.address).unwrap_or(&aircraft.address);
+    let model = registry
+        .get(&aircraft.address)
+        .filter(|device| device.is_identified() && !device.aircraft_model.is_empty())
+        .map(|device| format!("{}, ", device.aircraft_model))
+        .unwrap_or_default();
+    let remarks = format!("{}{:?} {}, via {}", model, last.aircraft_type, last.aprs_callsign, last.aprs_path);
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+            r#"<event version="2.0" uid="OGN-{}" type="{}" how="m-g" time="{}" start="{}" stale="{}">"#,
+            r#"<point lat="{:.6}" lon="{:.6}" hae="{:.1}" ce="10.0" le="10.0"/>"#,
+            r#"<detail><contact callsign="{}"/><track course="{:.1}" speed="{:.1}"/><remarks>{}</remarks></detail>"#,
+            r#"</event>"#,
+        ),
+        escape(&aircraft.address),
+        cot_type(&last.aircraft_type),
+        time(now),
+        time(now),
+        time(aircraft.last_heard + stale_after),
+        aircraft.latitude(),
+        aircraft.longitude(),
+        last.position.altitude,
+        escape(callsign),
+        last.position.heading,
+        last.velocity.horizontal,
+        escape(&remarks),
+    )
+}
+
+// the events of one update, for aircraft that aren't stale.
+pub fn events(fleet: &FleetState, registry: &Registry, now: DateTime<Utc>) -> Vec<String> {
+    let stale_after = fleet.config().stale_after;
+    let mut aircraft: Vec<_> = fleet
+        .aircraft()
+        .filter(|aircraft| !fleet.is_stale(aircraft, now))
+        .collect();
+    aircraft.sort_by(|a, b| a.address.cmp(&b.address));
+    aircraft.into_iter().map(|aircraft| event(aircraft, registry, now, stale_after)).collect()
+}
+
+fn current_events(fleet: &SharedFleet, registry: &SharedRegistry) -> Vec<String> {
+    let live = fleet.read().unwrap();
+    events(&live.fleet, &registry.read().unwrap(), live.clock.now())
+}
+
+pub async fn send(config: CotConfig, fleet: SharedFleet, registry: SharedRegistry) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut tick = interval(config.interval);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    match &config.target {
+        CotTarget::Udp(addr) => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+            socket.set_multicast_ttl_v4(8)?;
+            info!("sending cot events to udp {}", addr);
+            loop {
+                tick.tick().await;
+                for event in current_events(&fleet, &registry) {
+                    if let Err(err) = socket.send_to(event.as_bytes(), addr).await {
+                        debug!("error sending cot event: {:?}", err);
+                    }
+                }
+            }
+        }
+        CotTarget::Tcp(addr) => loop {
+            // reconnect for as long as the scraper runs, TAK servers restart too.
+            let mut stream = match TcpStream::connect(addr).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("error connecting to tak server {}: {:?}", addr, err);
+                    sleep(Duration::from_secs(10)).await;
+                    continue;
+                }
+            };
+            info!("sending cot events to tcp {}", addr);
+            loop {
+                tick.tick().await;
+                let events = current_events(&fleet, &registry).concat();
+                if let Err(err) = stream.write_all(events.as_bytes()).await {
+                    warn!("lost connection to tak server {}: {:?}", addr, err);
+                    break;
+                }
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+    use ogn_fleet_state::FleetConfig;
+
+    #[test]
+    fn events_carry_type_callsign_and_stale_time() {
+        let received = Utc.with_ymd_and_hms(2023, 2, 19, 15, 40, 6).unwrap();
+        let mut fleet = FleetState::new(FleetConfig::default());
+        for line in [
+            "FLRDF153A>APRS,qAS,RHST:/154006h4858.10N\\00820.89E'301/050/A=001145 !W00! id06DF153A",
+            "ICA3E6DBA>APRS,qAS,RHST:/154006h4858.54N\\00820.00EX180/097/A=001640 !W00! id0D3E6DBA",
+            "FLRDD9999>APRS,qAS,RHST:/154006h4858.54N\\00820.00E'180/097/A=001640 !W00! id06DD9999",
+        ] {
+            fleet.update(OGNStatusMessage::from_str(line, Some(received)).unwrap(), received);
+        }
+        let registry = Registry::from_json(
+            br#"{"devices":[
+                {"device_id":"DF153A","aircraft_model":"ASK-21","registration":"D-1234 <&>","cn":"KA","tracked":"Y","identified":"Y"},
+                {"device_id":"DD9999","registration":"D-9999","tracked":"Y","identified":"N"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let events = events(&fleet, &registry, received + chrono::Duration::seconds(60));
+        assert_eq!(events.len(), 3);
+        let helicopter = &events[0];
+        assert!(helicopter.contains(r#"uid="OGN-3E6DBA" type="a-n-A-C-H""#), "{}", helicopter);
+        assert!(helicopter.contains(r#"<contact callsign="3E6DBA"/>"#));
+
+        assert!(events[1].contains(r#"<contact callsign="DD9999"/>"#), "{}", events[1]);
+
+        let glider = &events[2];
+        assert!(glider.starts_with(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><event version="2.0" uid="OGN-DF153A" type="a-n-A-C-F" how="m-g""#));
+        assert!(glider.contains(r#"time="2023-02-19T15:41:06.000Z" start="2023-02-19T15:41:06.000Z" stale="2023-02-19T15:45:06.000Z""#), "{}", glider);
+        assert!(glider.contains(r#"<point lat="48.968334" lon="8.348166" hae="349.0" ce="10.0" le="10.0"/>"#), "{}", glider);
+        assert!(glider.contains(r#"<contact callsign="D-1234 &lt;&amp;&gt;"/><track course="301.0" speed="25.7"/>"#), "{}", glider);
+        assert!(glider.contains("<remarks>ASK-21, Glider FLRDF153A, via APRS,qAS,RHST</remarks>"), "{}", glider);
+        assert_eq!(CotTarget::parse("udp://239.2.3.1:6969"), Some(CotTarget::Udp("239.2.3.1:6969".to_owned())));
+        assert_eq!(CotTarget::parse("239.2.3.1:6969"), None);
+    }
+}
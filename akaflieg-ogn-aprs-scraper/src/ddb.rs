@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::time::{interval, MissedTickBehavior};
+
+use tracing::{info, warn};
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+
+// The OGN device database, which maps device ids to registrations, competition numbers and models,
+// and holds the owners' wishes on whether they may be tracked and identified at all.
+pub const DEFAULT_URL: &str = "http://ddb.glidernet.org/download/?j=1";
+
+pub type SharedRegistry = Arc<RwLock<Registry>>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Device {
+    pub device_id: String,
+    #[serde(default)]
+    pub aircraft_model: String,
+    #[serde(default)]
+    pub registration: String,
+    #[serde(default)]
+    pub cn: String,
+    // "Y" or "N".
+    #[serde(default)]
+    pub tracked: String,
+    #[serde(default)]
+    pub identified: String,
+}
+
+impl Device {
+    // owners may opt out of being tracked, and of being identified while tracked.
+    pub fn is_tracked(&self) -> bool {
+        self.tracked != "N"
+    }
+
+    pub fn is_identified(&self) -> bool {
+        self.identified != "N"
+    }
+}
+
+#[derive(Deserialize)]
+struct Download {
+    devices: Vec<Device>,
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    devices: HashMap<String, Device>,
+}
+
+impl Registry {
+    // the json download, see `DEFAULT_URL`.
+    pub fn from_json(json: &[u8]) -> serde_json::Result<Self> {
+        let download: Download = serde_json::from_slice(json)?;
+        let devices = download.devices.into_iter().map(|device| (device.device_id.to_uppercase(), device)).collect();
+        Ok(Registry { devices })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Device> {
+        self.devices.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    // whether an aircraft may be shown, unknown devices are.
+    pub fn is_tracked(&self, id: &str) -> bool {
+        self.get(id).is_none_or(Device::is_tracked)
+    }
+
+    // whether a position may be shown: its owner didn't opt out here, and its device doesn't ask not
+    // to be tracked with the no-tracking flag either.
+    pub fn may_track(&self, msg: &OGNStatusMessage) -> bool {
+        let no_tracking = msg.ogn_flags.as_ref().is_some_and(|flags| flags.no_tracking_mode);
+        !no_tracking && self.is_tracked(msg.aircraft_id.as_deref().unwrap_or(&msg.aprs_callsign))
+    }
+
+    // the name to show for an aircraft: its registration, else its competition number, unless the
+    // owner doesn't want to be identified.
+    pub fn callsign(&self, id: &str) -> Option<&str> {
+        let device = self.get(id).filter(|device| device.is_identified())?;
+        [&device.registration, &device.cn].into_iter().find(|name| !name.is_empty()).map(String::as_str)
+    }
+}
+
+async fn fetch(url: &str) -> Result<Registry, Box<dyn Error + Send + Sync>> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+    Ok(Registry::from_json(&response.bytes().await?)?)
+}
+
+// download the database every `every`; until the first download succeeds the registry is empty,
+// and a failed download keeps the last one.
+pub async fn refresh(url: String, registry: SharedRegistry, every: Duration) {
+    let mut tick = interval(every);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tick.tick().await;
+        match fetch(&url).await {
+            Ok(fetched) => {
+                info!("loaded {} devices from the ogn ddb", fetched.len());
+                *registry.write().unwrap() = fetched;
+            }
+            Err(err) => warn!("error loading the ogn ddb from {}: {:?}", url, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn respects_the_owners_wishes() {
+        let json = br#"{"devices":[
+            {"device_type":"F","device_id":"DF153A","aircraft_model":"ASK-21","registration":"D-1234","cn":"KA","tracked":"Y","identified":"Y","aircraft_type":"1"},
+            {"device_type":"F","device_id":"dd1234","aircraft_model":"LS-4","registration":"","cn":"X2","tracked":"Y","identified":"Y","aircraft_type":"1"},
+            {"device_type":"F","device_id":"DD5678","aircraft_model":"Discus","registration":"D-5678","cn":"","tracked":"Y","identified":"N","aircraft_type":"1"},
+            {"device_type":"F","device_id":"DD9999","aircraft_model":"","registration":"D-9999","cn":"","tracked":"N","identified":"N","aircraft_type":"1"}
+        ]}"#;
+        let registry = Registry::from_json(json).unwrap();
+        assert_eq!(registry.len(), 4);
+        assert_eq!(registry.callsign("DF153A"), Some("D-1234"));
+        assert_eq!(registry.callsign("DD1234"), Some("X2"));
+        assert_eq!(registry.callsign("DD5678"), None);
+        assert_eq!(registry.callsign("3E6DBA"), None);
+        assert!(registry.is_tracked("DD5678") && registry.is_tracked("3E6DBA"));
+        assert!(!registry.is_tracked("DD9999"));
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::metrics::{UPSTREAM_LAG, UPSTREAM_MISSED};
+use crate::record::Packet;
+
+// Removes the duplicates that redundant upstreams deliver. A line is a duplicate if a line with the
+// same content was received from any source within the window before it. Server comments are
+// never duplicates, each server sends its own.
+//
+// While a line is in the window, we remember which sources delivered it and when. That gives the
+// per source lag (how long after the first delivery a source delivered a line) and, once the line
+// leaves the window, loss (which of the redundant upstreams never delivered it). Other sources,
+// e.g. a local receiver or a file, only deliver part of the feed, so they can't miss lines.
+pub struct Dedup {
+    window: Duration,
+    // the upstreams come first, then the other sources in order of their first line.
+    sources: Vec<Arc<str>>,
+    upstreams: usize,
+    seen: HashMap<u64, Seen>,
+    // line hashes in order of first receipt, to expire them from `seen`.
+    order: VecDeque<(DateTime<Utc>, u64)>,
+}
+
+struct Seen {
+    first: DateTime<Utc>,
+    // bit i is set if `sources[i]` delivered the line.
+    delivered_by: u64,
+}
+
+impl Dedup {
+    pub fn new(window: Duration, upstreams: Vec<Arc<str>>) -> Self {
+        Dedup {
+            window,
+            upstreams: upstreams.len(),
+            sources: upstreams,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    // returns true if the packet is the first with its content, and should be forwarded.
+    pub fn is_first(&mut self, packet: &Packet) -> bool {
+        self.expire(packet.received - self.window);
+        // positions from other feeds, e.g. ADS-B, have no line to compare.
+        if packet.line.starts_with('#') || packet.line.is_empty() {
+            return true;
+        }
+
+        let source = self.source_bit(&packet.source);
+        let key = hash(&packet.line);
+        match self.seen.get_mut(&key) {
+            Some(seen) => {
+                // a source repeating a line isn't lagging, only count its first delivery.
+                if seen.delivered_by & source == 0 {
+                    seen.delivered_by |= source;
+                    let lag = (packet.received - seen.first).to_std().unwrap_or_default();
+                    UPSTREAM_LAG.with_label_values(&[&packet.source]).observe(lag.as_secs_f64());
+                }
+                false
+            }
+            None => {
+                self.seen.insert(key, Seen { first: packet.received, delivered_by: source });
+                self.order.push_back((packet.received, key));
+                UPSTREAM_LAG.with_label_values(&[&packet.source]).observe(0.0);
+                true
+            }
+        }
+    }
+
+    // forget lines first received before `oldest`, and count them as missed by the sources
+    // that didn't deliver them.
+    fn expire(&mut self, oldest: DateTime<Utc>) {
+        while let Some(&(first, key)) = self.order.front() {
+            if first >= oldest {
+                break;
+            }
+            self.order.pop_front();
+            if let Some(seen) = self.seen.remove(&key) {
+                self.count_missed(seen.delivered_by);
+            }
+        }
+    }
+
+    fn count_missed(&self, delivered_by: u64) {
+        // with a single upstream there's nothing to compare against, and a line none of them
+        // delivered didn't come from the feed they share.
+        let upstreams = (1 << self.upstreams.min(63)) - 1;
+        if self.upstreams < 2 || delivered_by & upstreams == 0 {
+            return;
+        }
+        for (i, source) in self.sources[..self.upstreams].iter().enumerate() {
+            if delivered_by & (1 << i) == 0 {
+                UPSTREAM_MISSED.with_label_values(&[source]).inc();
+            }
+        }
+    }
+
+    fn source_bit(&mut self, source: &Arc<str>) -> u64 {
+        let i = match self.sources.iter().position(|known| known == source) {
+            Some(i) => i,
+            None => {
+                self.sources.push(source.clone());
+                self.sources.len() - 1
+            }
+        };
+        // more sources than that can't be told apart, which only affects the metrics.
+        1 << i.min(63)
+    }
+}
+
+// lines are only kept as a hash, a window of a busy feed holds tens of thousands of them.
+fn hash(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    const MSG: &str = "FLRDF153A>APRS,qAS,RHST:/154006h4858.10N\\00820.89E^301/050/A=001145 !W71! id22DF153A";
+
+    fn packet(source: &str, seconds: i64, line: &str) -> Packet {
+        Packet {
+            received: Utc.with_ymd_and_hms(2023, 2, 19, 15, 40, 6).unwrap() + Duration::seconds(seconds),
+            source: source.into(),
+            line: line.to_owned(),
+            position: None,
+        }
+    }
+
+    #[test]
+    fn duplicates_within_window_are_removed() {
+        let mut dedup = Dedup::new(Duration::seconds(30), vec!["a".into(), "b".into()]);
+        assert!(dedup.is_first(&packet("a", 0, MSG)));
+        assert!(!dedup.is_first(&packet("b", 1, MSG)));
+        assert!(!dedup.is_first(&packet("a", 2, MSG)));
+        // once out of the window, the same content is forwarded again.
+        assert!(dedup.is_first(&packet("b", 31, MSG)));
+        // comments are never duplicates.
+        assert!(dedup.is_first(&packet("a", 32, "# aprsc 2.1.5")));
+        assert!(dedup.is_first(&packet("b", 32, "# aprsc 2.1.5")));
+    }
+
+    #[test]
+    fn lines_missing_from_an_upstream_are_counted() {
+        let mut dedup = Dedup::new(Duration::seconds(30), vec!["missing-a".into(), "missing-b".into()]);
+        let missed_a = UPSTREAM_MISSED.with_label_values(&["missing-a"]);
+        let missed_b = UPSTREAM_MISSED.with_label_values(&["missing-b"]);
+        let missed_local = UPSTREAM_MISSED.with_label_values(&["missing-local"]);
+        let (before_a, before_b, before_local) = (missed_a.get(), missed_b.get(), missed_local.get());
+
+        dedup.is_first(&packet("missing-a", 0, MSG));
+        dedup.is_first(&packet("missing-b", 0, MSG));
+        dedup.is_first(&packet("missing-local", 0, MSG));
+        dedup.is_first(&packet("missing-a", 1, "FLRDF153A>APRS,qAS,RHST:/154007h"));
+        // only a local receiver heard this one, none of the upstreams missed it.
+        dedup.is_first(&packet("missing-local", 2, "FLRDF153A>APRS,qAS,RHST:/154008h"));
+        dedup.is_first(&packet("missing-a", 40, "FLRDF153A>APRS,qAS,RHST:/154046h"));
+
+        assert_eq!(missed_a.get() - before_a, 0);
+        assert_eq!(missed_b.get() - before_b, 1);
+        assert_eq!(missed_local.get() - before_local, 0);
+    }
+}
@@ -0,0 +1,77 @@
+use ogn_aprs_parser::ogn::ogn_aircraft_types::AircraftType;
+
+// An ADS-B emitter category, e.g. B1 for gliders, as used by readsb and GDL90. Categories come in
+// sets A to D of eight, numbered through, so B1 is 9.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmitterCategory(pub u8);
+
+impl EmitterCategory {
+    pub fn from_aircraft_type(aircraft_type: &AircraftType) -> Self {
+        use AircraftType::*;
+        EmitterCategory(match aircraft_type {
+            // light aircraft, below 15500 lbs.
+            TowPlane | DropPlane | PoweredAircraft => 1,
+            // jets and turboprops, most of those we see are small to medium.
+            JetAircraft => 3,
+            Helicopter => 7,
+            Glider => 9,
+            Balloon | Airship => 10,
+            Parachute => 11,
+            // ultralights, hang gliders and paragliders.
+            HangGlider | ParaGlider => 12,
+            UAV => 14,
+            // surface service vehicles.
+            GroundSupport => 18,
+            StaticObject => 19,
+            Other | UFO | Unknown => 0,
+        })
+    }
+
+    // a category as readsb writes it, e.g. `B1`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.as_bytes() {
+            [set @ b'A'..=b'D', number @ b'0'..=b'7'] => Some(EmitterCategory((set - b'A') * 8 + (number - b'0'))),
+            _ => None,
+        }
+    }
+
+    // the closest aircraft type, for aircraft only known from ADS-B.
+    pub fn aircraft_type(self) -> AircraftType {
+        use AircraftType::*;
+        match self.0 {
+            1 => PoweredAircraft,
+            2..=6 => JetAircraft,
+            7 => Helicopter,
+            9 => Glider,
+            10 => Balloon,
+            11 => Parachute,
+            12 => HangGlider,
+            14 => UAV,
+            17 | 18 => GroundSupport,
+            19..=23 => StaticObject,
+            _ => Unknown,
+        }
+    }
+
+    // the category as readsb writes it, e.g. `B1`.
+    pub fn name(self) -> String {
+        format!("{}{}", (b'A' + self.0 / 8) as char, self.0 % 8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categories_are_numbered_through_the_sets() {
+        let glider = EmitterCategory::from_aircraft_type(&AircraftType::Glider);
+        assert_eq!((glider.0, glider.name()), (9, "B1".to_owned()));
+        assert_eq!(EmitterCategory::from_aircraft_type(&AircraftType::TowPlane).name(), "A1");
+        assert_eq!(EmitterCategory::from_aircraft_type(&AircraftType::StaticObject).name(), "C3");
+        assert_eq!(EmitterCategory::from_aircraft_type(&AircraftType::Unknown).name(), "A0");
+        assert_eq!(EmitterCategory::from_name("B1"), Some(glider));
+        assert_eq!(EmitterCategory::from_name("A7").map(EmitterCategory::aircraft_type), Some(AircraftType::Helicopter));
+        assert_eq!(EmitterCategory::from_name("E1"), None);
+    }
+}
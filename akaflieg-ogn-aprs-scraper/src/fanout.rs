@@ -0,0 +1,113 @@
+use tokio::sync::mpsc::Receiver;
+
+use tracing::{debug_span, info, warn, Instrument};
+
+use ogn_aprs_parser::model::ogn_receiver_beacon::OGNReceiverBeacon;
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+
+use crate::backpressure::QueueSender;
+use crate::dedup::Dedup;
+use crate::metrics::{
+    self, DUPLICATE_LINES, PARSED_LINES, PARSE_FAILURES, RECEIVED_LINES, SINK_QUEUE_DEPTH,
+};
+use crate::record::Packet;
+
+// forward every message from the sources to each of the enabled sinks, dropping the duplicates
+// of redundant sources if `dedup` is given. Each sink has its own queue, so a slow sink only
+// holds up the others if its backpressure policy is to block.
+pub async fn fan_out(mut rx: Receiver<Packet>, mut sinks: Vec<QueueSender>, mut dedup: Option<Dedup>) {
+    // loop while channel is still alive
+    while let Some(mut packet) = rx.recv().await {
+        RECEIVED_LINES.with_label_values(&[&packet.source]).inc();
+        metrics::packet_received();
+        if dedup.as_mut().is_some_and(|dedup| !dedup.is_first(&packet)) {
+            DUPLICATE_LINES.inc();
+            continue;
+        }
+        parse(&mut packet);
+
+        let span = debug_span!("packet", source = &*packet.source);
+        async {
+            for sink in &sinks {
+                if sink.send(packet.clone()).await.is_err() {
+                    warn!("{} sink channel closed, no longer forwarding to it", sink.name());
+                }
+                SINK_QUEUE_DEPTH.with_label_values(&[sink.name()]).set(sink.len() as i64);
+            }
+        }
+        .instrument(span)
+        .await;
+        // forget about sinks whose write loop has quit.
+        sinks.retain(|sink| !sink.is_closed());
+    }
+
+    info!("all sources are done, closing the sinks");
+}
+
+// parse the line once for all sinks, and count whether it could be.
+fn parse(packet: &mut Packet) {
+    // server comments aren't meant to be parsed, and positions from other feeds, e.g. ADS-B, come
+    // without a line.
+    if packet.line.starts_with('#') || packet.line.is_empty() {
+        return;
+    }
+    match OGNStatusMessage::from_str(&packet.line, Some(packet.received)) {
+        Ok(position) => {
+            PARSED_LINES.inc();
+            packet.position = Some(position);
+        }
+        // receiver beacons are positions, but not of aircraft.
+        Err(_) if OGNReceiverBeacon::from_str(&packet.line, Some(packet.received)).is_ok() => {}
+        Err(_) => PARSE_FAILURES.with_label_values(&[failure_reason(&packet.line)]).inc(),
+    }
+}
+
+// a rough reason why a line couldn't be parsed, with few enough values to be a label.
+fn failure_reason(line: &str) -> &'static str {
+    match line.split_once(':') {
+        Some((header, _)) if !header.contains('>') => "invalid_header",
+        None => "invalid_header",
+        // status reports, e.g. of receivers, carry no position.
+        Some((_, info)) if info.starts_with('>') => "status",
+        Some((_, info)) if info.starts_with(['/', '@', '!', '=']) => "invalid_position",
+        Some(_) => "unsupported_type",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+    use tokio::sync::mpsc;
+
+    use crate::backpressure::{self, Policy, QueueConfig};
+
+    #[tokio::test]
+    async fn sinks_get_the_line_parsed_once() {
+        let (tx, rx) = mpsc::channel(4);
+        let config = QueueConfig { capacity: 4, policy: Policy::Block, spill_dir: Default::default(), spill_max_bytes: 0 };
+        let (sink, mut sink_rx) = backpressure::sink_channel("fanout-test", &config);
+        tokio::spawn(fan_out(rx, vec![sink], None));
+
+        let line = "FLRDF153A>APRS,qAS,RHST:/154006h4858.10N\\00820.89E^301/050/A=001145 !W71! id22DF153A";
+        for line in [line, "# aprsc 2.1.5-g8af3cdc"] {
+            tx.send(Packet { received: Utc::now(), source: "test".into(), line: line.to_owned(), position: None }).await.unwrap();
+        }
+        let parsed = sink_rx.recv().await.unwrap();
+        assert_eq!(parsed.line, line);
+        assert_eq!(parsed.position.unwrap().aircraft_id.as_deref(), Some("DF153A"));
+        assert_eq!(sink_rx.recv().await.unwrap().position, None);
+    }
+
+    #[test]
+    fn failure_reasons_are_classified() {
+        assert_eq!(failure_reason("not aprs"), "invalid_header");
+        assert_eq!(
+            failure_reason("Koenigsd>OGNSDR,TCPIP*,qAC,GLIDERN2:>154002h v0.2.8.RPI-GPU CPU:0.7"),
+            "status"
+        );
+        assert_eq!(failure_reason("FLRDF153A>APRS,qAS,RHST:/154006hgarbage"), "invalid_position");
+        assert_eq!(failure_reason("FLRDF153A>APRS,qAS,RHST:;OBJECT"), "unsupported_type");
+    }
+}
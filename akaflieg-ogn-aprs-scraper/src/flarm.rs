@@ -0,0 +1,252 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{interval, MissedTickBehavior};
+
+use tracing::{debug, info, info_span, Instrument};
+
+use ogn_aprs_parser::ogn::ogn_address_type::AddressType;
+use ogn_aprs_parser::ogn::ogn_aircraft_types::AircraftType;
+use ogn_fleet_state::geo::distance_km;
+use ogn_fleet_state::{Aircraft, FleetState};
+
+use crate::live::{OwnPosition, Ownship, SharedFleet};
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+const KNOTS_PER_MPS: f64 = 1.943844;
+
+// Traffic as FLARM NMEA, for XCSoar, LK8000 and the like. Every `interval`, each connected client
+// gets the ownship position as `$GPRMC` and `$GPGGA`, a `$PFLAA` for every aircraft within `range_km`
+// of it, and a `$PFLAU` status. Clients connect to XCSoar's "TCP client" port, e.g. port 4353.
+pub struct FlarmConfig {
+    pub addr: String,
+    pub ownship: Ownship,
+    pub range_km: f64,
+    pub interval: Duration,
+}
+
+// the FLARM aircraft type, the inverse of `AircraftType::from_meta`.
+pub fn flarm_type(aircraft_type: &AircraftType) -> u8 {
+    use AircraftType::*;
+    match aircraft_type {
+        Other => 0,
+        Glider => 1,
+        TowPlane => 2,
+        Helicopter => 3,
+        Parachute => 4,
+        DropPlane => 5,
+        HangGlider => 6,
+        ParaGlider => 7,
+        PoweredAircraft => 8,
+        JetAircraft => 9,
+        UFO | Unknown => 10,
+        Balloon => 11,
+        Airship => 12,
+        UAV => 13,
+        GroundSupport => 14,
+        StaticObject => 15,
+    }
+}
+
+// `$<body>*<checksum>`, the checksum being the xor of all bytes of the body.
+pub fn sentence(body: &str) -> String {
+    let checksum = body.bytes().fold(0, |checksum, byte| checksum ^ byte);
+    format!("${}*{:02X}", body, checksum)
+}
+
+// degrees as NMEA `ddmm.mmm`, with `width` digits of degrees.
+fn nmea_degrees(degrees: f64, width: usize, positive: char, negative: char) -> String {
+    let hemisphere = if degrees < 0.0 { negative } else { positive };
+    // round to the thousandth of a minute first, so a minute never rounds up to 60.
+    let thousandths = (degrees.abs() * 60_000.0).round() as u64;
+    let (degrees, minutes) = (thousandths / 60_000, thousandths % 60_000);
+    format!("{:0width$}{:02}.{:03},{}", degrees, minutes / 1000, minutes % 1000, hemisphere, width = width)
+}
+
+fn pflaa(own: &OwnPosition, aircraft: &Aircraft) -> String {
+    let last = &aircraft.last;
+    // flat earth is plenty accurate within FLARM's range.
+    let north = (aircraft.latitude() - own.latitude).to_radians() * EARTH_RADIUS_M;
+    let east = (aircraft.longitude() - own.longitude).to_radians() * EARTH_RADIUS_M * own.latitude.to_radians().cos();
+    let vertical = last.position.altitude as f64 - own.altitude;
+    let id_type = match last.address_type {
+        Some(AddressType::ICAO) => 1,
+        Some(AddressType::FLARM) | Some(AddressType::OGN) => 2,
+        _ => 0,
+    };
+    let turn_rate = last.velocity.rotation.map(|rate| format!("{:.0}", (rate as f64).to_degrees())).unwrap_or_default();
+    let climb = last.velocity.vertical.map(|climb| format!("{:.1}", climb)).unwrap_or_default();
+    sentence(&format!(
+        "PFLAA,0,{},{},{},{},{},{:.0},{},{:.0},{},{:X}",
+        north.round() as i64,
+        east.round() as i64,
+        vertical.round() as i64,
+        id_type,
+        last.aircraft_id.as_deref().unwrap_or(&aircraft.address),
+        last.position.heading,
+        turn_rate,
+        last.velocity.horizontal,
+        climb,
+        flarm_type(&last.aircraft_type),
+    ))
+}
+
+// everything sent to the clients for one update.
+pub fn sentences(fleet: &FleetState, ownship: &Ownship, range_km: f64, now: DateTime<Utc>) -> Vec<String> {
+    let Some(own) = ownship.position(fleet, now) else {
+        // without an ownship position there is no traffic to report, only that there's no gps.
+        return vec![sentence("PFLAU,0,1,0,1,0,,0,,")];
+    };
+
+    let time = now.format("%H%M%S%.3f").to_string();
+    let (latitude, longitude) = (nmea_degrees(own.latitude, 2, 'N', 'S'), nmea_degrees(own.longitude, 3, 'E', 'W'));
+    let mut sentences = vec![
+        sentence(&format!(
+            "GPRMC,{},A,{},{},{:.1},{:.1},{},,",
+            time,
+            latitude,
+            longitude,
+            own.speed * KNOTS_PER_MPS,
+            own.track,
+            now.format("%d%m%y")
+        )),
+        sentence(&format!("GPGGA,{},{},{},1,08,1.0,{:.1},M,,M,,", time, latitude, longitude, own.altitude)),
+    ];
+
+    let mut traffic: Vec<_> = fleet
+        .aircraft()
+        .filter(|aircraft| Some(aircraft.address.as_str()) != own.address && !fleet.is_stale(aircraft, now))
+        .filter(|aircraft| distance_km((own.latitude, own.longitude), (aircraft.latitude(), aircraft.longitude())) <= range_km)
+        .collect();
+    traffic.sort_by(|a, b| a.address.cmp(&b.address));
+    sentences.extend(traffic.iter().map(|aircraft| pflaa(&own, aircraft)));
+    sentences.push(sentence(&format!("PFLAU,{},1,2,1,0,,0,,", traffic.len())));
+    sentences
+}
+
+pub async fn serve(config: FlarmConfig, fleet: SharedFleet) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let listener = TcpListener::bind(&config.addr).await?;
+    info!("serving flarm traffic on {:?}", config.addr);
+    accept(listener, fleet, Arc::new(config)).await?;
+    Ok(())
+}
+
+async fn accept(listener: TcpListener, fleet: SharedFleet, config: Arc<FlarmConfig>) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        info!("flarm client connected from {}", peer);
+        let (fleet, config) = (fleet.clone(), config.clone());
+        tokio::spawn(
+            async move {
+                if let Err(err) = feed(stream, fleet, config).await {
+                    debug!("flarm client gone: {:?}", err);
+                }
+                info!("flarm client disconnected");
+            }
+            .instrument(info_span!("flarm", client = %peer)),
+        );
+    }
+}
+
+// clients may send commands, e.g. `$PFLAC`, which aren't answered; only traffic is sent.
+async fn feed(mut stream: TcpStream, fleet: SharedFleet, config: Arc<FlarmConfig>) -> std::io::Result<()> {
+    let mut tick = interval(config.interval);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tick.tick().await;
+        let sentences = {
+            let live = fleet.read().unwrap();
+            sentences(&live.fleet, &config.ownship, config.range_km, live.clock.now())
+        };
+        let mut buf = String::new();
+        for sentence in sentences {
+            buf.push_str(&sentence);
+            buf.push_str("\r\n");
+        }
+        stream.write_all(buf.as_bytes()).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::RwLock;
+
+    use chrono::TimeZone;
+    use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+    use ogn_fleet_state::FleetConfig;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    use crate::live::LiveFleet;
+
+    fn at(h: u32, m: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 2, 19, h, m, s).unwrap()
+    }
+
+    // a glider circling above Bad Rappenau, a tow plane 1 km north and one far away in Munich.
+    fn fleet() -> FleetState {
+        let mut fleet = FleetState::new(FleetConfig::default());
+        for line in [
+            "FLRDF153A>APRS,qAS,RHST:/154006h4858.00N\\00820.00E'090/050/A=003281 !W00! id06DF153A +394fpm +1.0rot",
+            "ICA3E6DBA>APRS,qAS,RHST:/154006h4858.54N\\00820.00E'180/097/A=001640 !W00! id093E6DBA -197fpm",
+            "FLRDD5678>APRS,qAS,RHST:/154006h4808.00N\\01134.00E'000/050/A=003281 !W00! id06DD5678",
+        ] {
+            fleet.update(OGNStatusMessage::from_str(line, Some(at(0, 0, 0))).unwrap(), at(15, 40, 6));
+        }
+        fleet
+    }
+
+    #[test]
+    fn checksums_match_the_nmea_reference() {
+        let rmc = sentence("GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W");
+        assert_eq!(rmc, "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A");
+        assert_eq!(nmea_degrees(-8.5, 3, 'E', 'W'), "00830.000,W");
+        assert_eq!(nmea_degrees(48.99999999, 2, 'N', 'S'), "4900.000,N");
+        assert_eq!(nmea_degrees(48.1173, 2, 'N', 'S'), "4807.038,N");
+    }
+
+    #[test]
+    fn reports_traffic_relative_to_the_ownship() {
+        let fleet = fleet();
+        let ownship = Ownship::parse("DF153A").unwrap();
+        let sentences = sentences(&fleet, &ownship, 30.0, at(15, 40, 8));
+        assert_eq!(sentences.len(), 4);
+        assert!(sentences[0].starts_with("$GPRMC,154008.000,A,4858.000,N,00820.000,E,50.0,90.0,190223,,*"));
+        assert!(sentences[1].starts_with("$GPGGA,154008.000,4858.000,N,00820.000,E,1,08,1.0,1000.0,M,,M,,*"));
+        // 0.54' north is 1000 m, 500 m below, heading south at 50 m/s and sinking 1 m/s, a tow plane.
+        assert!(sentences[2].starts_with("$PFLAA,0,1001,0,-500,1,3E6DBA,180,,50,-1.0,2*"), "{}", sentences[2]);
+        assert!(sentences[3].starts_with("$PFLAU,1,1,2,1,0,,0,,*"));
+
+        let clubhouse = Ownship::parse("48.966667,8.333333,200").unwrap();
+        let sentences = super::sentences(&fleet, &clubhouse, 30.0, at(15, 40, 8));
+        assert!(sentences[3].starts_with("$PFLAA,0,0,0,800,2,DF153A,90,3,26,2.0,1*"), "{}", sentences[3]);
+        assert!(sentences.last().unwrap().starts_with("$PFLAU,2,"));
+
+        let unknown = Ownship::parse("DD0000").unwrap();
+        assert_eq!(super::sentences(&fleet, &unknown, 30.0, at(15, 40, 8)), [sentence("PFLAU,0,1,0,1,0,,0,,")]);
+    }
+
+    #[tokio::test]
+    async fn clients_get_the_traffic() {
+        let fleet = Arc::new(RwLock::new(LiveFleet::new(FleetState::new(FleetConfig::default()))));
+        let config = FlarmConfig {
+            addr: String::new(),
+            ownship: Ownship::parse("48.9667,8.3333,200").unwrap(),
+            range_km: 30.0,
+            interval: Duration::from_millis(10),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(accept(listener, fleet, Arc::new(config)));
+
+        let mut lines = BufReader::new(TcpStream::connect(addr).await.unwrap()).lines();
+        assert!(lines.next_line().await.unwrap().unwrap().starts_with("$GPRMC,"));
+        assert!(lines.next_line().await.unwrap().unwrap().starts_with("$GPGGA,"));
+        assert!(lines.next_line().await.unwrap().unwrap().starts_with("$PFLAU,0,1,2,"));
+    }
+}
@@ -0,0 +1,307 @@
+use std::error::Error;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Timelike, Utc};
+use tokio::net::UdpSocket;
+use tokio::time::{interval, MissedTickBehavior};
+
+use tracing::{debug, info};
+
+use ogn_aprs_parser::ogn::ogn_address_type::AddressType;
+use ogn_fleet_state::geo::distance_km;
+use ogn_fleet_state::{Aircraft, FleetState};
+
+use crate::emitter::EmitterCategory;
+use crate::live::{OwnPosition, Ownship, SharedFleet};
+
+const FEET_PER_METER: f64 = 3.28084;
+const KNOTS_PER_MPS: f64 = 1.943844;
+
+const FLAG: u8 = 0x7E;
+const ESCAPE: u8 = 0x7D;
+
+const HEARTBEAT: u8 = 0;
+const OWNSHIP_REPORT: u8 = 10;
+const TRAFFIC_REPORT: u8 = 20;
+
+// Traffic as GDL90 over UDP, for SkyDemon, ForeFlight and other EFB apps, following the GDL 90 Data
+// Interface Specification (560-1058-00 Rev A). Every `interval` a heartbeat, the ownship report if
+// `ownship` is set and a traffic report per aircraft are sent to `addr`, usually the broadcast
+// address of the tablets' network on port 4000.
+pub struct Gdl90Config {
+    pub addr: String,
+    pub ownship: Option<Ownship>,
+    // without an ownship position, all aircraft are sent.
+    pub range_km: f64,
+    pub interval: Duration,
+}
+
+// the CRC-CCITT table from the specification, section 2.2.3.
+static CRC_TABLE: LazyLock<[u16; 256]> = LazyLock::new(|| {
+    let mut table = [0; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = (i as u16) << 8;
+        for _ in 0..8 {
+            crc = (crc << 1) ^ if crc & 0x8000 != 0 { 0x1021 } else { 0 };
+        }
+        *entry = crc;
+    }
+    table
+});
+
+pub fn crc(message: &[u8]) -> u16 {
+    message.iter().fold(0, |crc, byte| CRC_TABLE[(crc >> 8) as usize] ^ (crc << 8) ^ *byte as u16)
+}
+
+// a message with its crc, least significant byte first, between flags. Flag and escape bytes in
+// between are escaped as the escape byte followed by the byte xor 0x20.
+pub fn frame(message: &[u8]) -> Vec<u8> {
+    let crc = crc(message);
+    let mut framed = vec![FLAG];
+    for &byte in message.iter().chain(&crc.to_le_bytes()) {
+        if byte == FLAG || byte == ESCAPE {
+            framed.extend([ESCAPE, byte ^ 0x20]);
+        } else {
+            framed.push(byte);
+        }
+    }
+    framed.push(FLAG);
+    framed
+}
+
+// the heartbeat, with the seconds since midnight UTC. `gps_valid` tells the app whether to trust
+// the ownship position.
+pub fn heartbeat(now: DateTime<Utc>, gps_valid: bool) -> Vec<u8> {
+    let timestamp = now.num_seconds_from_midnight();
+    let status1 = if gps_valid { 0x81 } else { 0x01 };
+    // utc ok, and the 17th bit of the timestamp.
+    let status2 = 0x01 | ((timestamp >> 16) as u8 & 0x01) << 7;
+    let [low, high, ..] = timestamp.to_le_bytes();
+    vec![HEARTBEAT, status1, status2, low, high, 0, 0]
+}
+
+// the fields of an ownship or traffic report, in the units of the specification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    // 0 for ADS-B with an ICAO address, 1 for a self-assigned one, e.g. FLARM.
+    pub address_type: u8,
+    pub address: u32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude_ft: f64,
+    pub airborne: bool,
+    pub speed_kt: f64,
+    pub vertical_fpm: Option<f64>,
+    pub track: f64,
+    pub category: EmitterCategory,
+    pub callsign: String,
+}
+
+// degrees as 24 bit two's complement fractions of 180°.
+fn semicircles(degrees: f64) -> [u8; 3] {
+    let value = (degrees * (1 << 23) as f64 / 180.0) as i32;
+    let [_, high, mid, low] = value.to_be_bytes();
+    [high, mid, low]
+}
+
+impl Report {
+    pub fn encode(&self, id: u8) -> Vec<u8> {
+        let mut message = vec![id, self.address_type & 0x0F];
+        message.extend(&self.address.to_be_bytes()[1..]);
+        message.extend(semicircles(self.latitude));
+        message.extend(semicircles(self.longitude));
+
+        // 25 ft steps from -1000 ft; the misc nibble tells airborne and that the track is true.
+        let altitude = ((self.altitude_ft + 1000.0) / 25.0).round().clamp(0.0, 0xFFE as f64) as u16;
+        let misc = if self.airborne { 0b1001 } else { 0b0001 };
+        message.extend([(altitude >> 4) as u8, ((altitude & 0x0F) << 4) as u8 | misc]);
+        // NIC 10 and NACp 9, positions are from GPS.
+        message.push(0xA9);
+
+        let speed = (self.speed_kt.round() as u16).min(0xFFE);
+        // 64 fpm steps, 0x800 if unknown.
+        let vertical = match self.vertical_fpm {
+            Some(fpm) => ((fpm / 64.0).round().clamp(-510.0, 510.0) as i16 as u16) & 0x0FFF,
+            None => 0x800,
+        };
+        message.extend([(speed >> 4) as u8, ((speed & 0x0F) << 4) as u8 | (vertical >> 8) as u8, vertical as u8]);
+        message.push((self.track.rem_euclid(360.0) * 256.0 / 360.0) as u8);
+        message.push(self.category.0);
+
+        let mut callsign: Vec<u8> = self.callsign.bytes().filter(u8::is_ascii_alphanumeric).take(8).collect();
+        callsign.resize(8, b' ');
+        message.extend(callsign);
+        // no emergency.
+        message.push(0);
+        message
+    }
+}
+
+fn traffic(aircraft: &Aircraft) -> Option<Report> {
+    let last = &aircraft.last;
+    let address = u32::from_str_radix(last.aircraft_id.as_deref()?, 16).ok()?;
+    Some(Report {
+        address_type: if last.address_type == Some(AddressType::ICAO) { 0 } else { 1 },
+        address,
+        latitude: aircraft.latitude(),
+        longitude: aircraft.longitude(),
+        altitude_ft: last.position.altitude as f64 * FEET_PER_METER,
+        airborne: true,
+        speed_kt: last.velocity.horizontal as f64 * KNOTS_PER_MPS,
+        vertical_fpm: last.velocity.vertical.map(|climb| climb as f64 * FEET_PER_METER * 60.0),
+        track: last.position.heading as f64,
+        category: EmitterCategory::from_aircraft_type(&last.aircraft_type),
+        callsign: aircraft.address.clone(),
+    })
+}
+
+fn ownship(own: &OwnPosition, fleet: &FleetState) -> Report {
+    let aircraft = own.address.and_then(|address| fleet.get(address));
+    match aircraft.and_then(traffic) {
+        Some(report) => report,
+        None => Report {
+            address_type: 1,
+            address: 0,
+            latitude: own.latitude,
+            longitude: own.longitude,
+            altitude_ft: own.altitude * FEET_PER_METER,
+            airborne: false,
+            speed_kt: own.speed * KNOTS_PER_MPS,
+            vertical_fpm: own.climb.map(|climb| climb * FEET_PER_METER * 60.0),
+            track: own.track,
+            category: EmitterCategory(0),
+            callsign: "OGN".to_owned(),
+        },
+    }
+}
+
+// the framed messages of one update.
+pub fn messages(fleet: &FleetState, config: &Gdl90Config, now: DateTime<Utc>) -> Vec<Vec<u8>> {
+    let own = config.ownship.as_ref().and_then(|ownship| ownship.position(fleet, now));
+    let mut messages = vec![frame(&heartbeat(now, own.is_some()))];
+    if let Some(own) = &own {
+        messages.push(frame(&ownship(own, fleet).encode(OWNSHIP_REPORT)));
+    }
+
+    let in_range = |aircraft: &Aircraft| match &own {
+        Some(own) => distance_km((own.latitude, own.longitude), (aircraft.latitude(), aircraft.longitude())) <= config.range_km,
+        None => true,
+    };
+    let own_address = own.as_ref().and_then(|own| own.address);
+    let mut traffic: Vec<_> = fleet
+        .aircraft()
+        .filter(|aircraft| Some(aircraft.address.as_str()) != own_address && !fleet.is_stale(aircraft, now))
+        .filter(|aircraft| in_range(aircraft))
+        .filter_map(traffic)
+        .collect();
+    traffic.sort_by_key(|report| report.address);
+    messages.extend(traffic.iter().map(|report| frame(&report.encode(TRAFFIC_REPORT))));
+    messages
+}
+
+pub async fn send(config: Gdl90Config, fleet: SharedFleet) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.set_broadcast(true)?;
+    info!("sending gdl90 traffic to {:?}", config.addr);
+
+    let mut tick = interval(config.interval);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tick.tick().await;
+        let messages = {
+            let live = fleet.read().unwrap();
+            messages(&live.fleet, &config, live.clock.now())
+        };
+        for message in messages {
+            // nobody listening, e.g. the tablets aren't on the network yet, isn't an error.
+            if let Err(err) = socket.send_to(&message, &config.addr).await {
+                debug!("error sending gdl90 message: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+    use ogn_fleet_state::FleetConfig;
+
+    #[test]
+    fn frames_match_the_specification() {
+        // the heartbeat example of section 2.2.4.
+        let example = [0x00, 0x81, 0x41, 0xDB, 0xD0, 0x08, 0x02];
+        assert_eq!(frame(&example), [0x7E, 0x00, 0x81, 0x41, 0xDB, 0xD0, 0x08, 0x02, 0xB3, 0x8B, 0x7E]);
+        // flag and escape bytes, here in the crc, are escaped.
+        assert_eq!(frame(&[0x7E, 0x7D]).iter().filter(|byte| **byte == FLAG).count(), 2);
+        assert_eq!(&frame(&[0x7E, 0x7D])[1..5], [ESCAPE, 0x5E, ESCAPE, 0x5D]);
+
+        let time = Utc.with_ymd_and_hms(2023, 2, 19, 14, 51, 7).unwrap();
+        assert_eq!(heartbeat(time, true), [0x00, 0x81, 0x01, 0xDB, 0xD0, 0x00, 0x00]);
+        let evening = Utc.with_ymd_and_hms(2023, 2, 19, 20, 0, 0).unwrap();
+        assert_eq!(heartbeat(evening, false)[1..3], [0x01, 0x81]);
+    }
+
+    #[test]
+    fn reports_match_the_specification() {
+        // the traffic report example of section 3.5.4.
+        let report = Report {
+            address_type: 0,
+            address: 0xAB4549,
+            latitude: 44.90708,
+            longitude: -122.99488,
+            altitude_ft: 5000.0,
+            airborne: true,
+            speed_kt: 123.0,
+            vertical_fpm: Some(64.0),
+            track: 45.0,
+            category: EmitterCategory(1),
+            callsign: "N825V".to_owned(),
+        };
+        let expected = [
+            0x14, 0x00, 0xAB, 0x45, 0x49, 0x1F, 0xEF, 0x15, 0xA8, 0x89, 0x78, 0x0F, 0x09, 0xA9, 0x07, 0xB0,
+            0x01, 0x20, 0x01, 0x4E, 0x38, 0x32, 0x35, 0x56, 0x20, 0x20, 0x20, 0x00,
+        ];
+        assert_eq!(report.encode(TRAFFIC_REPORT), expected);
+        let unknown = Report { vertical_fpm: None, ..report };
+        assert_eq!(unknown.encode(TRAFFIC_REPORT)[15..17], [0xB8, 0x00]);
+    }
+
+    #[test]
+    fn sends_the_fleet_around_the_ownship() {
+        let time = Utc.with_ymd_and_hms(2023, 2, 19, 15, 40, 8).unwrap();
+        let mut fleet = FleetState::new(FleetConfig::default());
+        for line in [
+            "FLRDF153A>APRS,qAS,RHST:/154006h4858.00N\\00820.00E'090/050/A=003281 !W00! id06DF153A -128fpm",
+            "ICA3E6DBA>APRS,qAS,RHST:/154006h4858.54N\\00820.00E'180/097/A=001640 !W00! id093E6DBA",
+            "FLRDD5678>APRS,qAS,RHST:/154006h4808.00N\\01134.00E'000/050/A=003281 !W00! id06DD5678",
+        ] {
+            fleet.update(OGNStatusMessage::from_str(line, Some(time)).unwrap(), time);
+        }
+        let config = Gdl90Config {
+            addr: String::new(),
+            ownship: Ownship::parse("DF153A"),
+            range_km: 30.0,
+            interval: Duration::from_secs(1),
+        };
+
+        let messages = messages(&fleet, &config, time);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0][2], 0x81);
+        // the ownship is the glider with its self-assigned address.
+        let ownship = &messages[1];
+        assert_eq!(ownship[1..6], [OWNSHIP_REPORT, 0x01, 0xDF, 0x15, 0x3A]);
+        assert_eq!(ownship[15..18], [0x03, 0x2F, 0xFE]);
+        assert_eq!(ownship[19], 9);
+        assert_eq!(&ownship[20..28], b"DF153A  ");
+        // the tow plane, an ICAO address, is in range but Munich isn't.
+        assert_eq!(messages[2][1..6], [TRAFFIC_REPORT, 0x00, 0x3E, 0x6D, 0xBA]);
+        assert_eq!(messages[2][19], 1);
+
+        let without_ownship = Gdl90Config { ownship: None, ..config };
+        assert_eq!(super::messages(&fleet, &without_ownship, time).len(), 4);
+    }
+}
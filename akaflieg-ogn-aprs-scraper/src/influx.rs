@@ -1,92 +1,362 @@
+use std::error::Error;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
+use chrono::{DateTime, SecondsFormat, Utc};
 use tokio::sync::mpsc::Receiver;
-use tokio_stream::Stream;
+use tokio::time::{interval, sleep, MissedTickBehavior};
 
-use influxdb2::models::DataPoint;
-use influxdb2::Client;
+use influxdb2::models::WriteDataPoint;
+use influxdb2::{Client, RequestError};
 
-use log::{error, warn, info};
+use tracing::{debug, error, info, warn};
 
-use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+use crate::metrics::{
+    INFLUX_DROPPED_POINTS, INFLUX_QUEUE_DEPTH, INFLUX_SPOOL_BYTES, INFLUX_SPOOL_POINTS,
+    INFLUX_WRITE_RETRIES, INFLUX_WRITTEN_POINTS, SINK_ERRORS, SINK_WRITE_SECONDS,
+};
+use crate::influx_schema::{transform_parsed, transform_position};
+use crate::record::Packet;
+use crate::spool::Spool;
 
-pub async fn write_aprs(client: Arc<Client>, aprs_bucket: &str, mut rx: Receiver<String>) {
-    // loop while channel is still alive
-    while let Some(aprs_msg) = rx.recv().await {
-        // transform received string to influx datapoint
-        let transformed = match transform_aprs(aprs_msg) {
-            Some(datapoint) => datapoint,
-            None => continue,
-        };
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
 
-        // write the datapoint to db, log any errors but continue anyway.
-        match client.write(aprs_bucket, transformed).await {
-            Ok(_) => continue,
-            Err(err) => {
-                error!("error writing to influx: {:?}", err);
-                continue;
-            }
+pub struct InfluxWriterConfig {
+    pub org: String,
+    pub bucket: String,
+    pub batch_size: u64,
+    pub flush_interval: Duration,
+    pub max_retries: u32,
+    pub spool_dir: PathBuf,
+    pub spool_max_bytes: u64,
+}
+
+pub async fn write_aprs(client: Arc<Client>, config: InfluxWriterConfig, mut rx: Receiver<Packet>) {
+    let mut flush_timer = interval(config.flush_interval);
+    flush_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut writer = InfluxWriter::new(client, config);
+    let mut lines = Vec::new();
+    let mut points = 0;
+
+    // loop while channel is still alive, flushing whenever the batch is full or the timer fires.
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(packet) => {
+                    // transform received string to influx line protocol; positions from other
+                    // feeds, e.g. ADS-B, only make a fix.
+                    let datapoints = match packet.position {
+                        Some(position) if packet.line.is_empty() => transform_position(position).into_iter().collect(),
+                        position => transform_parsed(&packet.line, packet.received, position),
+                    };
+                    for datapoint in datapoints {
+                        match datapoint.write_data_point_to(&mut lines) {
+                            Ok(()) => points += 1,
+                            Err(err) => error!("error encoding datapoint: {:?}", err),
+                        }
+                    }
+                    INFLUX_QUEUE_DEPTH.set(rx.len() as i64 + points as i64);
+                    if points < writer.config.batch_size {
+                        continue;
+                    }
+                }
+                None => break,
+            },
+            _ = flush_timer.tick() => {}
         }
+
+        writer.flush(std::mem::take(&mut lines), points).await;
+        points = 0;
+        INFLUX_QUEUE_DEPTH.set(rx.len() as i64);
     }
 
-    // this should really only happen when quitting the program.
-    warn! {"quit influx aprs write loop"}
+    writer.flush(lines, points).await;
+
+    // all sources are done or were stopped for shutdown, and everything they read is written.
+    info!("flushed and closed influx sink");
+}
+
+// Writes batches of line protocol to influx. Batches that can't be written even after retrying
+// go to the on-disk spool, which is replayed in order once influx is reachable again.
+struct InfluxWriter {
+    client: Arc<Client>,
+    config: InfluxWriterConfig,
+    spool: Option<Spool>,
 }
 
-pub fn transform_aprs(aprs_msg: String) -> Option<impl Stream<Item = DataPoint>> {
-    let mut builder = DataPoint::builder("aprs").field("message", aprs_msg.clone());
-    let parsed =OGNStatusMessage::from_str(&aprs_msg, None);
-    if let Ok(p) = parsed  {
-        builder = builder.timestamp( p.timestamp.timestamp_nanos());
-        builder = builder
-            .tag("aprs_callsign", p.aprs_callsign)
-            .tag("aprs_path", p.aprs_path)
-            .tag("aprs_type", format!("{:?}", p.aprs_type))
-            .tag("aircraft_type", format!("{:?}", p.aircraft_type))
-            .tag("parsed", "true");
-        builder = builder
-            .field("lat", p.position.latitude as f64)
-            .field("long", p.position.longitude as f64)
-            .field("heading", p.position.heading as f64)
-            .field("altitude", p.position.altitude as f64);
-        builder = builder.field("vel_horiz", p.velocity.horizontal as f64);
-        if let Some(vel) = p.velocity.vertical {
-            builder = builder.field("vel_vert", vel as f64);
+impl InfluxWriter {
+    fn new(client: Arc<Client>, config: InfluxWriterConfig) -> Self {
+        let spool = match Spool::open(&config.spool_dir, config.spool_max_bytes) {
+            Ok(spool) => Some(spool),
+            Err(err) => {
+                error!(
+                    "error opening influx spool at {:?}, continuing without: {:?}",
+                    config.spool_dir, err
+                );
+                None
+            }
+        };
+        let writer = InfluxWriter { client, config, spool };
+        writer.update_spool_metrics();
+        writer
+    }
+
+    async fn flush(&mut self, lines: Vec<u8>, points: u64) {
+        // as long as there are spooled points, new points queue up behind them to keep their order.
+        if self.spool.as_ref().is_some_and(|spool| !spool.is_empty()) {
+            if points > 0 {
+                self.spool_batch(&lines, points);
+            }
+            self.replay_spool().await;
+            return;
         }
-        if let Some(rot) = p.velocity.rotation {
-            builder = builder.field("rot", rot as f64);
+
+        if points == 0 {
+            return;
         }
-        if let Some(id) = p.aircraft_id {
-            builder = builder.tag("aircraft_id", id);
+
+        match self.write_with_retries(&lines).await {
+            Ok(()) => INFLUX_WRITTEN_POINTS.inc_by(points),
+            Err(err) => {
+                error!("error writing to influx, spooling {} points: {:?}", points, err);
+                self.spool_batch(&lines, points);
+            }
         }
-        if let Some(flags) = p.ogn_flags {
-            builder = builder
-                .tag("stealth", flags.stealth_mode.to_string())
-                .tag("no_track", flags.no_tracking_mode.to_string());
+    }
+
+    async fn write_with_retries(&self, lines: &[u8]) -> Result<(), RequestError> {
+        let mut backoff = Duration::from_secs(1);
+        let mut attempt = 0;
+        loop {
+            match self.write(lines).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.config.max_retries => {
+                    warn!("error writing to influx, retrying in {:?}: {:?}", backoff, err);
+                    INFLUX_WRITE_RETRIES.inc();
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
         }
-        if let Some(addr_t) = p.address_type {
-            builder = builder.tag("addr_type", format!("{:?}", addr_t));
+    }
+
+    async fn write(&self, lines: &[u8]) -> Result<(), RequestError> {
+        let _timer = SINK_WRITE_SECONDS.with_label_values(&["influx"]).start_timer();
+        let result = self
+            .client
+            .write_line_protocol(&self.config.org, &self.config.bucket, lines.to_vec())
+            .await;
+        if result.is_err() {
+            SINK_ERRORS.with_label_values(&["influx"]).inc();
         }
-        if let Some(reception) = p.reception {
-            builder = builder.field("reception", reception as f64);
+        result
+    }
+
+    fn spool_batch(&mut self, lines: &[u8], points: u64) {
+        let dropped = match &mut self.spool {
+            Some(spool) => match spool.push(lines, points) {
+                Ok(dropped) => dropped,
+                Err(err) => {
+                    error!("error writing to influx spool: {:?}", err);
+                    points
+                }
+            },
+            None => points,
+        };
+        if dropped > 0 {
+            warn!("dropped {} points that could not be written to influx", dropped);
+            INFLUX_DROPPED_POINTS.inc_by(dropped);
         }
-    } else {
-        println!("FAILED PARSE: {}", aprs_msg);
-        info!("Parse failed.");
-        println!("{:?}", parsed);
-        builder = builder.tag("parsed", "false")
+        self.update_spool_metrics();
     }
 
-    let dp = match builder.build() {
-        Ok(dp) => dp,
-        Err(err) => {
-            error!(
-                "error creating datapoint from aprs message <{:?}>: {:?}",
-                aprs_msg, err
-            );
-            return None;
+    // write spooled batches oldest first, stopping at the first one that fails.
+    async fn replay_spool(&mut self) {
+        while let Some(front) = self.spool.as_ref().and_then(Spool::front) {
+            let written = match front {
+                Ok(lines) => match self.write(&lines).await {
+                    Ok(()) => true,
+                    Err(err) => {
+                        debug!("influx still unavailable, keeping spool: {:?}", err);
+                        break;
+                    }
+                },
+                Err(err) => {
+                    error!("error reading influx spool, dropping segment: {:?}", err);
+                    false
+                }
+            };
+
+            if let Some(spool) = &mut self.spool {
+                match spool.pop_front() {
+                    Ok(Some(points)) if written => INFLUX_WRITTEN_POINTS.inc_by(points),
+                    Ok(Some(points)) => INFLUX_DROPPED_POINTS.inc_by(points),
+                    Ok(None) => {}
+                    Err(err) => {
+                        error!("error removing influx spool segment: {:?}", err);
+                        break;
+                    }
+                }
+            }
+            self.update_spool_metrics();
         }
-    };
+    }
 
-    Some(tokio_stream::once(dp))
+    fn update_spool_metrics(&self) {
+        let (bytes, points) = self
+            .spool
+            .as_ref()
+            .map_or((0, 0), |spool| (spool.bytes(), spool.records()));
+        INFLUX_SPOOL_BYTES.set(bytes as i64);
+        INFLUX_SPOOL_POINTS.set(points as i64);
+    }
+}
+
+// Deletes points in [start, stop], both inclusive. Unlike `Client::delete`, which truncates
+// to whole seconds, this keeps nanosecond precision so single points can be deleted.
+pub async fn delete_range(
+    influx: &InfluxConnection,
+    bucket: &str,
+    start: DateTime<Utc>,
+    stop: DateTime<Utc>,
+    predicate: &str,
+) -> Result<(), Box<dyn Error>> {
+    let body = serde_json::json!({
+        "start": start.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        "stop": stop.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        "predicate": predicate,
+    });
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v2/delete", influx.url.trim_end_matches('/')))
+        .query(&[("org", influx.org.as_str()), ("bucket", bucket)])
+        .header("Authorization", format!("Token {}", influx.token))
+        .json(&body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(format!("error deleting from influx: {}", response.text().await?).into());
+    }
+    Ok(())
+}
+
+// what we need to talk to the influx API directly.
+pub struct InfluxConnection {
+    pub url: String,
+    pub org: String,
+    pub token: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::Router;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+
+    #[derive(Default)]
+    struct FakeInflux {
+        up: AtomicBool,
+        bodies: Mutex<Vec<String>>,
+    }
+
+    async fn fake_write(State(influx): State<Arc<FakeInflux>>, body: String) -> StatusCode {
+        if !influx.up.load(Ordering::SeqCst) {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        influx.bodies.lock().unwrap().push(body);
+        StatusCode::NO_CONTENT
+    }
+
+    fn message(time: &str) -> Packet {
+        let line = format!("ICA3E6DBA>APRS,qAS,Schwend:/{}h4832.45N\\00803.85E^206/080/A=003503 !W75! id213E6DBA -316fpm", time);
+        Packet {
+            received: crate::influx_schema::receive_time(),
+            source: "test".into(),
+            line: line.clone(),
+            position: OGNStatusMessage::from_str(&line, None).ok(),
+        }
+    }
+
+    #[tokio::test]
+    async fn spooled_points_are_replayed_in_order() {
+        let influx = Arc::new(FakeInflux::default());
+        let app = Router::new()
+            .route("/api/v2/write", post(fake_write))
+            .with_state(influx.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let spool_dir = tempfile::tempdir().unwrap();
+        let config = InfluxWriterConfig {
+            org: "org".to_owned(),
+            bucket: "aprs".to_owned(),
+            batch_size: 1,
+            flush_interval: Duration::from_millis(50),
+            max_retries: 0,
+            spool_dir: spool_dir.path().to_owned(),
+            spool_max_bytes: 1 << 20,
+        };
+        let (tx, rx) = mpsc::channel(8);
+        let writer = tokio::spawn(write_aprs(Arc::new(Client::new(url, "org", "token")), config, rx));
+
+        // influx is down, both points end up in the spool.
+        tx.send(message("112437")).await.unwrap();
+        tx.send(message("112438")).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        assert!(influx.bodies.lock().unwrap().is_empty());
+
+        // once influx is back, the next flush replays the spool oldest first.
+        influx.up.store(true, Ordering::SeqCst);
+        sleep(Duration::from_millis(200)).await;
+        drop(tx);
+        writer.await.unwrap();
+
+        // each batch holds the fix and the raw message.
+        let bodies = influx.bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert!(bodies[0].starts_with("aprs_fix"));
+        assert!(bodies[0].contains("112437h"));
+        assert!(bodies[1].contains("112438h"));
+        assert_eq!(std::fs::read_dir(spool_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn deletes_keep_nanosecond_precision() {
+        let influx = Arc::new(FakeInflux::default());
+        influx.up.store(true, Ordering::SeqCst);
+        let app = Router::new()
+            .route("/api/v2/delete", post(fake_write))
+            .with_state(influx.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let connection = InfluxConnection {
+            url,
+            org: "org".to_owned(),
+            token: "token".to_owned(),
+        };
+        let time = DateTime::parse_from_rfc3339("2023-02-19T11:24:37.000000042Z").unwrap().to_utc();
+        delete_range(&connection, "aprs", time, time, "parsed=\"false\"").await.unwrap();
+
+        let bodies = influx.bodies.lock().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(body["start"], "2023-02-19T11:24:37.000000042Z");
+        assert_eq!(body["stop"], body["start"]);
+        assert_eq!(body["predicate"], "parsed=\"false\"");
+    }
 }
@@ -0,0 +1,269 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use std::error::Error;
+
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+
+use influxdb2::models::data_point::DataPointBuilder;
+use influxdb2::models::{DataPoint, Query};
+use influxdb2::{Client, FromMap};
+use influxdb2_structmap::value::Value;
+use influxdb2_structmap::GenericMap;
+
+use tracing::{debug, error};
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+
+use crate::record::AprsFix;
+
+// InfluxDB measurement schema, version 2.
+//
+// Tags only hold values from a small, bounded set, everything else is a field.
+// Every point carries a `schema_version` tag, so points from different versions can be told apart.
+//
+// `aprs_fix`: one point per decoded position, timestamped with the packet time.
+//   tags:   schema_version, address, aprs_type, aircraft_type, addr_type, stealth, no_track
+//   fields: aprs_callsign, aprs_path, aircraft_id, lat, long, altitude, heading, vel_horiz,
+//           vel_vert, rot, reception, bit_errors, frequency_offset, gps_resolution_horizontal,
+//           gps_resolution_vertical, position_precision_lat, position_precision_long
+//
+//   `address` (the aircraft id, or the callsign if the message has none) is the only per-aircraft
+//   tag. It is required, as fixes of two aircraft in the same second would overwrite each other
+//   otherwise, and its cardinality is bounded by the number of tracked aircraft.
+//
+// `aprs_raw`: every received line, timestamped with the time of receipt.
+//   tags:   schema_version, parsed ("true" / "false")
+//   fields: message
+//
+// Units are SI: degrees, metres, m/s, rad/s, dB, kHz. Optional fields are left out when the
+// message doesn't carry them.
+//
+// Version 1 wrote parsed fields and the raw message into a single `aprs` measurement, with
+// `aprs_path` and `aircraft_id` as tags. Use the `migrate-schema` command to convert it.
+pub const SCHEMA_VERSION: &str = "2";
+pub const FIX_MEASUREMENT: &str = "aprs_fix";
+pub const RAW_MEASUREMENT: &str = "aprs_raw";
+pub const LEGACY_MEASUREMENT: &str = "aprs";
+
+// transform one received message into its `aprs_fix` and `aprs_raw` points.
+// The receive time dates the packet (which only carries a time of day) and the raw message.
+pub fn transform_aprs(aprs_msg: &str, received: DateTime<Utc>) -> Vec<DataPoint> {
+    let parsed = OGNStatusMessage::from_str(aprs_msg, Some(received));
+    if let Err(err) = &parsed {
+        debug!("failed to parse <{}>: {:?}", aprs_msg, err);
+    }
+    transform_parsed(aprs_msg, received, parsed.ok())
+}
+
+// like `transform_aprs`, for a message that was already parsed, see `fanout`.
+pub fn transform_parsed(aprs_msg: &str, received: DateTime<Utc>, parsed: Option<OGNStatusMessage>) -> Vec<DataPoint> {
+    let is_parsed = parsed.is_some();
+    let mut points = Vec::with_capacity(2);
+    if let Some(p) = parsed {
+        points.extend(build(aprs_msg, fix_point(&AprsFix::from(p))));
+    }
+    points.extend(build(aprs_msg, raw_point(aprs_msg, received, is_parsed)));
+    points
+}
+
+// the `aprs_fix` point of a position that didn't arrive as an APRS line, e.g. from ADS-B.
+pub fn transform_position(position: OGNStatusMessage) -> Option<DataPoint> {
+    let fix = AprsFix::from(position);
+    build(&fix.aprs_path, fix_point(&fix))
+}
+
+// only the `aprs_fix` point of a message, if it can be parsed.
+pub fn transform_fix(aprs_msg: &str, received: DateTime<Utc>) -> Option<DataPoint> {
+    let parsed = OGNStatusMessage::from_str(aprs_msg, Some(received)).ok()?;
+    build(aprs_msg, fix_point(&AprsFix::from(parsed)))
+}
+
+fn fix_point(fix: &AprsFix) -> DataPointBuilder {
+    let mut builder = DataPoint::builder(FIX_MEASUREMENT)
+        .timestamp(nanos(fix.timestamp))
+        .tag("schema_version", SCHEMA_VERSION)
+        .tag("address", fix.address())
+        .tag("aprs_type", &fix.aprs_type)
+        .tag("aircraft_type", &fix.aircraft_type)
+        .field("aprs_callsign", fix.aprs_callsign.clone())
+        .field("aprs_path", fix.aprs_path.clone())
+        .field("lat", fix.lat)
+        .field("long", fix.long)
+        .field("altitude", fix.altitude)
+        .field("heading", fix.heading)
+        .field("vel_horiz", fix.vel_horiz);
+
+    if let Some(addr_t) = &fix.addr_type {
+        builder = builder.tag("addr_type", addr_t);
+    }
+    if let Some(stealth) = fix.stealth {
+        builder = builder.tag("stealth", stealth.to_string());
+    }
+    if let Some(no_track) = fix.no_track {
+        builder = builder.tag("no_track", no_track.to_string());
+    }
+    if let Some(id) = &fix.aircraft_id {
+        builder = builder.field("aircraft_id", id.clone());
+    }
+
+    let optional_fields = [
+        ("vel_vert", fix.vel_vert),
+        ("rot", fix.rot),
+        ("reception", fix.reception),
+        ("bit_errors", fix.bit_errors.map(f64::from)),
+        ("frequency_offset", fix.frequency_offset),
+        ("gps_resolution_horizontal", fix.gps_resolution_horizontal.map(f64::from)),
+        ("gps_resolution_vertical", fix.gps_resolution_vertical.map(f64::from)),
+        ("position_precision_lat", fix.position_precision_lat),
+        ("position_precision_long", fix.position_precision_long),
+    ];
+    for (name, value) in optional_fields {
+        if let Some(value) = value {
+            builder = builder.field(name, value);
+        }
+    }
+    builder
+}
+
+fn raw_point(
+    aprs_msg: &str,
+    received: DateTime<Utc>,
+    parsed: bool,
+) -> DataPointBuilder {
+    DataPoint::builder(RAW_MEASUREMENT)
+        .timestamp(nanos(received))
+        .tag("schema_version", SCHEMA_VERSION)
+        .tag("parsed", parsed.to_string())
+        .field("message", aprs_msg.to_owned())
+}
+
+fn build(aprs_msg: &str, builder: DataPointBuilder) -> Option<DataPoint> {
+    match builder.build() {
+        Ok(dp) => Some(dp),
+        Err(err) => {
+            error!(
+                "error creating datapoint from aprs message <{:?}>: {:?}",
+                aprs_msg, err
+            );
+            None
+        }
+    }
+}
+
+// a stored message and its timestamp, as returned by `query_messages`.
+#[derive(Debug, Default)]
+pub struct MessageRow {
+    pub time: Option<DateTime<FixedOffset>>,
+    pub message: String,
+}
+
+impl FromMap for MessageRow {
+    fn from_genericmap(map: GenericMap) -> Self {
+        let mut row = MessageRow::default();
+        if let Some(Value::TimeRFC(time)) = map.get("_time") {
+            row.time = Some(*time);
+        }
+        if let Some(Value::String(message)) = map.get("_value") {
+            row.message = message.clone();
+        }
+        row
+    }
+}
+
+// query stored messages in [start, stop) oldest first, `filter` selects the message field.
+pub async fn query_messages(
+    client: &Client,
+    bucket: &str,
+    filter: &str,
+    start: DateTime<Utc>,
+    stop: DateTime<Utc>,
+) -> Result<Vec<MessageRow>, Box<dyn Error>> {
+    let flux = format!(
+        "from(bucket: \"{}\")
+            |> range(start: {}, stop: {})
+            |> filter(fn: (r) => {})
+            |> keep(columns: [\"_time\", \"_value\"])
+            |> group()
+            |> sort(columns: [\"_time\"])",
+        bucket,
+        start.to_rfc3339(),
+        stop.to_rfc3339(),
+        filter
+    );
+    Ok(client.query::<MessageRow>(Some(Query::new(flux))).await?)
+}
+
+// nanoseconds since the epoch, which is what influx expects for timestamps.
+pub fn nanos(time: DateTime<Utc>) -> i64 {
+    time.timestamp_nanos_opt().unwrap_or_default()
+}
+
+// The current time, strictly increasing in nanoseconds between calls. Raw messages are keyed
+// by their time of receipt only, so two lines must never share a timestamp.
+pub fn receive_time() -> DateTime<Utc> {
+    static LAST: AtomicI64 = AtomicI64::new(0);
+
+    let now = nanos(Utc::now());
+    let prev = LAST
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            Some(now.max(last + 1))
+        })
+        .unwrap_or(now);
+    Utc.timestamp_nanos(now.max(prev + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use influxdb2::models::WriteDataPoint;
+
+    fn line_protocol(points: &[DataPoint]) -> Vec<String> {
+        points
+            .iter()
+            .map(|point| {
+                let mut buf = Vec::new();
+                point.write_data_point_to(&mut buf).unwrap();
+                String::from_utf8(buf).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parsed_message_is_split_into_fix_and_raw() {
+        let msg = "ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\\00803.85E^206/080/A=003503 !W75! id213E6DBA -316fpm +0.1rot 9.8dB 6e -4.5kHz gps2x2";
+        let received = Utc.with_ymd_and_hms(2023, 2, 19, 11, 24, 38).unwrap();
+        let lines = line_protocol(&transform_aprs(msg, received));
+        assert_eq!(lines.len(), 2);
+
+        let fix = &lines[0];
+        assert!(fix.starts_with("aprs_fix,"));
+        assert!(fix.contains(",address=3E6DBA,"));
+        let tags = fix.split(' ').next().unwrap();
+        assert!(!tags.contains("aprs_path="));
+        assert!(fix.contains("aprs_path=\"APRS,qAS,Schwend\""));
+        assert!(fix.contains("bit_errors=6"));
+        assert!(fix.contains("frequency_offset=-4.5"));
+        assert!(fix.contains("gps_resolution_horizontal=2"));
+        assert!(!fix.contains("message="));
+        assert!(fix.ends_with(&format!(" {}\n", nanos(Utc.with_ymd_and_hms(2023, 2, 19, 11, 24, 37).unwrap()))));
+
+        let raw = &lines[1];
+        assert!(raw.starts_with("aprs_raw,parsed=true,schema_version=2 message="));
+        assert!(raw.ends_with(&format!(" {}\n", nanos(received))));
+    }
+
+    #[test]
+    fn unparsed_message_is_only_raw() {
+        let lines = line_protocol(&transform_aprs("# aprsc 2.1.5-g8af3cdc", Utc::now()));
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("aprs_raw,parsed=false,"));
+    }
+
+    #[test]
+    fn receive_time_is_strictly_increasing() {
+        let first = receive_time();
+        let second = receive_time();
+        assert!(second > first);
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{interval, MissedTickBehavior};
+
+use tracing::{error, info, warn};
+
+use ogn_aprs_parser::model::ogn_receiver_beacon::OGNReceiverBeacon;
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+use ogn_fleet_state::{FleetConfig, FleetState};
+
+use crate::clock::Clock;
+use crate::ddb::SharedRegistry;
+use crate::metrics::{SINK_ERRORS, SINK_WRITE_SECONDS};
+use crate::record::Packet;
+
+// The live state of the fleet as seen by the scraper, for the http api. Kept up to date by the
+// `fleet` sink and shared with the api handlers.
+pub type SharedFleet = Arc<RwLock<LiveFleet>>;
+
+pub struct LiveFleetConfig {
+    pub fleet: FleetConfig,
+    // the state is saved here every `save_interval` and when the sink closes, and restored on start.
+    pub snapshot: Option<PathBuf>,
+    pub save_interval: Duration,
+}
+
+// a receiver, known from the aircraft positions it relayed and from its own position beacons.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReceiverInfo {
+    pub name: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<f64>,
+    pub last_heard: DateTime<Utc>,
+    // aircraft positions relayed since the scraper started.
+    pub positions: u64,
+}
+
+// positions buffered for each streaming client, see `api::stream`.
+pub const UPDATES_BUFFER: usize = 1024;
+
+pub struct LiveFleet {
+    pub fleet: FleetState,
+    pub receivers: HashMap<String, ReceiverInfo>,
+    // every aircraft position applied to the fleet, for live streams.
+    pub updates: broadcast::Sender<OGNStatusMessage>,
+    // aircraft whose owners opted out of tracking, in the ogn ddb or with their device's no-tracking
+    // flag, are never applied, so none of the outputs reading the fleet show them.
+    pub registry: SharedRegistry,
+    // what the outputs reading the fleet take as now, see `Clock`.
+    pub clock: Clock,
+}
+
+impl LiveFleet {
+    pub fn new(fleet: FleetState) -> Self {
+        LiveFleet {
+            fleet,
+            receivers: HashMap::new(),
+            updates: broadcast::channel(UPDATES_BUFFER).0,
+            registry: SharedRegistry::default(),
+            clock: Clock::default(),
+        }
+    }
+
+    pub fn update(&mut self, msg: OGNStatusMessage, received: DateTime<Utc>) {
+        if !self.registry.read().unwrap().may_track(&msg) {
+            return;
+        }
+        if let Some(name) = receiver_name(&msg.aprs_path) {
+            self.receiver(name, received).positions += 1;
+        }
+        let update = (self.updates.receiver_count() > 0).then(|| msg.clone());
+        if self.fleet.update(msg, received) {
+            if let Some(update) = update {
+                self.updates.send(update).ok();
+            }
+        }
+    }
+
+    // receivers send their own position to `OGNSDR`, e.g.
+    //   Bad_Rappe>OGNSDR,TCPIP*,qAC,GLIDERN2:/154005h4858.00NI00820.00E&/A=000500
+    pub fn update_receiver(&mut self, beacon: OGNReceiverBeacon, received: DateTime<Utc>) {
+        let receiver = self.receiver(&beacon.aprs_callsign, received);
+        receiver.latitude = Some(beacon.position.latitude as f64);
+        receiver.longitude = Some(beacon.position.longitude as f64);
+        receiver.altitude = Some(beacon.position.altitude as f64);
+    }
+
+    fn receiver(&mut self, name: &str, received: DateTime<Utc>) -> &mut ReceiverInfo {
+        let receiver = self.receivers.entry(name.to_owned()).or_insert_with(|| ReceiverInfo {
+            name: name.to_owned(),
+            latitude: None,
+            longitude: None,
+            altitude: None,
+            last_heard: received,
+            positions: 0,
+        });
+        receiver.last_heard = receiver.last_heard.max(received);
+        receiver
+    }
+
+    // forget aircraft and receivers not heard for the fleet's `expire_after`.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> usize {
+        let expire_after = self.fleet.config().expire_after;
+        self.receivers.retain(|_, receiver| now - receiver.last_heard <= expire_after);
+        self.fleet.expire(now)
+    }
+}
+
+// where the live outputs see the traffic from: a fixed position, e.g. the clubhouse, or a tracked
+// aircraft, e.g. the tow plane.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ownship {
+    Fixed { latitude: f64, longitude: f64, altitude: f64 },
+    Aircraft(String),
+}
+
+// the ownship as of one update.
+pub struct OwnPosition<'a> {
+    pub address: Option<&'a str>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+    pub track: f64,
+    // m/s, like the parsed messages.
+    pub speed: f64,
+    pub climb: Option<f64>,
+}
+
+impl Ownship {
+    // `<latitude>,<longitude>,<altitude in m>` or the address of an aircraft, e.g. `DF153A`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        match spec.split(',').collect::<Vec<_>>()[..] {
+            [latitude, longitude, altitude] => Some(Ownship::Fixed {
+                latitude: latitude.trim().parse().ok()?,
+                longitude: longitude.trim().parse().ok()?,
+                altitude: altitude.trim().parse().ok()?,
+            }),
+            [address] if !address.is_empty() => Some(Ownship::Aircraft(address.to_owned())),
+            _ => None,
+        }
+    }
+
+    // None while the ownship aircraft isn't heard.
+    pub fn position<'a>(&'a self, fleet: &FleetState, now: DateTime<Utc>) -> Option<OwnPosition<'a>> {
+        match self {
+            Ownship::Fixed { latitude, longitude, altitude } => Some(OwnPosition {
+                address: None,
+                latitude: *latitude,
+                longitude: *longitude,
+                altitude: *altitude,
+                track: 0.0,
+                speed: 0.0,
+                climb: None,
+            }),
+            Ownship::Aircraft(address) => {
+                let aircraft = fleet.get(address).filter(|aircraft| !fleet.is_stale(aircraft, now))?;
+                Some(OwnPosition {
+                    address: Some(address),
+                    latitude: aircraft.latitude(),
+                    longitude: aircraft.longitude(),
+                    altitude: aircraft.last.position.altitude as f64,
+                    track: aircraft.last.position.heading as f64,
+                    speed: aircraft.last.velocity.horizontal as f64,
+                    climb: aircraft.last.velocity.vertical.map(|climb| climb as f64),
+                })
+            }
+        }
+    }
+}
+
+// the receiver that heard a message is the last hop of its path, after the q construct,
+// e.g. `Schwend` in `APRS,qAS,Schwend`.
+pub fn receiver_name(path: &str) -> Option<&str> {
+    let mut hops = path.split(',').skip_while(|hop| !hop.starts_with('q'));
+    hops.next()?;
+    hops.last()
+}
+
+// restore the last snapshot, if any, or start with an empty fleet.
+pub fn restore(config: &LiveFleetConfig, registry: SharedRegistry, clock: Clock) -> SharedFleet {
+    let state = match &config.snapshot {
+        Some(path) if path.exists() => match FleetState::load(path, config.fleet.clone(), clock.now()) {
+            Ok(state) => {
+                info!("restored {} aircraft from {:?}", state.len(), path);
+                state
+            }
+            Err(err) => {
+                warn!("ignoring fleet snapshot {:?}: {:?}", path, err);
+                FleetState::new(config.fleet.clone())
+            }
+        },
+        _ => FleetState::new(config.fleet.clone()),
+    };
+    let mut live = LiveFleet::new(state);
+    live.registry = registry;
+    live.clock = clock;
+    Arc::new(RwLock::new(live))
+}
+
+pub async fn write_aprs(fleet: SharedFleet, config: LiveFleetConfig, mut rx: Receiver<Packet>) {
+    let mut tick = interval(config.save_interval);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            packet = rx.recv() => {
+                let Some(packet) = packet else { break };
+                let Some(parsed) = packet.position else {
+                    // receiver beacons aren't aircraft positions, so they're left unparsed.
+                    if let Ok(beacon) = OGNReceiverBeacon::from_str(&packet.line, Some(packet.received)) {
+                        fleet.write().unwrap().update_receiver(beacon, packet.received);
+                    }
+                    continue;
+                };
+                let _timer = SINK_WRITE_SECONDS.with_label_values(&["fleet"]).start_timer();
+                fleet.write().unwrap().update(parsed, packet.received);
+            }
+            _ = tick.tick() => {
+                let mut live = fleet.write().unwrap();
+                let now = live.clock.now();
+                live.expire(now);
+                drop(live);
+                save(&fleet, &config);
+            }
+        }
+    }
+
+    save(&fleet, &config);
+    // all sources are done or were stopped for shutdown, and everything they read is applied.
+    info!("closed fleet sink");
+}
+
+fn save(fleet: &SharedFleet, config: &LiveFleetConfig) {
+    let Some(path) = &config.snapshot else { return };
+    let live = fleet.read().unwrap();
+    if let Err(err) = live.fleet.save(path, live.clock.now()) {
+        error!("error saving fleet snapshot: {:?}", err);
+        SINK_ERRORS.with_label_values(&["fleet"]).inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    use crate::ddb::Registry;
+
+    fn date() -> Option<DateTime<Utc>> {
+        Some(Utc.with_ymd_and_hms(2023, 2, 19, 0, 0, 0).unwrap())
+    }
+
+    fn parse(line: &str) -> OGNStatusMessage {
+        OGNStatusMessage::from_str(line, date()).unwrap()
+    }
+
+    #[test]
+    fn tracks_receivers_apart_from_aircraft() {
+        let received = Utc.with_ymd_and_hms(2023, 2, 19, 15, 40, 6).unwrap();
+        let mut live = LiveFleet::new(FleetState::new(FleetConfig::default()));
+        let beacon = "Bad_Rappe>OGNSDR,TCPIP*,qAC,GLIDERN2:/154005h4858.00NI00820.00E&/A=000500";
+        live.update_receiver(OGNReceiverBeacon::from_str(beacon, date()).unwrap(), received);
+        live.update(
+            parse("FLRDF153A>APRS,qAS,Bad_Rappe:/154006h4858.10N\\00820.89E^301/050/A=001145 !W71! id22DF153A"),
+            received,
+        );
+
+        assert_eq!(live.fleet.len(), 1);
+        assert!(live.fleet.get("DF153A").is_some());
+        let receiver = &live.receivers["Bad_Rappe"];
+        assert_eq!(receiver.positions, 1);
+        assert!((receiver.latitude.unwrap() - 48.9667).abs() < 0.001);
+        assert_eq!(receiver_name("APRS,qAS,Schwend"), Some("Schwend"));
+        assert_eq!(receiver_name("APRS"), None);
+    }
+
+    #[test]
+    fn only_applied_positions_are_streamed() {
+        let received = Utc.with_ymd_and_hms(2023, 2, 19, 15, 40, 21).unwrap();
+        let mut live = LiveFleet::new(FleetState::new(FleetConfig::default()));
+        let mut updates = live.updates.subscribe();
+        live.update(parse("FLRDF153A>APRS,qAS,RHST:/154020h4858.30N\\00821.10E^301/050/A=001145 !W71! id22DF153A"), received);
+        // late, from another receiver, the fleet keeps the newer position.
+        live.update(parse("FLRDF153A>APRS,qAS,RHST:/154008h4858.15N\\00820.95E^301/050/A=001145 !W71! id22DF153A"), received);
+
+        assert_eq!(updates.try_recv().unwrap().timestamp, Utc.with_ymd_and_hms(2023, 2, 19, 15, 40, 20).unwrap());
+        assert!(updates.try_recv().is_err());
+    }
+
+    #[test]
+    fn leaves_out_aircraft_not_to_be_tracked() {
+        let received = Utc.with_ymd_and_hms(2023, 2, 19, 15, 40, 6).unwrap();
+        let mut live = LiveFleet::new(FleetState::new(FleetConfig::default()));
+        let json = br#"{"devices":[{"device_id":"DD9999","tracked":"N","identified":"N"}]}"#;
+        *live.registry.write().unwrap() = Registry::from_json(json).unwrap();
+        let mut updates = live.updates.subscribe();
+        live.update(
+            parse("FLRDD9999>APRS,qAS,Bad_Rappe:/154006h4858.54N\\00820.00E'180/097/A=001640 !W00! id06DD9999"),
+            received,
+        );
+        live.update(
+            parse("FLRDD8888>APRS,qAS,Bad_Rappe:/154006h4858.54N\\00820.00E'180/097/A=001640 !W00! id46DD8888"),
+            received,
+        );
+        live.update(
+            parse("FLRDF153A>APRS,qAS,Bad_Rappe:/154006h4858.10N\\00820.89E^301/050/A=001145 !W71! id22DF153A"),
+            received,
+        );
+
+        assert_eq!(live.fleet.len(), 1);
+        assert!(live.fleet.get("DD9999").is_none() && live.fleet.get("DD8888").is_none());
+        assert_eq!(updates.try_recv().unwrap().aircraft_id.as_deref(), Some("DF153A"));
+        assert_eq!(live.receivers["Bad_Rappe"].positions, 1);
+    }
+}
@@ -0,0 +1,379 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::time::{interval, MissedTickBehavior};
+
+use influxdb2::models::DataPoint;
+use influxdb2::Client;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::influx::InfluxConnection;
+use crate::metrics::LOG_DROPPED_RECORDS;
+
+// records waiting for the background writer; more are dropped rather than slowing down the caller.
+const CHANNEL_SIZE: usize = 4096;
+
+// Where log records are shipped to, besides the console.
+pub enum LogTarget {
+    // the `log` measurement of a bucket, with its own client so logs about a failing influx
+    // don't queue up behind the failing writes.
+    Influx { client: Client, bucket: String },
+    // one JSON object per line on stdout, instead of the human readable console output.
+    StdoutJson,
+    // one JSON object per line, appended to a file.
+    File(PathBuf),
+}
+
+impl LogTarget {
+    // `influx`, `json`, `file:<path>` or `none`.
+    pub fn parse(spec: &str, influx: &InfluxConnection, bucket: &str) -> Result<Option<Self>, String> {
+        Ok(Some(match spec {
+            "influx" => LogTarget::Influx {
+                client: Client::new(&influx.url, &influx.org, &influx.token),
+                bucket: bucket.to_owned(),
+            },
+            "json" => LogTarget::StdoutJson,
+            "none" => return Ok(None),
+            _ => match spec.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => LogTarget::File(path.into()),
+                _ => return Err(format!("invalid log sink <{}>", spec)),
+            },
+        }))
+    }
+}
+
+pub struct LogSinkConfig {
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+}
+
+// A log event, with the fields of the spans it happened in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub time: DateTime<Utc>,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    pub fields: Map<String, Value>,
+}
+
+impl LogRecord {
+    fn to_json(&self) -> Value {
+        serde_json::json!({
+            "time": self.time.to_rfc3339_opts(SecondsFormat::Micros, true),
+            "level": self.level.as_str(),
+            "target": self.target,
+            "message": self.message,
+            "fields": self.fields,
+        })
+    }
+
+    fn to_point(&self) -> Result<DataPoint, Box<dyn Error>> {
+        let mut builder = DataPoint::builder("log")
+            .timestamp(self.time.timestamp_nanos_opt().unwrap_or_default())
+            .tag("type", self.level.as_str())
+            .tag("target", &self.target)
+            .field("content", self.message.clone());
+        if !self.fields.is_empty() {
+            builder = builder.field("fields", Value::Object(self.fields.clone()).to_string());
+        }
+        Ok(builder.build()?)
+    }
+}
+
+pub enum Command {
+    Record(LogRecord),
+    // write everything received so far, then answer.
+    Flush(oneshot::Sender<()>),
+}
+
+// Flushes the log sink, e.g. before the program exits.
+#[derive(Clone)]
+pub struct LogSinkHandle(Option<Sender<Command>>);
+
+impl LogSinkHandle {
+    pub async fn flush(&self) {
+        if let Some(tx) = &self.0 {
+            let (done_tx, done_rx) = oneshot::channel();
+            if tx.send(Command::Flush(done_tx)).await.is_ok() {
+                done_rx.await.ok();
+            }
+        }
+    }
+}
+
+// Install the global subscriber: human readable console output (unless logs go to stdout as JSON)
+// and the background sink for `target`, both limited by `filter`, e.g. `info,akaflieg=debug`.
+// Records of the `log` crate, as used by the libraries, are forwarded as well.
+pub fn init(filter: &str, target: Option<LogTarget>, config: LogSinkConfig) -> Result<LogSinkHandle, Box<dyn Error>> {
+    let filter = EnvFilter::try_new(filter)?;
+    let console = match target {
+        Some(LogTarget::StdoutJson) => None,
+        _ => Some(tracing_subscriber::fmt::layer()),
+    };
+    let (sink, tx) = match target {
+        Some(target) => {
+            let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+            tokio::spawn(run(target, config, rx));
+            (Some(LogSinkLayer { tx: tx.clone() }), Some(tx))
+        }
+        None => (None, None),
+    };
+    tracing_subscriber::registry().with(filter).with(console).with(sink).try_init()?;
+    Ok(LogSinkHandle(tx))
+}
+
+// Turns events into `LogRecord`s for the background writer. This never blocks and doesn't need
+// a runtime: when the channel is full, records are dropped and counted.
+pub struct LogSinkLayer {
+    pub tx: Sender<Command>,
+}
+
+// the fields of a span, as recorded so far.
+struct SpanFields(Map<String, Value>);
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for LogSinkLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+                values.record(&mut JsonVisitor(&mut fields.0));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        // inner spans and the event itself override fields of the same name.
+        let mut fields = Map::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<SpanFields>() {
+                    fields.extend(span_fields.0.clone());
+                }
+            }
+        }
+        event.record(&mut JsonVisitor(&mut fields));
+
+        // events from the `log` crate carry their real metadata in `log.*` fields.
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        fields.retain(|name, _| !name.starts_with("log."));
+        let message = match fields.remove("message") {
+            Some(Value::String(message)) => message,
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+
+        let record = LogRecord {
+            time: Utc::now(),
+            level: *metadata.level(),
+            target: metadata.target().to_owned(),
+            message,
+            fields,
+        };
+        if self.tx.try_send(Command::Record(record)).is_err() {
+            LOG_DROPPED_RECORDS.inc();
+        }
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_owned(), format!("{:?}", value).into());
+    }
+}
+
+// write records in batches, whenever the batch is full or the timer fires.
+pub async fn run(target: LogTarget, config: LogSinkConfig, mut rx: Receiver<Command>) {
+    let mut flush_timer = interval(config.flush_interval);
+    flush_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut writer = LogWriter::new(target);
+    let mut batch = Vec::with_capacity(config.batch_size);
+    loop {
+        let mut done = None;
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(Command::Record(record)) => {
+                    batch.push(record);
+                    if batch.len() < config.batch_size {
+                        continue;
+                    }
+                }
+                Some(Command::Flush(done_tx)) => done = Some(done_tx),
+                None => break,
+            },
+            _ = flush_timer.tick() => {}
+        }
+
+        writer.write(std::mem::take(&mut batch)).await;
+        if let Some(done) = done {
+            done.send(()).ok();
+        }
+    }
+    writer.write(batch).await;
+}
+
+// Errors of the log sink itself go to stderr: logging them would only add to the records
+// that can't be written.
+struct LogWriter {
+    target: LogTarget,
+    file: Option<BufWriter<File>>,
+}
+
+impl LogWriter {
+    fn new(target: LogTarget) -> Self {
+        LogWriter { target, file: None }
+    }
+
+    async fn write(&mut self, batch: Vec<LogRecord>) {
+        if batch.is_empty() {
+            return;
+        }
+        let result = match &self.target {
+            LogTarget::Influx { client, bucket } => {
+                let points: Vec<DataPoint> = batch.iter().filter_map(|record| record.to_point().ok()).collect();
+                client.write(bucket, futures::stream::iter(points)).await.map_err(Into::into)
+            }
+            LogTarget::StdoutJson => write_json(&mut io::stdout().lock(), &batch).map_err(Into::into),
+            LogTarget::File(path) => {
+                let file = match self.file.take() {
+                    Some(file) => Ok(file),
+                    None => OpenOptions::new().create(true).append(true).open(path).map(BufWriter::new),
+                };
+                file.and_then(|mut file| {
+                    write_json(&mut file, &batch)?;
+                    // keep the file open only while it works, reopen it with the next batch otherwise.
+                    self.file = Some(file);
+                    Ok(())
+                })
+                .map_err(Into::into)
+            }
+        };
+        if let Err(err) = result {
+            let err: Box<dyn Error> = err;
+            eprintln!("error writing {} log records, dropping them: {:?}", batch.len(), err);
+            LOG_DROPPED_RECORDS.inc_by(batch.len() as u64);
+        }
+    }
+}
+
+fn write_json(out: &mut impl Write, batch: &[LogRecord]) -> io::Result<()> {
+    for record in batch {
+        writeln!(out, "{}", record.to_json())?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tracing::{info, info_span};
+    use tracing_subscriber::Registry;
+
+    // collect the records of everything logged in `f`.
+    fn capture(capacity: usize, f: impl FnOnce()) -> Vec<LogRecord> {
+        let (tx, mut rx) = mpsc::channel(capacity);
+        let subscriber = Registry::default().with(LogSinkLayer { tx });
+        tracing::subscriber::with_default(subscriber, f);
+        let mut records = Vec::new();
+        while let Ok(Command::Record(record)) = rx.try_recv() {
+            records.push(record);
+        }
+        records
+    }
+
+    #[test]
+    fn records_carry_span_fields() {
+        let records = capture(16, || {
+            let span = info_span!("connection", source = "glidernet", attempt = 1);
+            let _entered = span.enter();
+            info!(lines = 3, "read {} lines", 3);
+        });
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, "read 3 lines");
+        assert_eq!(records[0].level, Level::INFO);
+        assert_eq!(records[0].fields["source"], "glidernet");
+        assert_eq!(records[0].fields["attempt"], 1);
+        assert_eq!(records[0].fields["lines"], 3);
+    }
+
+    #[test]
+    fn records_are_dropped_when_the_channel_is_full() {
+        let dropped = LOG_DROPPED_RECORDS.get();
+        let records = capture(2, || (0..5).for_each(|i| info!("line {}", i)));
+        assert_eq!(records.len(), 2);
+        assert!(LOG_DROPPED_RECORDS.get() >= dropped + 3);
+    }
+
+    #[tokio::test]
+    async fn file_target_appends_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scraper.log");
+        let (tx, rx) = mpsc::channel(16);
+        let config = LogSinkConfig { batch_size: 100, flush_interval: Duration::from_secs(3600) };
+        let task = tokio::spawn(run(LogTarget::File(path.clone()), config, rx));
+
+        let handle = LogSinkHandle(Some(tx.clone()));
+        let records = capture(16, || info!(sink = "influx", "written"));
+        for record in records {
+            tx.send(Command::Record(record)).await.unwrap();
+        }
+        handle.flush().await;
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let json: Value = serde_json::from_str(written.lines().next().unwrap()).unwrap();
+        assert_eq!(json["message"], "written");
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["fields"]["sink"], "influx");
+
+        drop((tx, handle));
+        task.await.unwrap();
+    }
+}
@@ -1,18 +1,47 @@
 use std::sync::Arc;
 use std::time::Duration;
 use std::error::Error;
-use std::{io, str};
 
-use log::{info, warn, error, debug};
+use tracing::{error, info, info_span, Instrument};
 
-use tokio::net::TcpStream;
-use tokio::time::timeout;
-use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 use influxdb2::Client;
 
-mod influx_logger;
+use crate::record::Packet;
+
+mod adsb;
+mod api;
+mod aprs_server;
+mod archive;
+mod backpressure;
+mod aprs_log;
+mod cli;
+mod clock;
+mod cot;
+mod ddb;
+mod dedup;
+mod emitter;
+mod fanout;
+mod flarm;
+mod gdl90;
 mod influx;
+mod influx_schema;
+mod live;
+mod log_sink;
+mod metrics;
+mod migrate;
+mod mqtt;
+mod postgres;
+mod record;
+mod readsb;
+mod replay;
+mod reprocess;
+mod sbs;
+mod shutdown;
+mod source;
+mod spool;
 mod util;
 
 #[tokio::main]
@@ -20,112 +49,337 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // load environment variables from .env file.
     dotenv::dotenv().ok();
+
+    // `healthcheck` asks a running scraper for its health, for the docker HEALTHCHECK.
+    if std::env::args().nth(1).is_some_and(|command| command == "healthcheck") {
+        let addr = dotenv::var("METRICS_ADDR")?.replace("0.0.0.0", "127.0.0.1");
+        let response = reqwest::get(format!("http://{}/healthz", addr)).await?;
+        let status = response.status();
+        println!("{}", response.text().await?.trim_end());
+        return if status.is_success() { Ok(()) } else { Err(format!("unhealthy: {}", status).into()) };
+    }
+
     let url = dotenv::var("INFLUX_URL")?;
     let org = dotenv::var("INFLUX_ORG")?;
     let token = dotenv::var("INFLUX_TOKEN")?;
 
-    let aprs_addr = dotenv::var("APRS_ADDR")?;
-    let aprs_login_str = dotenv::var("APRS_LOGIN_STR")?;
-
+    // create the InfluxDB client.
+    let client = Arc::new(Client::new(&url, &org, &token));
+    let influx = influx::InfluxConnection { url, org, token };
 
-    // create and init InfluxDB client, and setup the logger.
-    let client = Arc::new(Client::new(url, org, token));
-    influx_logger::InfluxLogger::init(client.clone(), "logs");
+    // setup logging: `LOG_FILTER` picks what is logged, e.g. `info,akaflieg_ogn_aprs_scraper=debug`,
+    // and `LOG_SINK` where it is shipped besides the console, see `log_sink::LogTarget`.
+    let log_target = log_sink::LogTarget::parse(
+        &dotenv::var("LOG_SINK").unwrap_or_else(|_| "influx".to_owned()),
+        &influx,
+        &dotenv::var("LOG_BUCKET").unwrap_or_else(|_| "logs".to_owned()),
+    )?;
+    let log_config = log_sink::LogSinkConfig {
+        batch_size: dotenv::var("LOG_BATCH_SIZE").map_or(Ok(100), |s| s.parse())?,
+        flush_interval: Duration::from_secs(dotenv::var("LOG_FLUSH_SECS").map_or(Ok(5), |s| s.parse())?),
+    };
+    let logs = log_sink::init(&dotenv::var("LOG_FILTER").unwrap_or_else(|_| "info".to_owned()), log_target, log_config)?;
 
-    // setup the return channel for APRS messages from the TCP stream;
-    // write all arriving messages to influx.
-    let (tx, rx) = mpsc::channel::<String>(32);
-    tokio::spawn(influx::write_aprs(client.clone(), "aprs", rx));
-
-    // Main connection loop
-    loop {
-        // connect to APRS server until we get disconnected, then loop.
-        let result = connect(&aprs_addr, &aprs_login_str, &tx).await;
-        // see if we have any unusual errors apart from timeout disconnects.
-        if let Err(err) = result {
-            error!("{:?}", err);
+    // maintenance commands run instead of the scraper.
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("migrate-schema") | Some("reprocess") => {
+            let result = match args[1].as_str() {
+                "migrate-schema" => migrate::run(&client, &influx, "aprs", &args[2..]).await,
+                _ => reprocess::run(&client, &influx, "aprs", &args[2..]).await,
+            };
+            logs.flush().await;
+            return result;
         }
+        // replaying recorded logs runs the scraper with the log as its input.
+        Some("replay") => {}
+        Some(command) => return Err(format!("unknown command: {}", command).into()),
+        None => {}
+    }
+
+    // serve prometheus metrics and the health check, if requested. The scraper is unhealthy once
+    // no line arrived from any source for `HEALTH_MAX_PACKET_AGE_SECS`.
+    if let Ok(metrics_addr) = dotenv::var("METRICS_ADDR") {
+        let health = metrics::Health {
+            started: chrono::Utc::now(),
+            max_packet_age: Duration::from_secs(
+                dotenv::var("HEALTH_MAX_PACKET_AGE_SECS").map_or(Ok(300), |s| s.parse())?,
+            ),
+        };
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(metrics_addr, health).await {
+                error!("error serving metrics: {:?}", err);
+            }
+        });
     }
-}
 
-// Connect to APRS server, log all APRS messages to Influx
-async fn connect(addr: &str, login_str: &str, aprs_tx: &Sender<String>) -> Result<(), Box<dyn Error>> {
+    // on SIGTERM or SIGINT the sources stop, and the sinks get `SHUTDOWN_TIMEOUT_SECS` to write out
+    // everything that was read; the exit status tells whether they made it.
+    let shutdown = CancellationToken::new();
+    let shutdown_timeout = Duration::from_secs(dotenv::var("SHUTDOWN_TIMEOUT_SECS").map_or(Ok(8), |s| s.parse())?);
+    tokio::spawn(shutdown::watch_signals(shutdown.clone()));
 
-    // establish a TCP connection to the APRS server.
-    let mut stream = TcpStream::connect(addr).await?;
-    info!("connected to {:?}", addr);
+    // setup the return channel for APRS messages from the TCP stream;
+    // write all arriving messages to influx, and to any other configured sinks.
+    let (tx, rx) = mpsc::channel::<Packet>(32);
+    let influx_config = influx::InfluxWriterConfig {
+        org: influx.org.clone(),
+        bucket: "aprs".to_owned(),
+        batch_size: dotenv::var("INFLUX_BATCH_SIZE").map_or(Ok(500), |s| s.parse())?,
+        flush_interval: Duration::from_secs(
+            dotenv::var("INFLUX_FLUSH_SECS").map_or(Ok(1), |s| s.parse())?,
+        ),
+        max_retries: dotenv::var("INFLUX_MAX_RETRIES").map_or(Ok(3), |s| s.parse())?,
+        spool_dir: dotenv::var("INFLUX_SPOOL_DIR").unwrap_or_else(|_| "spool".to_owned()).into(),
+        spool_max_bytes: dotenv::var("INFLUX_SPOOL_MAX_BYTES").map_or(Ok(100 << 20), |s| s.parse())?,
+    };
+    // every sink gets a queue of `SINK_QUEUE_SIZE` packets. What happens once a slow sink filled it
+    // is up to `BACKPRESSURE_POLICY`, see `backpressure::Policy`.
+    let policy = dotenv::var("BACKPRESSURE_POLICY").unwrap_or_else(|_| "spill".to_owned());
+    let queue_config = backpressure::QueueConfig {
+        capacity: dotenv::var("SINK_QUEUE_SIZE").map_or(Ok(10_000), |s| s.parse())?,
+        policy: backpressure::Policy::from_name(&policy)
+            .ok_or(format!("invalid BACKPRESSURE_POLICY: {}", policy))?,
+        spill_dir: dotenv::var("SPILL_DIR").unwrap_or_else(|_| "spill".to_owned()).into(),
+        spill_max_bytes: dotenv::var("SPILL_MAX_BYTES").map_or(Ok(500 << 20), |s| s.parse())?,
+    };
 
-    let login_str = format!("{}\r\n", login_str);
-    let login_buf: &[u8] = login_str.as_bytes();
+    // the time the live outputs go by, moved along by the replay source while replaying a log.
+    let clock = clock::Clock::default();
 
-    // connection to the APRS server requires authenticating as an anonymous read-only user
-    // the login string is passed as an environment variable.
-    // see: http://wiki.glidernet.org/aprs-interaction-examples
-    // use -1 as password for anonymous connection.
-    write(&mut stream, login_buf).await?;
+    // the live fleet is only kept if something reads it: the json api, or one of the live outputs.
+    let live_fleet = ["API_ADDR", "FLARM_ADDR", "GDL90_ADDR", "COT_ADDR", "SBS_ADDR"].iter().any(|var| dotenv::var(var).is_ok());
+    // the outputs that show individual aircraft leave out those whose owners opted out of tracking
+    // in the ogn ddb; cot also names aircraft after their registration there.
+    let registry = ddb::SharedRegistry::default();
+    if live_fleet || dotenv::var("MQTT_HOST").is_ok() {
+        let ddb_url = dotenv::var("DDB_URL").unwrap_or_else(|_| ddb::DEFAULT_URL.to_owned());
+        tokio::spawn(ddb::refresh(ddb_url, registry.clone(), Duration::from_secs(6 * 3600)));
+    }
 
-    // we've authenticated, now enter a reading loop until we get a timeout
-    read(&stream, aprs_tx).await?;
+    let mut tasks = Vec::new();
+    let (influx_tx, influx_rx) = backpressure::sink_channel("influx", &queue_config);
+    tasks.push(tokio::spawn(influx::write_aprs(client.clone(), influx_config, influx_rx).instrument(info_span!("sink", sink = "influx"))));
+    let mut sinks = vec![influx_tx];
 
-    warn!("disconnected (server timeout or empty message)");
+    // the postgres sink is optional, and only enabled if a database url is given.
+    if let Ok(postgres_url) = dotenv::var("POSTGRES_URL") {
+        let config = postgres::PostgresConfig {
+            url: postgres_url,
+            batch_size: dotenv::var("POSTGRES_BATCH_SIZE").map_or(Ok(500), |s| s.parse())?,
+            flush_interval: Duration::from_secs(
+                dotenv::var("POSTGRES_FLUSH_SECS").map_or(Ok(5), |s| s.parse())?,
+            ),
+        };
+        let (postgres_tx, postgres_rx) = backpressure::sink_channel("postgres", &queue_config);
+        tasks.push(tokio::spawn(postgres::write_aprs(config, postgres_rx).instrument(info_span!("sink", sink = "postgres"))));
+        sinks.push(postgres_tx);
+    }
 
-    Ok(())
-}
+    // the mqtt sink is optional, and only enabled if a broker is given.
+    if let Ok(mqtt_host) = dotenv::var("MQTT_HOST") {
+        let qos = dotenv::var("MQTT_QOS").map_or(Ok(0), |s| s.parse())?;
+        let config = mqtt::MqttConfig {
+            host: mqtt_host,
+            port: dotenv::var("MQTT_PORT").map_or(Ok(1883), |s| s.parse())?,
+            client_id: dotenv::var("MQTT_CLIENT_ID")
+                .unwrap_or_else(|_| "akaflieg-ogn-aprs-scraper".to_owned()),
+            credentials: match (dotenv::var("MQTT_USER"), dotenv::var("MQTT_PASSWORD")) {
+                (Ok(user), Ok(password)) => Some((user, password)),
+                _ => None,
+            },
+            topic_prefix: dotenv::var("MQTT_TOPIC_PREFIX").unwrap_or_else(|_| "ogn".to_owned()),
+            qos: mqtt::qos_from(qos).ok_or(format!("invalid MQTT_QOS: {}", qos))?,
+            reconnect_delay: Duration::from_secs(5),
+        };
+        let (mqtt_tx, mqtt_rx) = backpressure::sink_channel("mqtt", &queue_config);
+        tasks.push(tokio::spawn(mqtt::write_aprs(config, registry.clone(), mqtt_rx).instrument(info_span!("sink", sink = "mqtt"))));
+        sinks.push(mqtt_tx);
+    }
 
-// loop while reading all incoming APRS messages
-// returns Ok(()) on timeout, as this is expected behaviour and should only cause a reconnect.
-async fn read(stream: &TcpStream, aprs_tx: &Sender<String>) -> Result<(), Box<dyn Error>> {
-    loop {
-        // according to spec, we should assume a timeout and reconnect,
-        // if we do not receive any new messages in one minute.
-        timeout(Duration::from_secs(60), stream.readable()).await??;
-
-        // use a large enough buffer to fit all expected APRS messages
-        let mut buf = [0; 512];
-
-        match stream.try_read(&mut buf) {
-            // we've gotten an empty message or a timeout, reconnect.
-            Ok(0) => break,
-            // we've received a message of n characters, log it to InfluxDB
-            Ok(n) => {
-                let string_rep = util::format_for_display(&buf);
-                debug!("(read {}) {}", n, string_rep);
-                aprs_tx.send(string_rep).await?;
-            }
-            // we're not ready to read yet, wait another loop until we can read the next message.
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                continue;
-            }
-            // we've received another kind of error, return the error and reconnect.
-            Err(e) => {
-                return Err(e.into());
+    // the raw feed archive is optional, and only enabled if a directory is given.
+    if let Ok(archive_dir) = dotenv::var("ARCHIVE_DIR") {
+        let compression = dotenv::var("ARCHIVE_COMPRESSION").unwrap_or_else(|_| "zstd".to_owned());
+        let config = archive::ArchiveConfig {
+            dir: archive_dir.into(),
+            compression: archive::Compression::from_name(&compression)
+                .ok_or(format!("invalid ARCHIVE_COMPRESSION: {}", compression))?,
+            retention_days: dotenv::var("ARCHIVE_RETENTION_DAYS").ok().map(|s| s.parse()).transpose()?,
+            flush_interval: Duration::from_secs(10),
+        };
+        let (archive_tx, archive_rx) = backpressure::sink_channel("archive", &queue_config);
+        tasks.push(tokio::spawn(archive::write_aprs(config, archive_rx).instrument(info_span!("sink", sink = "archive"))));
+        sinks.push(archive_tx);
+    }
+
+    // a local APRS-IS server, so other tools can share our upstream connections with their own filters.
+    if let Ok(aprs_server_addr) = dotenv::var("APRS_SERVER_ADDR") {
+        let config = aprs_server::AprsServerConfig {
+            addr: aprs_server_addr,
+            server_name: dotenv::var("APRS_SERVER_NAME").unwrap_or_else(|_| "SCRAPER".to_owned()),
+            keepalive_interval: Duration::from_secs(20),
+        };
+        let lines = aprs_server::channel();
+        let server_lines = lines.clone();
+        tokio::spawn(async move {
+            if let Err(err) = aprs_server::serve(config, server_lines).await {
+                error!("error serving aprs-is: {:?}", err);
             }
+        });
+        let (aprs_server_tx, aprs_server_rx) = backpressure::sink_channel("aprs-server", &queue_config);
+        tasks.push(tokio::spawn(aprs_server::write_aprs(lines, aprs_server_rx).instrument(info_span!("sink", sink = "aprs-server"))));
+        sinks.push(aprs_server_tx);
+    }
+
+    if live_fleet {
+        let config = live::LiveFleetConfig {
+            fleet: ogn_fleet_state::FleetConfig {
+                history_len: dotenv::var("FLEET_HISTORY_LEN").map_or(Ok(1000), |s| s.parse())?,
+                stale_after: chrono::Duration::seconds(dotenv::var("FLEET_STALE_SECS").map_or(Ok(300), |s| s.parse())?),
+                expire_after: chrono::Duration::seconds(dotenv::var("FLEET_EXPIRE_SECS").map_or(Ok(86400), |s| s.parse())?),
+            },
+            snapshot: dotenv::var("FLEET_SNAPSHOT").ok().map(Into::into),
+            save_interval: Duration::from_secs(60),
+        };
+        let fleet = live::restore(&config, registry.clone(), clock.clone());
+
+        if let Ok(api_addr) = dotenv::var("API_ADDR") {
+            let api_config = api::ApiConfig { addr: api_addr, token: dotenv::var("API_TOKEN").ok() };
+            let api_fleet = fleet.clone();
+            tokio::spawn(async move {
+                if let Err(err) = api::serve(api_config, api_fleet).await {
+                    error!("error serving the fleet api: {:?}", err);
+                }
+            });
+        }
+
+        // FLARM NMEA traffic for XCSoar, as seen from `FLARM_OWNSHIP`, see `flarm::Ownship`.
+        if let Ok(flarm_addr) = dotenv::var("FLARM_ADDR") {
+            let ownship = dotenv::var("FLARM_OWNSHIP")?;
+            let flarm_config = flarm::FlarmConfig {
+                addr: flarm_addr,
+                ownship: live::Ownship::parse(&ownship).ok_or(format!("invalid FLARM_OWNSHIP: {}", ownship))?,
+                range_km: dotenv::var("FLARM_RANGE_KM").map_or(Ok(30.0), |s| s.parse())?,
+                interval: Duration::from_secs(1),
+            };
+            let flarm_fleet = fleet.clone();
+            tokio::spawn(async move {
+                if let Err(err) = flarm::serve(flarm_config, flarm_fleet).await {
+                    error!("error serving flarm traffic: {:?}", err);
+                }
+            });
+        }
+
+        // GDL90 traffic for EFB apps, sent over UDP, usually to a broadcast address.
+        if let Ok(gdl90_addr) = dotenv::var("GDL90_ADDR") {
+            let ownship = dotenv::var("GDL90_OWNSHIP").ok();
+            let gdl90_config = gdl90::Gdl90Config {
+                addr: gdl90_addr,
+                ownship: ownship
+                    .map(|ownship| live::Ownship::parse(&ownship).ok_or(format!("invalid GDL90_OWNSHIP: {}", ownship)))
+                    .transpose()?,
+                range_km: dotenv::var("GDL90_RANGE_KM").map_or(Ok(50.0), |s| s.parse())?,
+                interval: Duration::from_secs(1),
+            };
+            let gdl90_fleet = fleet.clone();
+            tokio::spawn(async move {
+                if let Err(err) = gdl90::send(gdl90_config, gdl90_fleet).await {
+                    error!("error sending gdl90 traffic: {:?}", err);
+                }
+            });
+        }
+
+        // Cursor-on-Target events for ATAK, named after the aircraft's registration in the ogn ddb.
+        if let Ok(cot_addr) = dotenv::var("COT_ADDR") {
+            let cot_config = cot::CotConfig {
+                target: cot::CotTarget::parse(&cot_addr).ok_or(format!("invalid COT_ADDR: {}", cot_addr))?,
+                interval: Duration::from_secs(dotenv::var("COT_INTERVAL_SECS").map_or(Ok(5), |s| s.parse())?),
+            };
+            let (cot_fleet, cot_registry) = (fleet.clone(), registry.clone());
+            tokio::spawn(async move {
+                if let Err(err) = cot::send(cot_config, cot_fleet, cot_registry).await {
+                    error!("error sending cot events: {:?}", err);
+                }
+            });
+        }
+
+        // BaseStation positions like dump1090's port 30003. FLARM and OGN ids are passed as they are
+        // unless `SBS_NON_ICAO` says otherwise, see `sbs::NonIcao`.
+        if let Ok(sbs_addr) = dotenv::var("SBS_ADDR") {
+            let non_icao = dotenv::var("SBS_NON_ICAO").unwrap_or_else(|_| "raw".to_owned());
+            let sbs_config = sbs::SbsConfig {
+                addr: sbs_addr,
+                non_icao: sbs::NonIcao::from_name(&non_icao).ok_or(format!("invalid SBS_NON_ICAO: {}", non_icao))?,
+            };
+            let sbs_fleet = fleet.clone();
+            tokio::spawn(async move {
+                if let Err(err) = sbs::serve(sbs_config, sbs_fleet).await {
+                    error!("error serving sbs positions: {:?}", err);
+                }
+            });
         }
+
+        let (fleet_tx, fleet_rx) = backpressure::sink_channel("fleet", &queue_config);
+        tasks.push(tokio::spawn(live::write_aprs(fleet, config, fleet_rx).instrument(info_span!("sink", sink = "fleet"))));
+        sinks.push(fleet_tx);
     }
-    Ok(())
-}
 
-// write one message to the TCP socket.
-async fn write(stream: &mut TcpStream, str: &[u8]) -> Result<(), Box<dyn Error>> {
-    loop {
-        stream.writable().await?;
+    // read from all configured sources at once. Without `SOURCES`, the scraper connects to
+    // the APRS-IS server at `APRS_ADDR`, as it always has.
+    let sources: Vec<Box<dyn source::Source>> = if args.get(1).is_some_and(|command| command == "replay") {
+        vec![Box::new(replay::ReplaySource::from_args(&args[2..], clock)?)]
+    } else {
+        let login_str = dotenv::var("APRS_LOGIN_STR").ok();
+        let spec = match dotenv::var("SOURCES") {
+            Ok(spec) => spec,
+            Err(_) => format!("aprs-is:{}", dotenv::var("APRS_ADDR")?),
+        };
+        source::from_config(&spec, login_str.as_deref())?
+    };
 
-        match stream.try_write(str) {
-            // we've successfully written n characters to the socket.
-            Ok(n) => {
-                info!("(write {}) {}", n, util::format_for_display(str));
-                break;
-            }
-            // we're not ready to write yet, try again on the next loop.
-            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                continue;
-            }
-            // we've gotten another kind of error, return it.
-            Err(e) => {
-                return Err(e.into());
+    // lines delivered by more than one source, e.g. two redundant APRS-IS connections, are only
+    // forwarded once. A window of 0 disables deduplication.
+    let dedup_window: i64 = dotenv::var("DEDUP_WINDOW_SECS").map_or(Ok(30), |s| s.parse())?;
+    let upstreams = sources.iter().filter(|source| source.is_upstream()).map(|source| source.name().into()).collect();
+    let dedup = (dedup_window > 0).then(|| dedup::Dedup::new(chrono::Duration::seconds(dedup_window), upstreams));
+    tasks.push(tokio::spawn(fanout::fan_out(rx, sinks, dedup)));
+
+    let mut source_tasks = Vec::new();
+    for mut source in sources {
+        let tx = tx.clone();
+        // everything logged while reading, e.g. by the APRS-IS client, is tagged with the source.
+        let span = info_span!("connection", source = source.name());
+        let shutdown = shutdown.clone();
+        source_tasks.push(tokio::spawn(
+            async move {
+                tokio::select! {
+                    result = source.run(tx) => if let Err(err) = result {
+                        error!("source {} failed: {:?}", source.name(), err);
+                    },
+                    // dropping the source closes its connection and its end of the channel.
+                    _ = shutdown.cancelled() => info!("stopping source {}", source.name()),
+                }
+                info!("source {} is done", source.name());
             }
-        }
+            .instrument(span),
+        ));
     }
+    drop(tx);
 
-    Ok(())
-}
\ No newline at end of file
+    // once all sources are exhausted or stopped, the channel drains through the fan out into the
+    // sinks, which write out everything that was read and close.
+    for task in source_tasks {
+        task.await?;
+    }
+    let result = shutdown::wait_for_sinks(tasks, &shutdown, shutdown_timeout).await;
+    match &result {
+        Ok(()) => info!("all sinks flushed, exiting"),
+        Err(err) => error!("{}", err),
+    }
+    // a log sink that's unavailable itself mustn't hold up the exit.
+    tokio::time::timeout(Duration::from_secs(2), logs.flush()).await.ok();
+
+    // exit right away: a stopped source may still be blocked in a read, e.g. of stdin,
+    // which would keep the runtime from shutting down.
+    std::process::exit(if result.is_ok() { 0 } else { 1 })
+}
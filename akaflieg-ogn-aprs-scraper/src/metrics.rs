@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use chrono::{DateTime, Utc};
+use prometheus::{
+    register_gauge, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Gauge, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use tokio::net::TcpListener;
+
+use tracing::info;
+
+// lines read, by the name of the source they were read from.
+pub static RECEIVED_LINES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("aprs_received_lines_total", "Lines read from each source", &["source"]).unwrap()
+});
+
+// how long after the first delivery each source delivered a line, see `dedup`.
+pub static UPSTREAM_LAG: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "aprs_upstream_lag_seconds",
+        "Delay of each source behind the first delivery of a line",
+        &["source"],
+        vec![0.0, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_MISSED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "aprs_upstream_missed_lines_total",
+        "Lines delivered by another upstream but not by this one",
+        &["source"]
+    )
+    .unwrap()
+});
+
+// lines the parser understood, and those it didn't by a rough reason, see `influx_schema`.
+pub static PARSED_LINES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("aprs_parsed_lines_total", "Lines parsed into a position").unwrap()
+});
+
+pub static PARSE_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("aprs_parse_failures_total", "Lines that could not be parsed", &["reason"]).unwrap()
+});
+
+pub static SOURCE_RECONNECTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("aprs_source_reconnects_total", "Reconnects of each source", &["source"]).unwrap()
+});
+
+// wall clock time of the last line from any source, see `last_packet_age`.
+pub static LAST_PACKET_TIME: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!("aprs_last_packet_timestamp_seconds", "Unix time the last line was received").unwrap()
+});
+
+pub static LAST_PACKET_AGE: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!("aprs_last_packet_age_seconds", "Seconds since the last line was received").unwrap()
+});
+
+// messages waiting in the channel of each sink.
+pub static SINK_QUEUE_DEPTH: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("aprs_sink_queue_depth", "Messages waiting in each sink's channel", &["sink"]).unwrap()
+});
+
+// packets for a full sink queue, by what the backpressure policy did with them.
+pub static SINK_QUEUE_OVERFLOWS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "aprs_sink_queue_overflows_total",
+        "Packets for a full sink queue, by what happened to them",
+        &["sink", "action"]
+    )
+    .unwrap()
+});
+
+pub static SINK_SPILLED_PACKETS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("aprs_sink_spilled_packets", "Packets spilled to disk for each sink", &["sink"]).unwrap()
+});
+
+// how long each write of a sink took, a batch for influx and postgres, a single message otherwise.
+// mqtt only queues messages for its event loop, so it has no writes to time.
+pub static SINK_WRITE_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "aprs_sink_write_seconds",
+        "Duration of each sink write",
+        &["sink"],
+        vec![0.0001, 0.001, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+    )
+    .unwrap()
+});
+
+pub static SINK_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("aprs_sink_errors_total", "Failed writes of each sink", &["sink"]).unwrap()
+});
+
+pub static DUPLICATE_LINES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("aprs_duplicate_lines_total", "Duplicate lines removed before the sinks").unwrap()
+});
+
+// points waiting to be written to influx, in the write channel and the current batch.
+pub static INFLUX_QUEUE_DEPTH: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("influx_queue_depth", "Points waiting to be written to InfluxDB").unwrap()
+});
+
+pub static INFLUX_SPOOL_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("influx_spool_bytes", "Size of the on-disk InfluxDB spool in bytes").unwrap()
+});
+
+pub static INFLUX_SPOOL_POINTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("influx_spool_points", "Points held in the on-disk InfluxDB spool").unwrap()
+});
+
+pub static INFLUX_WRITTEN_POINTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("influx_written_points_total", "Points written to InfluxDB").unwrap()
+});
+
+pub static INFLUX_WRITE_RETRIES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("influx_write_retries_total", "Retried InfluxDB batch writes").unwrap()
+});
+
+pub static INFLUX_DROPPED_POINTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "influx_dropped_points_total",
+        "Points dropped because they could be neither written nor spooled"
+    )
+    .unwrap()
+});
+
+pub static LOG_DROPPED_RECORDS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("log_dropped_records_total", "Log records the log sink could not keep up with or write").unwrap()
+});
+
+pub fn packet_received() {
+    LAST_PACKET_TIME.set(Utc::now().timestamp_millis() as f64 / 1000.0);
+}
+
+// time since the last line, or since `started` if nothing was received yet.
+pub fn last_packet_age(started: DateTime<Utc>) -> Duration {
+    let last = match LAST_PACKET_TIME.get() {
+        0.0 => started.timestamp_millis() as f64 / 1000.0,
+        last => last,
+    };
+    let now = Utc::now().timestamp_millis() as f64 / 1000.0;
+    Duration::from_secs_f64((now - last).max(0.0))
+}
+
+#[derive(Clone)]
+pub struct Health {
+    pub started: DateTime<Utc>,
+    // `/healthz` fails once no line arrived for this long.
+    pub max_packet_age: Duration,
+}
+
+// serve all registered metrics in the prometheus text format on `/metrics`, and a health check
+// for docker on `/healthz`.
+pub async fn serve(addr: String, health: Health) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let app = Router::new()
+        .route("/metrics", get(render))
+        .route("/healthz", get(healthz))
+        .with_state(health);
+    let listener = TcpListener::bind(&addr).await?;
+    info!("serving metrics on {:?}", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn healthz(State(health): State<Health>) -> impl IntoResponse {
+    let age = last_packet_age(health.started);
+    if age > health.max_packet_age {
+        let message = format!("no packet received for {}s\n", age.as_secs());
+        return (StatusCode::SERVICE_UNAVAILABLE, message);
+    }
+    (StatusCode::OK, format!("ok, last packet {}s ago\n", age.as_secs()))
+}
+
+async fn render(State(health): State<Health>) -> impl IntoResponse {
+    LAST_PACKET_AGE.set(last_packet_age(health.started).as_secs_f64());
+
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buf) {
+        buf = format!("# error encoding metrics: {:?}\n", err).into_bytes();
+    }
+    ([(CONTENT_TYPE, encoder.format_type().to_owned())], buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::body::to_bytes;
+
+    #[tokio::test]
+    async fn healthz_fails_without_recent_packets() {
+        let started = Utc::now() - chrono::Duration::seconds(120);
+        let stale = Health { started, max_packet_age: Duration::from_secs(60) };
+        let fresh = Health { started, max_packet_age: Duration::from_secs(600) };
+        if LAST_PACKET_TIME.get() == 0.0 {
+            assert_eq!(healthz(State(stale.clone())).await.into_response().status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+        assert_eq!(healthz(State(fresh)).await.into_response().status(), StatusCode::OK);
+
+        packet_received();
+        assert_eq!(healthz(State(stale.clone())).await.into_response().status(), StatusCode::OK);
+        let body = to_bytes(render(State(stale)).await.into_response().into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("aprs_last_packet_age_seconds 0"));
+    }
+}
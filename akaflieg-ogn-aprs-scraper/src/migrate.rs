@@ -0,0 +1,76 @@
+use std::error::Error;
+
+use chrono::{Duration, TimeZone, Utc};
+
+use influxdb2::models::WriteDataPoint;
+use influxdb2::Client;
+
+use tracing::info;
+
+use crate::cli::{arg_flag, arg_time};
+use crate::influx::{delete_range, InfluxConnection};
+use crate::influx_schema::{query_messages, transform_aprs, LEGACY_MEASUREMENT};
+
+// migrate one hour at a time, so we never hold more than that in memory.
+pub const WINDOW: Duration = Duration::hours(1);
+
+// `migrate-schema --from <time> [--to <time>] [--delete-old]`
+//
+// Rewrites the raw messages stored in the schema version 1 `aprs` measurement into the current
+// schema, see `influx_schema`. Points are written with their original timestamps, so running the
+// migration twice over the same range doesn't duplicate anything. With `--delete-old`, each
+// migrated window of the old measurement is deleted once it has been written.
+pub async fn run(
+    client: &Client,
+    influx: &InfluxConnection,
+    bucket: &str,
+    args: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let from = arg_time(args, "--from")?.ok_or("missing --from")?;
+    let to = arg_time(args, "--to")?.unwrap_or_else(Utc::now);
+    let delete_old = arg_flag(args, "--delete-old");
+
+    let mut migrated = 0;
+    let mut start = from;
+    while start < to {
+        let stop = (start + WINDOW).min(to);
+        let filter = format!(
+            "r._measurement == \"{}\" and r._field == \"message\"",
+            LEGACY_MEASUREMENT
+        );
+        let rows = query_messages(client, bucket, &filter, start, stop).await?;
+
+        let mut lines = Vec::new();
+        let mut last_received = Utc.timestamp_nanos(0);
+        for row in &rows {
+            let time = match row.time {
+                Some(time) => time.with_timezone(&Utc),
+                None => continue,
+            };
+            // version 1 timestamped parsed messages with their second-resolution packet time;
+            // raw messages must not share a timestamp, so we spread them out by a nanosecond.
+            let received = time.max(last_received + Duration::nanoseconds(1));
+            last_received = received;
+
+            for point in transform_aprs(&row.message, received) {
+                point.write_data_point_to(&mut lines)?;
+            }
+        }
+
+        if !lines.is_empty() {
+            client.write_line_protocol(&influx.org, bucket, lines).await?;
+        }
+        if delete_old {
+            let predicate = format!("_measurement=\"{}\"", LEGACY_MEASUREMENT);
+            let last = stop - Duration::nanoseconds(1);
+            delete_range(influx, bucket, start, last, &predicate).await?;
+        }
+
+        migrated += rows.len();
+        info!("migrated {} messages up to {}", rows.len(), stop);
+        start = stop;
+    }
+
+    info!("migration done, {} messages migrated", migrated);
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use rumqttc::{
+    AsyncClient, ConnectionError, Event, EventLoop, MqttOptions, Outgoing, Packet as MqttPacket, QoS,
+};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{sleep, timeout};
+
+use tracing::{debug, error, info, warn};
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+
+use crate::ddb::SharedRegistry;
+use crate::metrics::SINK_ERRORS;
+use crate::record::Packet;
+
+// how long queued positions may take to reach the broker when the sink closes.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub credentials: Option<(String, String)>,
+    pub topic_prefix: String,
+    pub qos: QoS,
+    pub reconnect_delay: Duration,
+}
+
+// aircraft whose owners opted out of tracking, in the ogn ddb or with their device's no-tracking
+// flag, aren't published.
+pub async fn write_aprs(config: MqttConfig, registry: SharedRegistry, mut rx: Receiver<Packet>) {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some((user, password)) = &config.credentials {
+        options.set_credentials(user, password);
+    }
+
+    let (client, eventloop) = AsyncClient::new(options, 256);
+    let eventloop = tokio::spawn(drive_eventloop(eventloop, config.reconnect_delay));
+
+    // loop while channel is still alive
+    while let Some(packet) = rx.recv().await {
+        let Some(parsed) = packet.position else { continue };
+        if !registry.read().unwrap().may_track(&parsed) {
+            continue;
+        }
+
+        let payload = match serde_json::to_vec(&parsed) {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!("error serializing aprs message <{:?}>: {:?}", packet.line, err);
+                continue;
+            }
+        };
+
+        // the last position of every aircraft is retained, so new subscribers (e.g. a display
+        // that just started) immediately get the last known state of the whole fleet.
+        let topic = position_topic(&config.topic_prefix, &parsed);
+        if let Err(err) = client.try_publish(topic, config.qos, true, payload) {
+            // don't hold up the other sinks while the broker is unavailable, drop the position instead.
+            warn!("dropping mqtt position: {:?}", err);
+            SINK_ERRORS.with_label_values(&["mqtt"]).inc();
+        }
+    }
+
+    // let the eventloop send what is still queued; it ends once the disconnect went out.
+    if let Err(err) = client.disconnect().await {
+        debug!("error disconnecting from mqtt broker: {:?}", err);
+    }
+    drop(client);
+    if timeout(DISCONNECT_TIMEOUT, eventloop).await.is_err() {
+        warn!("mqtt broker unavailable, dropping queued positions");
+    }
+
+    // all sources are done or were stopped for shutdown, and everything they read is written.
+    info!("closed mqtt sink");
+}
+
+// polling the eventloop drives the connection; rumqttc reconnects on the next poll after an error.
+async fn drive_eventloop(mut eventloop: EventLoop, reconnect_delay: Duration) {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(MqttPacket::ConnAck(_))) => info!("connected to mqtt broker"),
+            // we're shutting down, everything queued before the disconnect was sent.
+            Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(ConnectionError::RequestsDone) => break,
+            Ok(_) => {}
+            Err(err) => {
+                error!("mqtt connection error, reconnecting: {:?}", err);
+                sleep(reconnect_delay).await;
+            }
+        }
+    }
+}
+
+// aircraft are identified by their address, falling back to the APRS callsign if we don't have one.
+fn position_topic(prefix: &str, msg: &OGNStatusMessage) -> String {
+    let address = msg.aircraft_id.as_deref().unwrap_or(&msg.aprs_callsign);
+    format!("{}/{}/position", prefix, address)
+}
+
+pub fn qos_from(level: u8) -> Option<QoS> {
+    match level {
+        0 => Some(QoS::AtMostOnce),
+        1 => Some(QoS::AtLeastOnce),
+        2 => Some(QoS::ExactlyOnce),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rumqttc::Outgoing;
+
+    const TEST_MSG: &str = "ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\\00803.85E^206/080/A=003503 !W75! id213E6DBA -316fpm +0.1rot 9.8dB 6e -4.5kHz gps2x2";
+
+    #[test]
+    fn topic_uses_aircraft_address() {
+        let msg = OGNStatusMessage::from_str(TEST_MSG, None).unwrap();
+        assert_eq!(position_topic("ogn", &msg), "ogn/3E6DBA/position");
+    }
+
+    #[test]
+    fn qos_levels_are_mapped() {
+        assert_eq!(qos_from(1), Some(QoS::AtLeastOnce));
+        assert_eq!(qos_from(3), None);
+    }
+
+    // needs a running broker, e.g. the `mosquitto` service from docker-compose.yaml:
+    // MQTT_TEST_HOST=localhost cargo test -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn positions_are_retained_on_local_broker() {
+        let host = std::env::var("MQTT_TEST_HOST").unwrap();
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        let config = MqttConfig {
+            host: host.clone(),
+            port: 1883,
+            client_id: "akaflieg-test-publisher".to_owned(),
+            credentials: None,
+            topic_prefix: "ogn-test".to_owned(),
+            qos: QoS::AtLeastOnce,
+            reconnect_delay: Duration::from_secs(1),
+        };
+        let publisher = tokio::spawn(write_aprs(config, SharedRegistry::default(), rx));
+        let packet = Packet {
+            received: chrono::Utc::now(),
+            source: "test".into(),
+            line: TEST_MSG.to_owned(),
+            position: OGNStatusMessage::from_str(TEST_MSG, None).ok(),
+        };
+        tx.send(packet).await.unwrap();
+        drop(tx);
+        publisher.await.unwrap();
+
+        // a subscriber connecting afterwards should get the retained position.
+        let (client, mut eventloop) = AsyncClient::new(
+            MqttOptions::new("akaflieg-test-subscriber", host, 1883),
+            10,
+        );
+        client
+            .subscribe("ogn-test/+/position", QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        let publish = loop {
+            match eventloop.poll().await.unwrap() {
+                Event::Incoming(MqttPacket::Publish(p)) => break p,
+                Event::Outgoing(Outgoing::Disconnect) => panic!("disconnected"),
+                _ => {}
+            }
+        };
+        assert_eq!(publish.topic, "ogn-test/3E6DBA/position");
+        assert!(publish.retain);
+        let json: serde_json::Value = serde_json::from_slice(&publish.payload).unwrap();
+        assert_eq!(json["aircraft_id"], "3E6DBA");
+    }
+}
@@ -0,0 +1,117 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use ogn_aprs_parser::ogn::ogn_address_type::AddressType;
+use ogn_fleet_state::{Aircraft, FleetState};
+
+use crate::emitter::EmitterCategory;
+
+const FEET_PER_METER: f64 = 3.28084;
+const KNOTS_PER_MPS: f64 = 1.943844;
+
+// The live fleet in the format of readsb's `aircraft.json`, as polled by tar1090 and Virtual Radar
+// Server, see https://github.com/wiedehopf/readsb/blob/dev/README-json.md
+//
+// OGN positions come from GPS, so altitude and climb are the geometric `alt_geom` and `geom_rate`.
+// Addresses that aren't ICAO, e.g. FLARM ids, get readsb's `~` prefix for non-ICAO addresses.
+#[derive(Debug, Serialize)]
+pub struct AircraftJson {
+    pub now: f64,
+    pub messages: u64,
+    pub aircraft: Vec<ReadsbAircraft>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadsbAircraft {
+    pub hex: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub category: String,
+    pub lat: f64,
+    pub lon: f64,
+    // feet.
+    pub alt_geom: i64,
+    // knots.
+    pub gs: f64,
+    pub track: f64,
+    // feet per minute.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geom_rate: Option<i64>,
+    // seconds since the last message and position, the same for OGN.
+    pub seen: f64,
+    pub seen_pos: f64,
+}
+
+// every aircraft not stale yet; those without an id, i.e. without an address, are left out.
+pub fn aircraft_json(fleet: &FleetState, now: DateTime<Utc>, messages: u64) -> AircraftJson {
+    let mut aircraft: Vec<_> = fleet
+        .aircraft()
+        .filter(|aircraft| !fleet.is_stale(aircraft, now))
+        .filter_map(|aircraft| readsb_aircraft(aircraft, now))
+        .collect();
+    aircraft.sort_by(|a, b| a.hex.cmp(&b.hex));
+    AircraftJson { now: now.timestamp_millis() as f64 / 1000.0, messages, aircraft }
+}
+
+fn readsb_aircraft(aircraft: &Aircraft, now: DateTime<Utc>) -> Option<ReadsbAircraft> {
+    let last = &aircraft.last;
+    let id = last.aircraft_id.as_ref()?.to_lowercase();
+    let seen = aircraft.age(now).num_milliseconds().max(0) as f64 / 1000.0;
+    Some(ReadsbAircraft {
+        hex: match last.address_type {
+            Some(AddressType::ICAO) => id,
+            _ => format!("~{}", id),
+        },
+        kind: "other",
+        category: EmitterCategory::from_aircraft_type(&last.aircraft_type).name(),
+        lat: aircraft.latitude(),
+        lon: aircraft.longitude(),
+        alt_geom: (last.position.altitude as f64 * FEET_PER_METER).round() as i64,
+        gs: (last.velocity.horizontal as f64 * KNOTS_PER_MPS * 10.0).round() / 10.0,
+        track: last.position.heading as f64,
+        geom_rate: last.velocity.vertical.map(|rate| (rate as f64 * FEET_PER_METER * 60.0).round() as i64),
+        seen,
+        seen_pos: seen,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+    use ogn_fleet_state::FleetConfig;
+
+    #[test]
+    fn converts_the_fleet_to_readsb_units() {
+        let received = Utc.with_ymd_and_hms(2023, 2, 19, 11, 24, 38).unwrap();
+        let mut fleet = FleetState::new(FleetConfig::default());
+        for line in [
+            "ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\\00803.85E^206/080/A=003503 !W75! id053E6DBA -316fpm",
+            "FLRDF153A>APRS,qAS,RHST:/112430h4858.10N\\00820.89E'301/050/A=001145 !W71! id06DF153A +693fpm",
+            "FLRDD1234>APRS,qAS,RHST:/100000h4858.10N\\00820.89E'301/050/A=001145 !W71! id06DD1234",
+        ] {
+            let msg = OGNStatusMessage::from_str(line, Some(received)).unwrap();
+            let heard = msg.timestamp;
+            fleet.update(msg, heard);
+        }
+
+        let json = aircraft_json(&fleet, received, 3);
+        let value = serde_json::to_value(&json).unwrap();
+        assert_eq!(value["now"], 1676805878.0);
+        // DD1234 is stale.
+        let aircraft = value["aircraft"].as_array().unwrap();
+        assert_eq!(aircraft.len(), 2);
+        assert_eq!(aircraft[0]["hex"], "3e6dba");
+        assert_eq!(aircraft[0]["type"], "other");
+        assert_eq!(aircraft[0]["category"], "B1");
+        assert_eq!(aircraft[0]["alt_geom"], 3503);
+        assert_eq!(aircraft[0]["gs"], 80.0);
+        assert_eq!(aircraft[0]["track"], 206.0);
+        assert_eq!(aircraft[0]["geom_rate"], -316);
+        assert_eq!(aircraft[0]["seen"], 1.0);
+        assert_eq!(aircraft[1]["hex"], "~df153a");
+        assert_eq!(aircraft[1]["seen_pos"], 8.0);
+    }
+}
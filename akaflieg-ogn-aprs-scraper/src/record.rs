@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+
+// one line as received from a source, dated on arrival.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub received: DateTime<Utc>,
+    // the name of the source the line was read from.
+    pub source: Arc<str>,
+    // the line as it was read, without the line ending. Empty for positions from other feeds,
+    // e.g. ADS-B (see `adsb`), which the sinks that keep the raw feed skip.
+    pub line: String,
+    // the aircraft position of the packet: parsed from its line once by `fanout`, for all sinks, or
+    // as it came from another feed.
+    pub position: Option<OGNStatusMessage>,
+}
+
+// the fields we extract from a parsed APRS message, shared between all sinks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AprsFix {
+    pub timestamp: DateTime<Utc>,
+    pub aprs_callsign: String,
+    pub aprs_path: String,
+    pub aprs_type: String,
+    pub aircraft_type: String,
+    pub lat: f64,
+    pub long: f64,
+    pub heading: f64,
+    pub altitude: f64,
+    pub vel_horiz: f64,
+    pub vel_vert: Option<f64>,
+    pub rot: Option<f64>,
+    pub aircraft_id: Option<String>,
+    pub stealth: Option<bool>,
+    pub no_track: Option<bool>,
+    pub addr_type: Option<String>,
+    pub reception: Option<f64>,
+    pub bit_errors: Option<u32>,
+    pub frequency_offset: Option<f64>,
+    pub gps_resolution_horizontal: Option<u32>,
+    pub gps_resolution_vertical: Option<u32>,
+    pub position_precision_lat: Option<f64>,
+    pub position_precision_long: Option<f64>,
+}
+
+impl AprsFix {
+    pub fn from(p: OGNStatusMessage) -> Self {
+        AprsFix {
+            timestamp: p.timestamp,
+            aprs_callsign: p.aprs_callsign,
+            aprs_path: p.aprs_path,
+            aprs_type: format!("{:?}", p.aprs_type),
+            aircraft_type: format!("{:?}", p.aircraft_type),
+            lat: p.position.latitude as f64,
+            long: p.position.longitude as f64,
+            heading: p.position.heading as f64,
+            altitude: p.position.altitude as f64,
+            vel_horiz: p.velocity.horizontal as f64,
+            vel_vert: p.velocity.vertical.map(|vel| vel as f64),
+            rot: p.velocity.rotation.map(|rot| rot as f64),
+            aircraft_id: p.aircraft_id,
+            stealth: p.ogn_flags.as_ref().map(|flags| flags.stealth_mode),
+            no_track: p.ogn_flags.as_ref().map(|flags| flags.no_tracking_mode),
+            addr_type: p.address_type.map(|addr_t| format!("{:?}", addr_t)),
+            reception: p.reception.map(|reception| reception as f64),
+            bit_errors: p.bit_errors,
+            frequency_offset: p.frequency_offset.map(|offset| offset as f64),
+            gps_resolution_horizontal: p.gps_resolution.as_ref().map(|gps| gps.horizontal),
+            gps_resolution_vertical: p.gps_resolution.as_ref().map(|gps| gps.vertical),
+            position_precision_lat: p.position_precision.as_ref().map(|pp| pp.latitude as f64),
+            position_precision_long: p.position_precision.as_ref().map(|pp| pp.longitude as f64),
+        }
+    }
+
+    // the address identifying the aircraft, falls back to the callsign if the
+    // message didn't carry an id extension.
+    pub fn address(&self) -> &str {
+        self.aircraft_id.as_deref().unwrap_or(&self.aprs_callsign)
+    }
+}
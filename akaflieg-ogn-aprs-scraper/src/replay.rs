@@ -0,0 +1,205 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::mpsc::Sender;
+use tokio::time::{sleep_until, Instant};
+
+use tracing::{debug, info};
+
+use crate::aprs_log::{read_log, received_comment_time};
+use crate::cli::{arg_flag, arg_value};
+use crate::clock::Clock;
+use crate::record::Packet;
+use crate::source::{Source, SourceError};
+use crate::util;
+
+// how fast recorded logs are fed into the pipeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    // with the gaps between lines as they were recorded.
+    Original,
+    // N times faster than recorded.
+    Speed(f64),
+    // as fast as the sinks accept lines.
+    Fast,
+}
+
+// Recorded logs (plain, `.gz` or `.zst`) fed into the pipeline in place of a live source.
+//
+// Lines are dated by the comments of the log (see `aprs_log`) and sent with that time as their
+// receive time, which also drives the virtual `Clock`. Lines before the first dated comment can't
+// be placed on the timeline and are skipped, as are the `# received` comments of our own archive.
+pub struct ReplaySource {
+    pub paths: Vec<PathBuf>,
+    pub pace: Pace,
+    // set to the time of every replayed line.
+    pub clock: Clock,
+}
+
+impl ReplaySource {
+    // `replay [--speed <factor> | --fast] <file>...`
+    pub fn from_args(args: &[String], clock: Clock) -> Result<Self, Box<dyn Error>> {
+        let pace = match (arg_value(args, "--speed"), arg_flag(args, "--fast")) {
+            (Some(_), true) => return Err("--speed and --fast are exclusive".into()),
+            (Some(speed), false) => {
+                let speed: f64 = speed.parse().map_err(|_| format!("invalid --speed <{}>", speed))?;
+                if speed <= 0.0 {
+                    return Err(format!("invalid --speed <{}>", speed).into());
+                }
+                Pace::Speed(speed)
+            }
+            (None, true) => Pace::Fast,
+            (None, false) => Pace::Original,
+        };
+
+        let mut paths = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--speed" => {
+                    args.next();
+                }
+                "--fast" => {}
+                path => paths.push(PathBuf::from(path)),
+            }
+        }
+        if paths.is_empty() {
+            return Err("no log files to replay".into());
+        }
+        Ok(ReplaySource { paths, pace, clock })
+    }
+}
+
+#[async_trait]
+impl Source for ReplaySource {
+    fn name(&self) -> &str {
+        "replay"
+    }
+
+    async fn run(&mut self, aprs_tx: Sender<Packet>) -> Result<(), SourceError> {
+        replay(&self.paths, self.pace, &self.clock, &aprs_tx).await
+    }
+}
+
+async fn replay(paths: &[PathBuf], pace: Pace, clock: &Clock, aprs_tx: &Sender<Packet>) -> Result<(), SourceError> {
+    let source: Arc<str> = "replay".into();
+    let mut timeline = Timeline::new(pace);
+    let (mut replayed, mut skipped) = (0, 0);
+
+    for path in paths {
+        info!("replaying {:?} at {:?} pace", path, pace);
+        for line in read_log(path)? {
+            let line = line?;
+            let time = match line.time {
+                Some(time) if received_comment_time(&line.line).is_none() => time,
+                _ => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+
+            let received = timeline.advance(time).await;
+            clock.set_virtual(received);
+            debug!("(replay) {}", util::format_for_display(line.line.as_bytes()));
+            aprs_tx.send(Packet { received, source: Arc::clone(&source), line: line.line, position: None }).await?;
+            replayed += 1;
+        }
+    }
+
+    info!("replay done, {} lines replayed, {} skipped", replayed, skipped);
+    Ok(())
+}
+
+// maps recorded times to wall clock times, and hands out strictly increasing receive times.
+struct Timeline {
+    pace: Pace,
+    start: Option<(DateTime<Utc>, Instant)>,
+    last: Option<DateTime<Utc>>,
+}
+
+impl Timeline {
+    fn new(pace: Pace) -> Self {
+        Timeline { pace, start: None, last: None }
+    }
+
+    // wait until the line recorded at `time` is due, and return its receive time. Server comments
+    // only date lines to the second, so lines sharing a time are spread out by a nanosecond, just
+    // like live receive times never repeat.
+    async fn advance(&mut self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let received = match self.last {
+            Some(last) => time.max(last + Duration::nanoseconds(1)),
+            None => time,
+        };
+        self.last = Some(received);
+
+        let (start_time, start_instant) = *self.start.get_or_insert((received, Instant::now()));
+        let elapsed = (received - start_time).to_std().unwrap_or_default();
+        match self.pace {
+            Pace::Original => sleep_until(start_instant + elapsed).await,
+            Pace::Speed(speed) => sleep_until(start_instant + elapsed.div_f64(speed)).await,
+            Pace::Fast => {}
+        }
+        received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    use chrono::TimeZone;
+    use tokio::sync::mpsc;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn pace_and_files_are_read_from_args() {
+        let config = ReplaySource::from_args(&args(&["a.log", "--speed", "10", "b.log.zst"]), Clock::default()).unwrap();
+        assert_eq!(config.pace, Pace::Speed(10.0));
+        assert_eq!(config.paths, [PathBuf::from("a.log"), PathBuf::from("b.log.zst")]);
+
+        assert_eq!(ReplaySource::from_args(&args(&["--fast", "a.log"]), Clock::default()).unwrap().pace, Pace::Fast);
+        assert!(ReplaySource::from_args(&args(&["--fast"]), Clock::default()).is_err());
+        assert!(ReplaySource::from_args(&args(&["--speed", "0", "a.log"]), Clock::default()).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replay_keeps_recorded_gaps_scaled_by_speed() {
+        let mut timeline = Timeline::new(Pace::Speed(10.0));
+        let t0 = Utc.with_ymd_and_hms(2023, 2, 19, 11, 18, 57).unwrap();
+        let start = Instant::now();
+
+        assert_eq!(timeline.advance(t0).await, t0);
+        assert_eq!(timeline.advance(t0).await, t0 + Duration::nanoseconds(1));
+        timeline.advance(t0 + Duration::seconds(20)).await;
+        assert_eq!(Instant::now() - start, std::time::Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn recorded_log_is_replayed_with_virtual_time() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../ogn-aprs-parser/assets/APRS2.log");
+        let clock = Clock::default();
+        let mut source = ReplaySource { paths: vec![path], pace: Pace::Fast, clock: clock.clone() };
+        let (tx, mut rx) = mpsc::channel(100_000);
+        source.run(tx).await.unwrap();
+
+        let first = rx.recv().await.unwrap();
+        assert!(first.line.starts_with("# aprsc"));
+        assert_eq!(first.received, Utc.with_ymd_and_hms(2023, 2, 19, 11, 18, 57).unwrap());
+
+        let mut last = first.received;
+        while let Some(packet) = rx.recv().await {
+            assert!(packet.received > last);
+            last = packet.received;
+        }
+        assert_eq!(clock.now(), last);
+    }
+}
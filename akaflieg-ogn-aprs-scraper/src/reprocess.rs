@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use influxdb2::models::WriteDataPoint;
+use influxdb2::Client;
+
+use tracing::info;
+
+use crate::aprs_log::read_log;
+use crate::cli::{arg_time, arg_value};
+use crate::influx::{delete_range, InfluxConnection};
+use crate::influx_schema::{query_messages, transform_aprs, transform_fix, RAW_MEASUREMENT};
+use crate::migrate::WINDOW;
+
+// `reprocess --from <time> [--to <time>] [--archive <file>]`
+//
+// Runs stored raw messages through the current parser again, e.g. after a parser upgrade.
+//
+// Without `--archive`, every `aprs_raw` row with `parsed=false` in the range is reparsed. Rows that
+// parse now get their `aprs_fix` point and are rewritten with `parsed=true` at their original
+// timestamp, then the `parsed=false` row is deleted. Writing before deleting means an interrupted
+// run loses nothing, and running it again only picks up the rows that still fail.
+//
+// With `--archive`, the same rows are reparsed from the lines of a recorded APRS log instead, e.g.
+// the raw feed archive. A line stands for the row stored at its receive time, so only archives
+// written with `# received` comments match rows; lines without a `parsed=false` row are left alone.
+pub async fn run(
+    client: &Client,
+    influx: &InfluxConnection,
+    bucket: &str,
+    args: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let from = arg_time(args, "--from")?.ok_or("missing --from")?;
+    let to = arg_time(args, "--to")?.unwrap_or_else(Utc::now).min(Utc::now());
+
+    match arg_value(args, "--archive") {
+        Some(path) => reprocess_archive(client, influx, bucket, Path::new(path), from, to).await,
+        None => reprocess_raw(client, influx, bucket, from, to).await,
+    }
+}
+
+async fn reprocess_raw(
+    client: &Client,
+    influx: &InfluxConnection,
+    bucket: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<(), Box<dyn Error>> {
+    let (mut examined, mut changed) = (0, 0);
+    let mut start = from;
+    while start < to {
+        let stop = (start + WINDOW).min(to);
+        let rows = query_unparsed(client, bucket, start, stop).await?;
+        let rows: Vec<_> = rows.iter().map(|(time, message)| (*time, Some(message.as_str()))).collect();
+        let now_parsed = reparse(client, influx, bucket, &rows).await?;
+
+        examined += rows.len();
+        changed += now_parsed;
+        info!("reprocessed {} unparsed messages up to {}, {} parse now", rows.len(), stop, now_parsed);
+        start = stop;
+    }
+
+    info!(
+        "reprocessing done, {} unparsed messages examined, {} parse now, {} still fail",
+        examined,
+        changed,
+        examined - changed
+    );
+    Ok(())
+}
+
+async fn reprocess_archive(
+    client: &Client,
+    influx: &InfluxConnection,
+    bucket: &str,
+    path: &Path,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<(), Box<dyn Error>> {
+    let (mut examined, mut changed) = (0, 0);
+    // the archived lines of one window, which starts at the first of them.
+    let mut window: Vec<(DateTime<Utc>, String)> = Vec::new();
+    let mut lines = read_log(path)?;
+    loop {
+        let line = lines.next().transpose()?;
+        if let Some(line) = &line {
+            match line.time {
+                Some(time) if !line.is_comment() && time >= from && time < to => {
+                    if window.first().is_none_or(|(start, _)| time < *start + WINDOW) {
+                        window.push((time, line.line.clone()));
+                        continue;
+                    }
+                }
+                _ => continue,
+            }
+        }
+
+        if let Some((start, _)) = window.first() {
+            let stop = (*start + WINDOW).min(to);
+            let unparsed = query_unparsed(client, bucket, *start, stop).await?;
+            let archived: HashMap<_, _> = window.iter().map(|(time, line)| (*time, line.as_str())).collect();
+            let rows: Vec<_> = unparsed.iter().map(|(time, _)| (*time, archived.get(time).copied())).collect();
+            let now_parsed = reparse(client, influx, bucket, &rows).await?;
+
+            examined += unparsed.len();
+            changed += now_parsed;
+            info!("reprocessed {} unparsed messages up to {}, {} parse now", unparsed.len(), stop, now_parsed);
+            window.clear();
+        }
+        match line {
+            Some(line) => window.push((line.time.unwrap(), line.line)),
+            None => break,
+        }
+    }
+
+    info!(
+        "reprocessing {:?} done, {} unparsed messages examined, {} parse now, {} unchanged",
+        path,
+        examined,
+        changed,
+        examined - changed
+    );
+    Ok(())
+}
+
+// the `parsed=false` rows in [start, stop), oldest first.
+async fn query_unparsed(
+    client: &Client,
+    bucket: &str,
+    start: DateTime<Utc>,
+    stop: DateTime<Utc>,
+) -> Result<Vec<(DateTime<Utc>, String)>, Box<dyn Error>> {
+    let filter = format!(
+        "r._measurement == \"{}\" and r._field == \"message\" and r.parsed == \"false\"",
+        RAW_MEASUREMENT
+    );
+    let rows = query_messages(client, bucket, &filter, start, stop).await?;
+    Ok(rows.into_iter().filter_map(|row| Some((row.time?.with_timezone(&Utc), row.message))).collect())
+}
+
+// Reparse unparsed rows, oldest first, with the message to reparse each from, if any. Rows that
+// parse now are rewritten, then their `parsed=false` rows are deleted. Returns how many parse now.
+async fn reparse(
+    client: &Client,
+    influx: &InfluxConnection,
+    bucket: &str,
+    rows: &[(DateTime<Utc>, Option<&str>)],
+) -> Result<usize, Box<dyn Error>> {
+    let mut lines = Vec::new();
+    let mut now_parsed = Vec::new();
+    for (time, message) in rows {
+        let parses = message.is_some_and(|message| transform_fix(message, *time).is_some());
+        if let (true, Some(message)) = (parses, message) {
+            for point in transform_aprs(message, *time) {
+                point.write_data_point_to(&mut lines)?;
+            }
+        }
+        now_parsed.push((*time, parses));
+    }
+
+    if !lines.is_empty() {
+        client.write_line_protocol(&influx.org, bucket, lines).await?;
+    }
+    let predicate = format!("_measurement=\"{}\" AND parsed=\"false\"", RAW_MEASUREMENT);
+    for (start, stop) in delete_ranges(&now_parsed) {
+        delete_range(influx, bucket, start, stop, &predicate).await?;
+    }
+    Ok(now_parsed.iter().filter(|(_, parses)| *parses).count())
+}
+
+// The time ranges covering rows that parse now, but none that still fail, so each run of rows
+// that parse now is deleted at once.
+fn delete_ranges(rows: &[(DateTime<Utc>, bool)]) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut ranges: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    let mut in_run = false;
+    for (time, parses) in rows {
+        match ranges.last_mut() {
+            Some(range) if *parses && in_run => range.1 = *time,
+            _ if *parses => ranges.push((*time, *time)),
+            _ => {}
+        }
+        in_run = *parses;
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn rows_that_parse_now_are_deleted_in_runs() {
+        let time = |second| Utc.with_ymd_and_hms(2023, 2, 19, 11, 24, second).unwrap();
+        let rows = [(time(0), true), (time(1), true), (time(2), false), (time(3), true), (time(4), false), (time(5), true), (time(6), true)];
+        assert_eq!(delete_ranges(&rows), [(time(0), time(1)), (time(3), time(3)), (time(5), time(6))]);
+        assert!(delete_ranges(&[(time(0), false)]).is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+use ogn_aprs_parser::ogn::ogn_address_type::AddressType;
+
+use crate::live::SharedFleet;
+
+const FEET_PER_METER: f64 = 3.28084;
+const KNOTS_PER_MPS: f64 = 1.943844;
+
+// Every new position as BaseStation (SBS-1) text, as dump1090 serves it on port 30003, for Virtual
+// Radar Server, PlanePlotter and scripts. Each position is a `MSG,3` with the position and altitude,
+// followed by a `MSG,4` with speed, track and climb.
+pub struct SbsConfig {
+    pub addr: String,
+    pub non_icao: NonIcao,
+}
+
+// What to do with FLARM and OGN tracker ids, which aren't ICAO addresses and may collide with one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NonIcao {
+    // leave them out.
+    Skip,
+    // pass them as if they were ICAO addresses.
+    Raw,
+    // prefix them with `~`, as readsb does for non-ICAO addresses.
+    Tilde,
+}
+
+impl NonIcao {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "skip" => Some(NonIcao::Skip),
+            "raw" => Some(NonIcao::Raw),
+            "tilde" => Some(NonIcao::Tilde),
+            _ => None,
+        }
+    }
+}
+
+fn hex(msg: &OGNStatusMessage, non_icao: NonIcao) -> Option<String> {
+    let id = msg.aircraft_id.as_deref()?.to_uppercase();
+    match (&msg.address_type, non_icao) {
+        (Some(AddressType::ICAO), _) | (_, NonIcao::Raw) => Some(id),
+        (_, NonIcao::Tilde) => Some(format!("~{}", id)),
+        (_, NonIcao::Skip) => None,
+    }
+}
+
+// the `MSG,3` and `MSG,4` lines for a message logged at `logged`, none for messages without an
+// aircraft id, or ids left out by `non_icao`.
+pub fn lines(msg: &OGNStatusMessage, logged: DateTime<Utc>, non_icao: NonIcao) -> Option<[String; 2]> {
+    let hex = hex(msg, non_icao)?;
+    let times = format!(
+        "{},{}",
+        msg.timestamp.format("%Y/%m/%d,%H:%M:%S%.3f"),
+        logged.format("%Y/%m/%d,%H:%M:%S%.3f")
+    );
+    let altitude = (msg.position.altitude as f64 * FEET_PER_METER).round();
+    let position = format!(
+        "MSG,3,1,1,{},1,{},,{},,,{:.5},{:.5},,,0,0,0,0",
+        hex, times, altitude, msg.position.latitude, msg.position.longitude
+    );
+    let climb = msg.velocity.vertical.map(|climb| (climb as f64 * FEET_PER_METER * 60.0).round().to_string());
+    let velocity = format!(
+        "MSG,4,1,1,{},1,{},,,{},{},,,{},,0,0,0,0",
+        hex,
+        times,
+        (msg.velocity.horizontal as f64 * KNOTS_PER_MPS).round(),
+        msg.position.heading.round(),
+        climb.unwrap_or_default()
+    );
+    Some([position, velocity])
+}
+
+pub async fn serve(config: SbsConfig, fleet: SharedFleet) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let listener = TcpListener::bind(&config.addr).await?;
+    info!("serving sbs positions on {:?}", config.addr);
+    accept(listener, fleet, Arc::new(config)).await?;
+    Ok(())
+}
+
+async fn accept(listener: TcpListener, fleet: SharedFleet, config: Arc<SbsConfig>) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        info!("sbs client connected from {}", peer);
+        let (fleet, config) = (fleet.clone(), config.clone());
+        tokio::spawn(
+            async move {
+                if let Err(err) = feed(stream, fleet, config).await {
+                    debug!("sbs client gone: {:?}", err);
+                }
+                info!("sbs client disconnected");
+            }
+            .instrument(info_span!("sbs", client = %peer)),
+        );
+    }
+}
+
+async fn feed(mut stream: TcpStream, fleet: SharedFleet, config: Arc<SbsConfig>) -> std::io::Result<()> {
+    let (mut updates, clock) = {
+        let live = fleet.read().unwrap();
+        (live.updates.subscribe(), live.clock.clone())
+    };
+    loop {
+        let msg = match updates.recv().await {
+            Ok(msg) => msg,
+            // unlike a live map, a feed of positions can just carry on with a gap.
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("sbs client too slow, skipped {} positions", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        if let Some([position, velocity]) = lines(&msg, clock.now(), config.non_icao) {
+            stream.write_all(format!("{}\r\n{}\r\n", position, velocity).as_bytes()).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::RwLock;
+
+    use chrono::TimeZone;
+    use ogn_fleet_state::{FleetConfig, FleetState};
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    use crate::live::LiveFleet;
+
+    const ICAO: &str = "ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\\00803.85E^206/080/A=003503 !W75! id213E6DBA -316fpm";
+    const FLARM: &str = "FLRDF153A>APRS,qAS,RHST:/112430h4858.10N\\00820.89E'301/050/A=001145 !W71! id06DF153A";
+
+    fn parse(line: &str) -> OGNStatusMessage {
+        OGNStatusMessage::from_str(line, Some(Utc.with_ymd_and_hms(2023, 2, 19, 0, 0, 0).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn writes_position_and_velocity_lines() {
+        let logged = Utc.with_ymd_and_hms(2023, 2, 19, 11, 24, 38).unwrap();
+        let [position, velocity] = lines(&parse(ICAO), logged, NonIcao::Skip).unwrap();
+        assert_eq!(
+            position,
+            "MSG,3,1,1,3E6DBA,1,2023/02/19,11:24:37.000,2023/02/19,11:24:38.000,,3503,,,48.54095,8.06425,,,0,0,0,0"
+        );
+        assert_eq!(velocity, "MSG,4,1,1,3E6DBA,1,2023/02/19,11:24:37.000,2023/02/19,11:24:38.000,,,80,206,,,-316,,0,0,0,0");
+
+        assert_eq!(lines(&parse(FLARM), logged, NonIcao::Skip), None);
+        assert!(lines(&parse(FLARM), logged, NonIcao::Raw).unwrap()[0].starts_with("MSG,3,1,1,DF153A,"));
+        let [_, velocity] = lines(&parse(FLARM), logged, NonIcao::Tilde).unwrap();
+        assert!(velocity.starts_with("MSG,4,1,1,~DF153A,") && velocity.contains(",,,50,301,,,,,0,0,0,0"), "{}", velocity);
+    }
+
+    #[tokio::test]
+    async fn clients_get_new_positions() {
+        let fleet = Arc::new(RwLock::new(LiveFleet::new(FleetState::new(FleetConfig::default()))));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = SbsConfig { addr: String::new(), non_icao: NonIcao::Skip };
+        tokio::spawn(accept(listener, fleet.clone(), Arc::new(config)));
+
+        let mut lines = BufReader::new(TcpStream::connect(addr).await.unwrap()).lines();
+        // wait for the client to be subscribed.
+        while fleet.read().unwrap().updates.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        let received = Utc.with_ymd_and_hms(2023, 2, 19, 11, 24, 38).unwrap();
+        fleet.write().unwrap().update(parse(FLARM), received);
+        fleet.write().unwrap().update(parse(ICAO), received);
+        assert!(lines.next_line().await.unwrap().unwrap().starts_with("MSG,3,1,1,3E6DBA,"));
+        assert!(lines.next_line().await.unwrap().unwrap().starts_with("MSG,4,1,1,3E6DBA,"));
+    }
+}
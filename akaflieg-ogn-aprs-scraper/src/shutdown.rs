@@ -0,0 +1,88 @@
+use std::io;
+use std::process;
+use std::time::Duration;
+
+use futures::future::join_all;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use tracing::{error, info, warn};
+
+// Cancel `shutdown` on the first SIGINT or SIGTERM, e.g. from `docker stop`. A second signal
+// exits right away, without waiting for the sinks.
+pub async fn watch_signals(shutdown: CancellationToken) -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+
+    let name = tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    };
+    info!("received {}, shutting down", name);
+    shutdown.cancel();
+
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
+    error!("received another signal, exiting without flushing the sinks");
+    process::exit(130);
+}
+
+// Wait for the sink tasks to write out what they have and close. Once `shutdown` is cancelled
+// they have `deadline` left to do so.
+pub async fn wait_for_sinks(
+    tasks: Vec<JoinHandle<()>>,
+    shutdown: &CancellationToken,
+    deadline: Duration,
+) -> Result<(), String> {
+    let sinks = join_all(tasks);
+    let results = tokio::select! {
+        results = sinks => results,
+        _ = async {
+            shutdown.cancelled().await;
+            sleep(deadline).await;
+        } => {
+            warn!("sinks did not finish within {:?}", deadline);
+            return Err(format!("sinks not flushed within the shutdown deadline of {:?}", deadline));
+        }
+    };
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if failed > 0 {
+        return Err(format!("{} sink tasks failed", failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::future::pending;
+
+    #[tokio::test(start_paused = true)]
+    async fn sinks_get_until_the_deadline_after_shutdown() {
+        let shutdown = CancellationToken::new();
+        let quick = tokio::spawn(sleep(Duration::from_secs(1)));
+        let slow = tokio::spawn(sleep(Duration::from_secs(60)));
+
+        // without a shutdown, slow sinks are waited for.
+        assert_eq!(wait_for_sinks(vec![quick, slow], &shutdown, Duration::from_secs(5)).await, Ok(()));
+
+        let stuck = tokio::spawn(pending::<()>());
+        shutdown.cancel();
+        let started = tokio::time::Instant::now();
+        assert!(wait_for_sinks(vec![stuck], &shutdown, Duration::from_secs(5)).await.is_err());
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn failed_sinks_are_reported() {
+        let failed = tokio::spawn(async { panic!("sink failed") });
+        let result = wait_for_sinks(vec![failed], &CancellationToken::new(), Duration::from_secs(5)).await;
+        assert_eq!(result, Err("1 sink tasks failed".to_owned()));
+    }
+}
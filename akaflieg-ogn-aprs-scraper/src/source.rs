@@ -0,0 +1,364 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::io;
+
+use aprs_is_client::{AprsIsClient, ClientConfig};
+use async_trait::async_trait;
+use futures::StreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc::Sender;
+use tokio::time::{sleep, timeout};
+
+use tracing::{debug, error, info, warn};
+
+use crate::adsb::{ReadsbJsonSource, SbsSource};
+use crate::influx_schema::receive_time;
+use crate::metrics::SOURCE_RECONNECTS;
+use crate::record::Packet;
+use crate::util;
+
+pub type SourceError = Box<dyn Error + Send + Sync>;
+
+// wait this long before reconnecting after a connection failed.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// An input of APRS lines. Every packet read is sent to the pipeline tagged with the source's name,
+// so several sources can feed the sinks at once.
+#[async_trait]
+pub trait Source: Send {
+    fn name(&self) -> &str;
+
+    // whether the source is one of several redundant upstreams carrying the same feed, like APRS-IS
+    // servers. Only those are counted for the lines they missed, see `dedup`.
+    fn is_upstream(&self) -> bool {
+        false
+    }
+
+    // read until the input is exhausted. Network sources reconnect on their own and never return.
+    async fn run(&mut self, aprs_tx: Sender<Packet>) -> Result<(), SourceError>;
+}
+
+// The sources to read from, as given in `SOURCES`: a comma separated list of `[<name>=]<source>`,
+// where `<source>` is one of
+//
+//   aprs-is:<host>:<port>     an APRS-IS server, logging in with `APRS_LOGIN_STR`
+//   ogn-decode:<host>:<port>  the APRS output port of a local `ogn-decode` receiver, usually 50001
+//   udp:<addr>:<port>         APRS lines sent as UDP datagrams to this address
+//   sbs:<host>:<port>         ADS-B positions from the SBS output of dump1090 or readsb, usually 30003
+//   readsb-json:<url>         ADS-B positions from the aircraft.json of readsb or tar1090
+//   file:<path>               the lines of a file
+//   stdin                     the lines of stdin
+//
+// Sources are named after their kind unless a name is given,
+// e.g. `SOURCES=glidernet=aprs-is:aprs.glidernet.org:14580,local=ogn-decode:localhost:50001`.
+// Names must be unique, they label the per source metrics.
+//
+// To bridge reconnects without gaps, connect to two APRS-IS servers at once, the duplicates are
+// removed before the sinks (see `dedup`):
+// `SOURCES=glidern1=aprs-is:glidern1.glidernet.org:14580,glidern2=aprs-is:glidern2.glidernet.org:14580`
+pub fn from_config(spec: &str, login_str: Option<&str>) -> Result<Vec<Box<dyn Source>>, String> {
+    let sources: Vec<Box<dyn Source>> = spec
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| parse_source(s, login_str))
+        .collect::<Result<_, _>>()?;
+
+    for (i, source) in sources.iter().enumerate() {
+        if sources[..i].iter().any(|other| other.name() == source.name()) {
+            return Err(format!("duplicate source name <{}>", source.name()));
+        }
+    }
+    Ok(sources)
+}
+
+fn parse_source(spec: &str, login_str: Option<&str>) -> Result<Box<dyn Source>, String> {
+    let (name, source) = match spec.split_once('=') {
+        Some((name, source)) => (Some(name), source),
+        None => (None, spec),
+    };
+    let (kind, arg) = source.split_once(':').unwrap_or((source, ""));
+    let name: Arc<str> = name.unwrap_or(kind).into();
+
+    Ok(match (kind, arg) {
+        ("aprs-is", addr) if !addr.is_empty() => {
+            let login_str = login_str.ok_or("aprs-is sources need APRS_LOGIN_STR")?;
+            let config = ClientConfig::from_login_line(addr, login_str)
+                .map_err(|err| format!("invalid APRS_LOGIN_STR: {}", err))?;
+            Box::new(AprsIsSource { name, config })
+        }
+        ("ogn-decode", addr) if !addr.is_empty() => Box::new(OgnDecodeSource { name, addr: addr.to_owned() }),
+        ("udp", addr) if !addr.is_empty() => Box::new(UdpSource { name, addr: addr.to_owned() }),
+        ("sbs", addr) if !addr.is_empty() => Box::new(SbsSource { name, addr: addr.to_owned() }),
+        ("readsb-json", url) if !url.is_empty() => {
+            Box::new(ReadsbJsonSource { name, url: url.to_owned(), interval: Duration::from_secs(1) })
+        }
+        ("file", path) if !path.is_empty() => Box::new(FileSource { name, path: path.into() }),
+        ("stdin", "") => Box::new(StdinSource { name }),
+        _ => return Err(format!("invalid source <{}>", spec)),
+    })
+}
+
+// An APRS-IS server, e.g. the OGN servers at aprs.glidernet.org:14580.
+// Login, keepalive and reconnects are handled by the `aprs-is-client` crate.
+pub struct AprsIsSource {
+    pub name: Arc<str>,
+    pub config: ClientConfig,
+}
+
+#[async_trait]
+impl Source for AprsIsSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_upstream(&self) -> bool {
+        true
+    }
+
+    async fn run(&mut self, aprs_tx: Sender<Packet>) -> Result<(), SourceError> {
+        let mut lines = Box::pin(AprsIsClient::new(self.config.clone()).lines());
+        let mut logins = 0;
+        while let Some(line) = lines.next().await {
+            let packet = packet(&self.name, line?.as_bytes());
+            // the client reconnects on its own, every login after the first is a reconnect.
+            if packet.line.starts_with("# logresp ") {
+                logins += 1;
+                if logins > 1 {
+                    SOURCE_RECONNECTS.with_label_values(&[&self.name]).inc();
+                }
+            }
+            debug!("[{}] (read) {}", self.name, util::format_for_display(packet.line.as_bytes()));
+            aprs_tx.send(packet).await?;
+        }
+        Ok(())
+    }
+}
+
+// The APRS output of a local `ogn-decode`, which serves the packets it decodes to any client
+// connecting to its port, without a login.
+pub struct OgnDecodeSource {
+    pub name: Arc<str>,
+    pub addr: String,
+}
+
+#[async_trait]
+impl Source for OgnDecodeSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&mut self, aprs_tx: Sender<Packet>) -> Result<(), SourceError> {
+        loop {
+            let result = match TcpStream::connect(&self.addr).await {
+                Ok(stream) => {
+                    info!("[{}] connected to {:?}", self.name, self.addr);
+                    // a receiver may well be quiet for a while when nothing is flying.
+                    read_lines(stream, &self.name, None, &aprs_tx).await
+                }
+                Err(err) => Err(err.into()),
+            };
+            if aprs_tx.is_closed() {
+                return Ok(());
+            }
+            if let Err(err) = result {
+                error!("[{}] {:?}", self.name, err);
+            }
+            warn!("[{}] disconnected, reconnecting in {:?}", self.name, RECONNECT_DELAY);
+            SOURCE_RECONNECTS.with_label_values(&[&self.name]).inc();
+            sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
+
+// APRS lines sent as UDP datagrams, one or more lines per datagram.
+pub struct UdpSource {
+    pub name: Arc<str>,
+    pub addr: String,
+}
+
+#[async_trait]
+impl Source for UdpSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&mut self, aprs_tx: Sender<Packet>) -> Result<(), SourceError> {
+        let socket = UdpSocket::bind(&self.addr).await?;
+        info!("[{}] listening on {:?}", self.name, socket.local_addr()?);
+
+        let mut buf = [0; 65536];
+        loop {
+            let (n, peer) = socket.recv_from(&mut buf).await?;
+            for line in buf[..n].split(|&byte| byte == b'\n') {
+                let line = line.trim_ascii_end();
+                if line.is_empty() {
+                    continue;
+                }
+                debug!("[{}] (read {} from {}) {}", self.name, line.len(), peer, util::format_for_display(line));
+                aprs_tx.send(packet(&self.name, line)).await?;
+            }
+        }
+    }
+}
+
+// the lines of a file, e.g. a recorded raw feed. Use `replay` to keep the recorded timing instead.
+pub struct FileSource {
+    pub name: Arc<str>,
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl Source for FileSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&mut self, aprs_tx: Sender<Packet>) -> Result<(), SourceError> {
+        let file = tokio::fs::File::open(&self.path).await?;
+        info!("[{}] reading {:?}", self.name, self.path);
+        read_lines(file, &self.name, None, &aprs_tx).await
+    }
+}
+
+pub struct StdinSource {
+    pub name: Arc<str>,
+}
+
+#[async_trait]
+impl Source for StdinSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&mut self, aprs_tx: Sender<Packet>) -> Result<(), SourceError> {
+        read_lines(tokio::io::stdin(), &self.name, None, &aprs_tx).await
+    }
+}
+
+// loop while reading all incoming APRS messages, one line at a time.
+// returns Ok(()) at the end of input or when no line arrived within `idle_timeout`.
+async fn read_lines(
+    reader: impl AsyncRead + Unpin,
+    name: &Arc<str>,
+    idle_timeout: Option<Duration>,
+    aprs_tx: &Sender<Packet>,
+) -> Result<(), SourceError> {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::with_capacity(512);
+    loop {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf);
+        let result = match idle_timeout {
+            Some(idle_timeout) => match timeout(idle_timeout, read).await {
+                Ok(result) => result,
+                Err(_) => return Ok(()),
+            },
+            None => read.await,
+        };
+
+        match result {
+            // we've reached the end of input, or the server closed the connection.
+            Ok(0) => return Ok(()),
+            // we've received a line of n characters, date it and hand it to the sinks.
+            Ok(n) => {
+                let packet = packet(name, buf.trim_ascii_end());
+                debug!("[{}] (read {}) {}", name, n, util::format_for_display(packet.line.as_bytes()));
+                aprs_tx.send(packet).await?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // we've received another kind of error, return the error and reconnect.
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+// a packet of the line as it was read, only log output escapes it. Lines are decoded as UTF-8,
+// the APRS-IS client does the same.
+pub fn packet(source: &Arc<str>, line: &[u8]) -> Packet {
+    Packet {
+        received: receive_time(),
+        source: source.clone(),
+        line: String::from_utf8_lossy(line).into_owned(),
+        position: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn sources_are_parsed_from_config() {
+        let sources = from_config(
+            "glidernet=aprs-is:aprs.glidernet.org:14580, ogn-decode:localhost:50001,stdin,sbs:localhost:30003,readsb-json:http://localhost/tar1090/data/aircraft.json",
+            Some("user TEST pass -1"),
+        )
+        .unwrap();
+        let names: Vec<&str> = sources.iter().map(|source| source.name()).collect();
+        assert_eq!(names, ["glidernet", "ogn-decode", "stdin", "sbs", "readsb-json"]);
+
+        assert!(from_config("aprs-is:aprs.glidernet.org:14580", None).is_err());
+        assert!(from_config("udp:0.0.0.0:8888,udp:0.0.0.0:8889", None).is_err());
+        assert!(from_config("udp", None).is_err());
+        assert!(from_config("carrier-pigeon:coop", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn aprs_is_source_logs_in_and_reads_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut login = String::new();
+            BufReader::new(&mut stream).read_line(&mut login).await.unwrap();
+            stream
+                .write_all(b"# aprsc 2.1.5\r\nFLRDF153A>APRS,qAS,RHST:/154006h gps2x2\r\n")
+                .await
+                .unwrap();
+            login
+        });
+
+        let (tx, mut rx) = mpsc::channel(8);
+        let mut source = AprsIsSource {
+            name: "test".into(),
+            config: ClientConfig::from_login_line(&addr, "user TEST pass -1 vers test 1.0").unwrap(),
+        };
+        let reader = tokio::spawn(async move { source.run(tx).await });
+
+        assert_eq!(server.await.unwrap(), "user TEST pass -1 vers test 1.0\r\n");
+        let first = rx.recv().await.unwrap();
+        assert_eq!(&*first.source, "test");
+        assert_eq!(first.line, "# aprsc 2.1.5");
+        let second = rx.recv().await.unwrap();
+        assert_eq!(second.line, "FLRDF153A>APRS,qAS,RHST:/154006h gps2x2");
+        assert!(second.received > first.received);
+        reader.abort();
+    }
+
+    #[tokio::test]
+    async fn udp_datagrams_are_split_into_lines() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let mut source = UdpSource { name: "udp".into(), addr: "127.0.0.1:0".to_owned() };
+
+        // bind first to learn the port the source will listen on.
+        let probe = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        source.addr = probe.local_addr().unwrap().to_string();
+        drop(probe);
+        let addr = source.addr.clone();
+        tokio::spawn(async move { source.run(tx).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.send_to(b"first\r\nsecond\n", &addr).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap().line, "first");
+        assert_eq!(rx.recv().await.unwrap().line, "second");
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// A bounded on-disk FIFO queue. Every pushed chunk is stored as its own segment file,
+// named `<sequence>-<records>.spool` so order and record counts survive a restart.
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    segments: VecDeque<Segment>,
+    next_seq: u64,
+}
+
+struct Segment {
+    path: PathBuf,
+    bytes: u64,
+    records: u64,
+}
+
+impl Spool {
+    // open the spool directory, picking up any segments left over from a previous run.
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if let Some((seq, records)) = parse_segment_name(&entry.path()) {
+                let bytes = entry.metadata()?.len();
+                segments.push((seq, Segment { path: entry.path(), bytes, records }));
+            }
+        }
+        segments.sort_by_key(|(seq, _)| *seq);
+
+        let next_seq = segments.last().map_or(0, |(seq, _)| seq + 1);
+        Ok(Spool {
+            dir,
+            max_bytes,
+            segments: segments.into_iter().map(|(_, segment)| segment).collect(),
+            next_seq,
+        })
+    }
+
+    // append a chunk to the end of the spool. If the spool grows over its size limit,
+    // the oldest segments are deleted; returns the number of records dropped that way.
+    pub fn push(&mut self, data: &[u8], records: u64) -> io::Result<u64> {
+        let path = self
+            .dir
+            .join(format!("{:020}-{}.spool", self.next_seq, records));
+        fs::write(&path, data)?;
+        self.next_seq += 1;
+        self.segments.push_back(Segment {
+            path,
+            bytes: data.len() as u64,
+            records,
+        });
+
+        let mut dropped = 0;
+        while self.bytes() > self.max_bytes {
+            match self.pop_front()? {
+                Some(records) => dropped += records,
+                None => break,
+            }
+        }
+        Ok(dropped)
+    }
+
+    // read the oldest chunk without removing it.
+    pub fn front(&self) -> Option<io::Result<Vec<u8>>> {
+        self.segments.front().map(|segment| fs::read(&segment.path))
+    }
+
+    // remove the oldest chunk, returns the number of records it held.
+    pub fn pop_front(&mut self) -> io::Result<Option<u64>> {
+        match self.segments.pop_front() {
+            Some(segment) => {
+                fs::remove_file(&segment.path)?;
+                Ok(Some(segment.records))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.bytes).sum()
+    }
+
+    pub fn records(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.records).sum()
+    }
+}
+
+fn parse_segment_name(path: &Path) -> Option<(u64, u64)> {
+    let name = path.file_name()?.to_str()?.strip_suffix(".spool")?;
+    let (seq, records) = name.split_once('-')?;
+    Some((seq.parse().ok()?, records.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spool_replays_in_order_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 1024).unwrap();
+        spool.push(b"first", 1).unwrap();
+        spool.push(b"second", 2).unwrap();
+
+        let mut spool = Spool::open(dir.path(), 1024).unwrap();
+        assert_eq!(spool.records(), 3);
+        assert_eq!(spool.front().unwrap().unwrap(), b"first");
+        assert_eq!(spool.pop_front().unwrap(), Some(1));
+        spool.push(b"third", 1).unwrap();
+        assert_eq!(spool.front().unwrap().unwrap(), b"second");
+        spool.pop_front().unwrap();
+        assert_eq!(spool.front().unwrap().unwrap(), b"third");
+    }
+
+    #[test]
+    fn spool_drops_oldest_when_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 10).unwrap();
+        assert_eq!(spool.push(b"123456", 4).unwrap(), 0);
+        assert_eq!(spool.push(b"789012", 2).unwrap(), 4);
+        assert_eq!(spool.bytes(), 6);
+        assert_eq!(spool.front().unwrap().unwrap(), b"789012");
+    }
+}
@@ -0,0 +1,65 @@
+use std::env;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use aprs_is_client::mock_server::{load_log, serve, MockServerConfig, Pace};
+
+const USAGE: &str = "usage: mock-aprs-server [--listen ADDR] [--speed N | --interval MS] \
+[--disconnect-every N] [--stall-every N] [--stall-secs S] [--split] [--merge N] [--loop] LOG...";
+
+fn value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    match args.next().map(|value| value.parse()) {
+        Some(Ok(value)) => value,
+        _ => exit(&format!("{} needs a numeric value", flag)),
+    }
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(2)
+}
+
+#[tokio::main]
+async fn main() {
+    let mut listen = "127.0.0.1:14580".to_owned();
+    let mut config = MockServerConfig::new(Vec::new());
+    config.faults.stall = Duration::from_secs(90);
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| exit("--listen needs an address")),
+            "--speed" => config.pace = Pace::Speed(value(&mut args, &arg)),
+            "--interval" => config.pace = Pace::Interval(Duration::from_millis(value(&mut args, &arg))),
+            "--disconnect-every" => config.faults.disconnect_every = Some(value(&mut args, &arg)),
+            "--stall-every" => config.faults.stall_every = Some(value(&mut args, &arg)),
+            "--stall-secs" => config.faults.stall = Duration::from_secs(value(&mut args, &arg)),
+            "--split" => config.faults.split_lines = true,
+            "--merge" => config.faults.merge_lines = value(&mut args, &arg),
+            "--loop" => config.repeat = true,
+            flag if flag.starts_with("--") => exit(&format!("unknown option {}", flag)),
+            log => match load_log(Path::new(log)) {
+                Ok(lines) => config.lines.extend(lines),
+                Err(err) => exit(&format!("can't read {}: {}", log, err)),
+            },
+        }
+    }
+    if config.lines.is_empty() {
+        exit("no log lines to send");
+    }
+
+    let listener = match TcpListener::bind(&listen).await {
+        Ok(listener) => listener,
+        Err(err) => exit(&format!("can't listen on {}: {}", listen, err)),
+    };
+    eprintln!("serving {} lines on {}", config.lines.len(), listen);
+    if let Err(err) = serve(listener, Arc::new(config)).await {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
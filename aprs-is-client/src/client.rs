@@ -0,0 +1,452 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use async_stream::stream;
+use chrono::Utc;
+use futures::{Stream, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::time::{interval_at, sleep, timeout, Instant, Interval};
+
+use log::{debug, info, warn};
+
+use crate::packet::{OGNPacket, ServerComment};
+use crate::passcode::passcode;
+
+// how to log in to an APRS-IS server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Passcode {
+    // `-1`, a read-only login, all we need for receiving.
+    ReadOnly,
+    // the passcode of the callsign, for a verified login.
+    Generate,
+    Given(i16),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientConfig {
+    // `host:port` of the server, e.g. `aprs.glidernet.org:14580`.
+    pub addr: String,
+    pub callsign: String,
+    pub passcode: Passcode,
+    // name and version of the software, sent with the login.
+    pub software: String,
+    // server-side filter, e.g. `r/48.5/8.0/100`, see http://www.aprs-is.net/javAPRSFilter.aspx
+    pub filter: Option<String>,
+    // send a comment line this often, so idle connections aren't dropped on the way.
+    pub keepalive_interval: Duration,
+    // servers send a keepalive comment every 20 seconds, so if nothing arrived for this long
+    // the connection is dead.
+    pub read_timeout: Duration,
+    pub reconnect: ReconnectPolicy,
+}
+
+impl ClientConfig {
+    pub fn new(addr: &str, callsign: &str) -> Self {
+        ClientConfig {
+            addr: addr.to_owned(),
+            callsign: callsign.to_owned(),
+            passcode: Passcode::ReadOnly,
+            software: format!("aprs-is-client {}", env!("CARGO_PKG_VERSION")),
+            filter: None,
+            keepalive_interval: Duration::from_secs(240),
+            read_timeout: Duration::from_secs(60),
+            reconnect: ReconnectPolicy::default(),
+        }
+    }
+
+    // read the config from a complete login line, e.g. `user N0CALL pass -1 vers app 1.0 filter r/48/8/50`.
+    pub fn from_login_line(addr: &str, line: &str) -> Result<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let value = |keyword: &str| -> Option<String> {
+            let start = words.iter().position(|word| *word == keyword)? + 1;
+            let len = words[start..]
+                .iter()
+                .position(|word| ["user", "pass", "vers", "filter"].contains(word))
+                .unwrap_or(words.len() - start);
+            Some(words[start..start + len].join(" ")).filter(|value| !value.is_empty())
+        };
+
+        let callsign = value("user").ok_or_else(|| anyhow!("login line has no user: {}", line))?;
+        let mut config = ClientConfig::new(addr, &callsign);
+        config.passcode = match value("pass").as_deref() {
+            None | Some("-1") => Passcode::ReadOnly,
+            Some(pass) => Passcode::Given(pass.parse()?),
+        };
+        if let Some(software) = value("vers") {
+            config.software = software;
+        }
+        config.filter = value("filter");
+        Ok(config)
+    }
+
+    pub fn login_line(&self, filter: Option<&str>) -> String {
+        let pass = match self.passcode {
+            Passcode::ReadOnly => -1,
+            Passcode::Generate => passcode(&self.callsign),
+            Passcode::Given(pass) => pass,
+        };
+        let mut line = format!("user {} pass {} vers {}", self.callsign, pass, self.software);
+        if let Some(filter) = filter {
+            line.push_str(" filter ");
+            line.push_str(filter);
+        }
+        line
+    }
+}
+
+// Wait `initial_delay` before reconnecting, doubling with every failed attempt up to `max_delay`.
+// Attempts count from the last line received, and after `max_attempts` the client gives up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    // the delay before the `attempt`th reconnect, or None if we should give up.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt > max) {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        Some(self.initial_delay.saturating_mul(factor).min(self.max_delay))
+    }
+}
+
+// An APRS-IS client that stays connected: it logs in, keeps the connection alive, and reconnects
+// after timeouts and errors according to its `ReconnectPolicy`.
+pub struct AprsIsClient {
+    config: ClientConfig,
+    filter: Arc<watch::Sender<Option<String>>>,
+}
+
+// changes the server-side filter of a running client.
+#[derive(Clone)]
+pub struct FilterHandle(Arc<watch::Sender<Option<String>>>);
+
+impl FilterHandle {
+    // sent to the server right away, and used for the login on later reconnects.
+    pub fn set_filter(&self, filter: &str) {
+        self.0.send_replace(Some(filter.to_owned()));
+    }
+}
+
+impl AprsIsClient {
+    pub fn new(config: ClientConfig) -> Self {
+        let (filter, _) = watch::channel(config.filter.clone());
+        AprsIsClient { config, filter: Arc::new(filter) }
+    }
+
+    pub fn filter_handle(&self) -> FilterHandle {
+        FilterHandle(self.filter.clone())
+    }
+
+    // All lines received, without line endings, including the server's `#` comments. The stream
+    // only ends, with an error, once the reconnect policy gives up.
+    pub fn lines(self) -> impl Stream<Item = Result<String>> + Send {
+        let AprsIsClient { config, filter } = self;
+        stream! {
+            let mut filter_rx = filter.subscribe();
+            let mut attempt = 0;
+            loop {
+                let current_filter = filter_rx.borrow_and_update().clone();
+                match Connection::open(&config, current_filter.as_deref()).await {
+                    Ok(mut connection) => loop {
+                        match connection.next_line(&mut filter_rx).await {
+                            Ok(Some(line)) => {
+                                attempt = 0;
+                                yield Ok(line);
+                            }
+                            Ok(None) => break,
+                            Err(err) => {
+                                warn!("connection to {} failed: {:?}", config.addr, err);
+                                break;
+                            }
+                        }
+                    },
+                    Err(err) => warn!("error connecting to {}: {:?}", config.addr, err),
+                }
+
+                attempt += 1;
+                match config.reconnect.delay(attempt) {
+                    Some(delay) => {
+                        info!("reconnecting to {} in {:?}", config.addr, delay);
+                        sleep(delay).await;
+                    }
+                    None => {
+                        yield Err(anyhow!("giving up on {} after {} attempts", config.addr, attempt - 1));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // All lines received, parsed. Packets are dated with the current day.
+    pub fn packets(self) -> impl Stream<Item = Result<OGNPacket>> + Send {
+        self.lines()
+            .map(|line| line.map(|line| OGNPacket::from_line(&line, Some(Utc::now()))))
+    }
+}
+
+struct Connection {
+    addr: String,
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    buf: Vec<u8>,
+    keepalive: Interval,
+    read_timeout: Duration,
+    expect_verified: bool,
+}
+
+impl Connection {
+    async fn open(config: &ClientConfig, filter: Option<&str>) -> Result<Self> {
+        let stream = TcpStream::connect(&config.addr).await?;
+        let (reader, writer) = stream.into_split();
+        info!("connected to {:?}", config.addr);
+
+        let period = config.keepalive_interval;
+        let mut connection = Connection {
+            addr: config.addr.clone(),
+            reader: BufReader::new(reader),
+            writer,
+            buf: Vec::with_capacity(512),
+            keepalive: interval_at(Instant::now() + period, period),
+            read_timeout: config.read_timeout,
+            expect_verified: config.passcode != Passcode::ReadOnly,
+        };
+        connection.send(&config.login_line(filter)).await?;
+        Ok(connection)
+    }
+
+    // the next line, or None if the server closed the connection or went quiet.
+    async fn next_line(&mut self, filter_rx: &mut watch::Receiver<Option<String>>) -> Result<Option<String>> {
+        loop {
+            tokio::select! {
+                // partially read lines stay in `buf` if another branch fires first.
+                read = timeout(self.read_timeout, self.reader.read_until(b'\n', &mut self.buf)) => {
+                    match read {
+                        Err(_) => {
+                            warn!("nothing received from {} for {:?}", self.addr, self.read_timeout);
+                            return Ok(None);
+                        }
+                        Ok(Ok(0)) => {
+                            warn!("{} closed the connection", self.addr);
+                            return Ok(None);
+                        }
+                        Ok(Ok(_)) => {
+                            let line = String::from_utf8_lossy(self.buf.trim_ascii_end()).into_owned();
+                            self.buf.clear();
+                            if line.starts_with('#') {
+                                self.handle_comment(&line);
+                            }
+                            return Ok(Some(line));
+                        }
+                        Ok(Err(err)) => return Err(err.into()),
+                    }
+                }
+                _ = self.keepalive.tick() => self.send("#keepalive").await?,
+                changed = filter_rx.changed() => {
+                    if changed.is_err() {
+                        bail!("filter handle closed");
+                    }
+                    let filter = filter_rx.borrow_and_update().clone();
+                    if let Some(filter) = filter {
+                        self.send(&format!("#filter {}", filter)).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_comment(&self, line: &str) {
+        match ServerComment::from_line(line) {
+            ServerComment::LogResp { callsign, verified, server } => {
+                info!("logged in to {} as {} ({})", server, callsign, if verified { "verified" } else { "unverified" });
+                if self.expect_verified && !verified {
+                    warn!("login as {} was not verified, check the passcode", callsign);
+                }
+            }
+            ServerComment::Keepalive { server, time, .. } => debug!("keepalive from {} at {}", server, time),
+            ServerComment::Other(line) => info!("{}: {}", self.addr, line),
+        }
+    }
+
+    async fn send(&mut self, line: &str) -> Result<()> {
+        debug!("(write) {}", line);
+        self.writer.write_all(format!("{}\r\n", line).as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    const POSITION: &str = "FLRDF153A>APRS,qAS,RHST:/154006h4858.10N\\00820.89E^301/050/A=001145 !W71! id22DF153A +119fpm +1.8rot 17.2dB 0e -2.0kHz gps2x2";
+
+    // A server that accepts `connections` clients one after another. It reports every line a
+    // client sends, and sends each client the lines it gets from `lines`, closing the connection
+    // on an empty line.
+    async fn mock_server(connections: usize) -> (String, mpsc::Receiver<String>, mpsc::Sender<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (received_tx, received_rx) = mpsc::channel(16);
+        let (lines_tx, mut lines_rx) = mpsc::channel::<String>(16);
+        tokio::spawn(async move {
+            for _ in 0..connections {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = stream.into_split();
+                let received_tx = received_tx.clone();
+                let reading = tokio::spawn(async move {
+                    let mut reader = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = reader.next_line().await {
+                        let _ = received_tx.send(line).await;
+                    }
+                });
+                while let Some(line) = lines_rx.recv().await {
+                    if line.is_empty() {
+                        break;
+                    }
+                    writer.write_all(format!("{}\r\n", line).as_bytes()).await.unwrap();
+                }
+                reading.abort();
+            }
+        });
+        (addr, received_rx, lines_tx)
+    }
+
+    fn config(addr: &str) -> ClientConfig {
+        let mut config = ClientConfig::new(addr, "N0CALL");
+        config.reconnect.initial_delay = Duration::from_millis(10);
+        config
+    }
+
+    #[test]
+    fn login_line_round_trips() {
+        let mut config = ClientConfig::new("localhost:14580", "N0CALL");
+        config.passcode = Passcode::Generate;
+        config.software = "test 1.0".to_owned();
+        config.filter = Some("r/48.5/8.0/100 t/p".to_owned());
+        let line = config.login_line(config.filter.as_deref());
+        assert_eq!(line, "user N0CALL pass 13023 vers test 1.0 filter r/48.5/8.0/100 t/p");
+
+        let parsed = ClientConfig::from_login_line("localhost:14580", &line).unwrap();
+        assert_eq!(parsed.passcode, Passcode::Given(13023));
+        assert_eq!(parsed.software, "test 1.0");
+        assert_eq!(parsed.filter.as_deref(), Some("r/48.5/8.0/100 t/p"));
+
+        let anonymous = ClientConfig::from_login_line("localhost:14580", "user N0CALL pass -1").unwrap();
+        assert_eq!(anonymous.passcode, Passcode::ReadOnly);
+        assert_eq!(anonymous.filter, None);
+        assert!(ClientConfig::from_login_line("localhost:14580", "pass -1").is_err());
+    }
+
+    #[test]
+    fn reconnect_delay_backs_off() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            max_attempts: Some(4),
+        };
+        let delays: Vec<Option<u64>> = (1..=5).map(|i| policy.delay(i).map(|d| d.as_secs())).collect();
+        assert_eq!(delays, [Some(1), Some(2), Some(4), Some(5), None]);
+    }
+
+    // streams only connect while polled, so drive the client in the background.
+    fn spawn<T: Send + 'static>(stream: impl Stream<Item = T> + Send + 'static) -> mpsc::Receiver<T> {
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut stream = Box::pin(stream);
+            while let Some(item) = stream.next().await {
+                if tx.send(item).await.is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    #[tokio::test]
+    async fn client_logs_in_and_changes_filter() {
+        let (addr, mut received, lines) = mock_server(1).await;
+        let mut config = config(&addr);
+        config.filter = Some("r/48/8/50".to_owned());
+        let client = AprsIsClient::new(config);
+        let filter = client.filter_handle();
+        let mut packets = spawn(client.packets());
+
+        assert_eq!(
+            received.recv().await.unwrap(),
+            format!("user N0CALL pass -1 vers aprs-is-client {} filter r/48/8/50", env!("CARGO_PKG_VERSION"))
+        );
+        lines.send("# logresp N0CALL unverified, server TEST".to_owned()).await.unwrap();
+        lines.send(POSITION.to_owned()).await.unwrap();
+        assert!(matches!(
+            packets.recv().await.unwrap().unwrap(),
+            OGNPacket::Comment(ServerComment::LogResp { verified: false, .. })
+        ));
+        assert!(matches!(packets.recv().await.unwrap().unwrap(), OGNPacket::Status(_)));
+
+        filter.set_filter("r/50/10/20");
+        assert_eq!(received.recv().await.unwrap(), "#filter r/50/10/20");
+    }
+
+    #[tokio::test]
+    async fn client_reconnects_and_sends_keepalives() {
+        let (addr, mut received, lines) = mock_server(2).await;
+        let mut config = config(&addr);
+        config.keepalive_interval = Duration::from_millis(50);
+        let mut client = spawn(AprsIsClient::new(config).lines());
+
+        assert!(received.recv().await.unwrap().starts_with("user N0CALL"));
+        lines.send("first".to_owned()).await.unwrap();
+        assert_eq!(client.recv().await.unwrap().unwrap(), "first");
+
+        // while waiting for lines, the client keeps the connection alive.
+        assert_eq!(received.recv().await.unwrap(), "#keepalive");
+
+        // the server drops the connection, the client logs in again.
+        lines.send(String::new()).await.unwrap();
+        lines.send("second".to_owned()).await.unwrap();
+        assert_eq!(client.recv().await.unwrap().unwrap(), "second");
+        let mut login = received.recv().await.unwrap();
+        while login == "#keepalive" {
+            login = received.recv().await.unwrap();
+        }
+        assert!(login.starts_with("user N0CALL"));
+    }
+
+    #[tokio::test]
+    async fn client_gives_up_after_max_attempts() {
+        // nothing listens on this port once the listener is dropped.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut config = config(&addr);
+        config.reconnect.max_attempts = Some(2);
+        let lines: Vec<Result<String>> = AprsIsClient::new(config).lines().collect().await;
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].as_ref().unwrap_err().to_string().contains("after 2 attempts"));
+    }
+}
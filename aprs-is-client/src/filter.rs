@@ -0,0 +1,128 @@
+use anyhow::{anyhow, bail, Result};
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+use ogn_fleet_state::geo::distance_km;
+
+// A server-side filter as sent with the login or a `#filter` command, see
+// http://www.aprs-is.net/javAPRSFilter.aspx. Supported are
+//
+//   r/lat/lon/dist   positions within dist km of lat/lon
+//   p/aa/bb/cc...    callsigns starting with one of the prefixes
+//   b/call1/call2... the given callsigns, `*` matches any suffix
+//
+// and their exclusions, e.g. `-p/ICA`. A line passes if it matches any filter and no exclusion.
+// An empty filter passes everything, like the full feed port of an APRS-IS server.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    include: Vec<Term>,
+    exclude: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Range { lat: f64, lon: f64, km: f64 },
+    Prefix(Vec<String>),
+    Budlist(Vec<String>),
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Self> {
+        let mut parsed = Filter::default();
+        for term in filter.split_whitespace() {
+            match term.strip_prefix('-') {
+                Some(term) => parsed.exclude.push(Term::parse(term)?),
+                None => parsed.include.push(Term::parse(term)?),
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn matches(&self, line: &str) -> bool {
+        // server comments reach every client.
+        if line.starts_with('#') {
+            return true;
+        }
+        let callsign = line.split('>').next().unwrap_or_default();
+        // only parse the position if a range term needs it.
+        let mut position = None;
+        let mut matches = |term: &Term| {
+            term.matches(callsign, || *position.get_or_insert_with(|| parse_position(line)))
+        };
+        let included = self.include.is_empty() || self.include.iter().any(&mut matches);
+        included && !self.exclude.iter().any(&mut matches)
+    }
+}
+
+impl Term {
+    fn parse(term: &str) -> Result<Self> {
+        let mut parts = term.split('/');
+        let kind = parts.next().unwrap_or_default();
+        let args: Vec<&str> = parts.filter(|arg| !arg.is_empty()).collect();
+        match kind {
+            "r" => {
+                let [lat, lon, km] = args[..] else {
+                    bail!("range filter needs lat/lon/dist: {}", term);
+                };
+                let number = |arg: &str| arg.parse::<f64>().map_err(|_| anyhow!("invalid number in {}", term));
+                Ok(Term::Range { lat: number(lat)?, lon: number(lon)?, km: number(km)? })
+            }
+            "p" | "b" if !args.is_empty() => {
+                let args = args.iter().map(|arg| arg.to_ascii_uppercase()).collect();
+                Ok(if kind == "p" { Term::Prefix(args) } else { Term::Budlist(args) })
+            }
+            _ => bail!("unsupported filter: {}", term),
+        }
+    }
+
+    fn matches(&self, callsign: &str, position: impl FnOnce() -> Option<(f64, f64)>) -> bool {
+        let callsign = callsign.to_ascii_uppercase();
+        match self {
+            Term::Range { lat, lon, km } => {
+                position().is_some_and(|position| distance_km((*lat, *lon), position) <= *km)
+            }
+            Term::Prefix(prefixes) => prefixes.iter().any(|prefix| callsign.starts_with(prefix.as_str())),
+            Term::Budlist(calls) => calls.iter().any(|call| match call.strip_suffix('*') {
+                Some(prefix) => callsign.starts_with(prefix),
+                None => callsign == *call,
+            }),
+        }
+    }
+}
+
+fn parse_position(line: &str) -> Option<(f64, f64)> {
+    let status = OGNStatusMessage::from_str(line, None).ok()?;
+    Some((status.position.latitude as f64, status.position.longitude as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a glider near Bad Rappenau, 48.97N 8.35E.
+    const FLARM: &str = "FLRDF153A>APRS,qAS,RHST:/154006h4858.10N\\00820.89E^301/050/A=001145 !W71! id22DF153A";
+
+    #[test]
+    fn range_prefix_and_budlist_filters_match() {
+        assert!(Filter::parse("r/49.0/8.4/10").unwrap().matches(FLARM));
+        assert!(!Filter::parse("r/52.5/13.4/100").unwrap().matches(FLARM));
+        assert!(Filter::parse("p/ICA/FLR").unwrap().matches(FLARM));
+        assert!(Filter::parse("b/OGN123 b/FLRDF*").unwrap().matches(FLARM));
+        assert!(!Filter::parse("b/FLRDF").unwrap().matches(FLARM));
+        assert!(Filter::parse("").unwrap().matches(FLARM));
+    }
+
+    #[test]
+    fn exclusions_win_and_comments_always_pass() {
+        let filter = Filter::parse("r/49.0/8.4/10 -p/FLR").unwrap();
+        assert!(!filter.matches(FLARM));
+        assert!(filter.matches("# aprsc 2.1.5"));
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        assert!(Filter::parse("r/49.0/8.4").is_err());
+        assert!(Filter::parse("r/north/8.4/10").is_err());
+        assert!(Filter::parse("p/").is_err());
+        assert!(Filter::parse("m/50").is_err());
+    }
+}
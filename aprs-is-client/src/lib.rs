@@ -0,0 +1,11 @@
+pub mod client;
+pub mod filter;
+pub mod mock_server;
+pub mod packet;
+pub mod passcode;
+pub mod server;
+
+pub use client::{AprsIsClient, ClientConfig, FilterHandle, Passcode, ReconnectPolicy};
+pub use filter::Filter;
+pub use packet::{OGNPacket, ServerComment};
+pub use server::ServerSession;
@@ -0,0 +1,321 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, sleep_until, Instant};
+
+use log::{info, warn};
+
+use crate::packet::ServerComment;
+use crate::server::{send, ServerSession};
+
+// how fast recorded lines are sent to clients.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    // as fast as the client reads.
+    Fast,
+    // a fixed delay before every line.
+    Interval(Duration),
+    // N times the recorded pace, taken from the dated comments in the log.
+    Speed(f64),
+}
+
+// Faults to inject into every connection, counted in lines sent to that client.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    // close the connection after every N lines.
+    pub disconnect_every: Option<usize>,
+    // stop sending anything, keepalives included, for `stall` after every N lines.
+    pub stall_every: Option<usize>,
+    pub stall: Duration,
+    // send every line in two writes, so it arrives in two TCP segments.
+    pub split_lines: bool,
+    // send this many lines in a single write.
+    pub merge_lines: usize,
+}
+
+pub struct MockServerConfig {
+    pub lines: Vec<String>,
+    pub pace: Pace,
+    pub faults: Faults,
+    // start over at the end of the log, instead of only sending keepalives.
+    pub repeat: bool,
+    pub server_name: String,
+    pub keepalive_interval: Duration,
+}
+
+impl MockServerConfig {
+    pub fn new(lines: Vec<String>) -> Self {
+        MockServerConfig {
+            lines,
+            pace: Pace::Fast,
+            faults: Faults::default(),
+            repeat: false,
+            server_name: "MOCK".to_owned(),
+            keepalive_interval: Duration::from_secs(20),
+        }
+    }
+}
+
+// read a recorded log such as `ogn-aprs-parser/assets/APRS.log`, one line per message.
+pub fn load_log(path: &Path) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        // older logs hold the escaped line endings of the raw TCP reads.
+        .map(|line| line.trim_end_matches("\\r\\n").to_owned())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+// A stand-in for an APRS-IS server, streaming a recorded log to every client that logs in.
+// Every connection starts at the beginning of the log. The log's own `#` comments aren't sent,
+// only used for pacing; instead the server sends its own banner, login response and keepalives.
+pub async fn serve(listener: TcpListener, config: Arc<MockServerConfig>) -> io::Result<()> {
+    let local_addr = listener.local_addr()?;
+    info!("mock APRS-IS server listening on {}", local_addr);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let config = config.clone();
+        tokio::spawn(async move {
+            match handle_client(stream, config).await {
+                Ok(()) => info!("{} disconnected", peer),
+                Err(err) => warn!("{} disconnected: {:?}", peer, err),
+            }
+        });
+    }
+}
+
+async fn handle_client(stream: TcpStream, config: Arc<MockServerConfig>) -> io::Result<()> {
+    let software = format!("mock-aprs-server {}", env!("CARGO_PKG_VERSION"));
+    let Some(mut session) = ServerSession::accept(stream, &software, &config.server_name).await? else {
+        return Ok(());
+    };
+    stream_log(&mut session, &config).await
+}
+
+async fn stream_log(session: &mut ServerSession, config: &MockServerConfig) -> io::Result<()> {
+    let faults = &config.faults;
+    let keepalive = || session.keepalive.line();
+    let mut out = Output { writer: &mut session.writer, split: faults.split_lines, merge: faults.merge_lines.max(1), merged: Vec::new(), pending: 0 };
+    let mut last_keepalive = Instant::now();
+    let filter = &session.filter;
+
+    let mut sent = 0;
+    loop {
+        // the recorded times start over with every pass through the log.
+        let mut start: Option<(DateTime<Utc>, Instant)> = None;
+        for line in &config.lines {
+            if last_keepalive.elapsed() >= config.keepalive_interval {
+                out.flush().await?;
+                send(out.writer, &keepalive()).await?;
+                last_keepalive = Instant::now();
+            }
+            // tell the client about refused filter commands.
+            while let Ok(reply) = session.replies.try_recv() {
+                out.flush().await?;
+                send(out.writer, &reply).await?;
+            }
+            if line.starts_with('#') {
+                if let (Pace::Speed(speed), Some(time)) = (config.pace, comment_time(line)) {
+                    let (start_time, start_instant) = *start.get_or_insert((time, Instant::now()));
+                    let elapsed = (time - start_time).to_std().unwrap_or_default();
+                    out.flush().await?;
+                    sleep_until(start_instant + elapsed.div_f64(speed)).await;
+                }
+                continue;
+            }
+            if let Pace::Interval(interval) = config.pace {
+                sleep(interval).await;
+            }
+            if !filter.borrow().matches(line) {
+                continue;
+            }
+
+            out.write_line(line).await?;
+            sent += 1;
+            if faults.disconnect_every.is_some_and(|n| sent % n == 0) {
+                return out.flush().await;
+            }
+            if faults.stall_every.is_some_and(|n| sent % n == 0) {
+                out.flush().await?;
+                sleep(faults.stall).await;
+            }
+        }
+        out.flush().await?;
+        if !config.repeat {
+            break;
+        }
+    }
+
+    // the log is done, only keep the connection alive.
+    loop {
+        sleep(config.keepalive_interval).await;
+        send(out.writer, &keepalive()).await?;
+    }
+}
+
+// the time of a dated comment in a recorded log, our own `# received` or an aprsc keepalive.
+fn comment_time(line: &str) -> Option<DateTime<Utc>> {
+    if let Some(time) = line.strip_prefix("# received ") {
+        return DateTime::parse_from_rfc3339(time).ok().map(|time| time.to_utc());
+    }
+    match ServerComment::from_line(line) {
+        ServerComment::Keepalive { time, .. } => Some(time),
+        _ => None,
+    }
+}
+
+// writes lines, splitting or merging them as the faults ask for.
+struct Output<'a> {
+    writer: &'a mut OwnedWriteHalf,
+    split: bool,
+    merge: usize,
+    merged: Vec<u8>,
+    pending: usize,
+}
+
+impl Output<'_> {
+    async fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.merged.extend_from_slice(line.as_bytes());
+        self.merged.extend_from_slice(b"\r\n");
+        self.pending += 1;
+        if self.pending >= self.merge {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        if self.merged.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::take(&mut self.merged);
+        self.pending = 0;
+        if self.split {
+            let (first, second) = data.split_at(data.len() / 2);
+            self.writer.write_all(first).await?;
+            self.writer.flush().await?;
+            // give the first half time to leave as its own segment.
+            sleep(Duration::from_millis(5)).await;
+            self.writer.write_all(second).await
+        } else {
+            self.writer.write_all(&data).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{Stream, StreamExt};
+
+    use crate::client::{AprsIsClient, ClientConfig, Passcode};
+
+    fn log() -> Vec<String> {
+        (0..10)
+            .map(|i| format!("FLRDF153A>APRS,qAS,RHST:/15400{}h4858.10N\\00820.89E^301/050/A=001145 !W71! id22DF153A", i))
+            .chain(["# aprsc 2.1.5-g8af3cdc 19 Feb 2023 11:18:57 GMT GLIDERN2 51.68.189.96:14580".to_owned()])
+            .chain(["ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\\00803.85E^206/080/A=003503 !W75! id213E6DBA".to_owned()])
+            .collect()
+    }
+
+    async fn start(config: MockServerConfig) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(listener, Arc::new(config)));
+        addr
+    }
+
+    fn client(addr: &str, filter: Option<&str>) -> ClientConfig {
+        let mut config = ClientConfig::new(addr, "N0CALL");
+        config.passcode = Passcode::Generate;
+        config.filter = filter.map(str::to_owned);
+        config.read_timeout = Duration::from_millis(200);
+        config.reconnect.initial_delay = Duration::from_millis(10);
+        config
+    }
+
+    // the lines received, without the server's comments, until `count` of them arrived.
+    async fn collect(lines: impl Stream<Item = anyhow::Result<String>>, count: usize) -> (Vec<String>, usize) {
+        let mut lines = Box::pin(lines);
+        let (mut received, mut logins) = (Vec::new(), 0);
+        while received.len() < count {
+            let line = lines.next().await.unwrap().unwrap();
+            if line.starts_with("# logresp") {
+                assert!(line.contains(" verified,"));
+                logins += 1;
+            } else if !line.starts_with('#') {
+                received.push(line);
+            }
+        }
+        (received, logins)
+    }
+
+    #[tokio::test]
+    async fn split_and_merged_lines_are_framed() {
+        let mut config = MockServerConfig::new(log());
+        config.faults.split_lines = true;
+        config.faults.merge_lines = 3;
+        let addr = start(config).await;
+
+        let (lines, _) = collect(AprsIsClient::new(client(&addr, None)).lines(), 11).await;
+        let expected: Vec<String> = log().into_iter().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(lines, expected);
+    }
+
+    #[tokio::test]
+    async fn client_reconnects_after_disconnects_and_stalls() {
+        let mut config = MockServerConfig::new(log());
+        config.faults.disconnect_every = Some(4);
+        let addr = start(config).await;
+        let (lines, logins) = collect(AprsIsClient::new(client(&addr, None)).lines(), 8).await;
+        assert_eq!(logins, 2);
+        assert_eq!(lines[4], lines[0]);
+
+        let mut config = MockServerConfig::new(log());
+        config.faults.stall_every = Some(4);
+        config.faults.stall = Duration::from_secs(1);
+        let addr = start(config).await;
+        let (lines, logins) = collect(AprsIsClient::new(client(&addr, None)).lines(), 8).await;
+        assert_eq!(logins, 2);
+        assert_eq!(lines[4], lines[0]);
+    }
+
+    #[tokio::test]
+    async fn login_filter_is_applied() {
+        let addr = start(MockServerConfig::new(log())).await;
+        let (lines, _) = collect(AprsIsClient::new(client(&addr, Some("p/ICA"))).lines(), 1).await;
+        assert!(lines[0].starts_with("ICA3E6DBA>"));
+    }
+
+    #[tokio::test]
+    async fn speed_follows_recorded_comments() {
+        let mut config = MockServerConfig::new(vec![
+            "# aprsc 2.1.5 19 Feb 2023 11:18:57 GMT GLIDERN2 51.68.189.96:14580".to_owned(),
+            "first".to_owned(),
+            "# aprsc 2.1.5 19 Feb 2023 11:19:17 GMT GLIDERN2 51.68.189.96:14580".to_owned(),
+            "second".to_owned(),
+        ]);
+        config.pace = Pace::Speed(100.0);
+        config.keepalive_interval = Duration::from_secs(3600);
+        let addr = start(config).await;
+
+        let mut config = client(&addr, None);
+        config.read_timeout = Duration::from_secs(60);
+        let mut lines = Box::pin(AprsIsClient::new(config).lines().filter(|line| {
+            std::future::ready(line.as_ref().is_ok_and(|line| !line.starts_with('#')))
+        }));
+        let started = Instant::now();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "first");
+        assert_eq!(lines.next().await.unwrap().unwrap(), "second");
+        // the 20 seconds between the comments, 100 times faster.
+        assert!(started.elapsed() >= Duration::from_millis(190));
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+
+// A line received from an APRS-IS server.
+#[derive(Debug, PartialEq)]
+pub enum OGNPacket {
+    // a position or status report the parser understands.
+    Status(Box<OGNStatusMessage>),
+    // a `#` comment sent by the server itself.
+    Comment(ServerComment),
+    // any other line, e.g. receiver status or a format the parser doesn't know yet.
+    Unparsed(String),
+}
+
+impl OGNPacket {
+    // `date` dates the packet, which only carries a time of day, and defaults to today.
+    pub fn from_line(line: &str, date: Option<DateTime<Utc>>) -> Self {
+        if line.starts_with('#') {
+            return OGNPacket::Comment(ServerComment::from_line(line));
+        }
+        match OGNStatusMessage::from_str(line, date) {
+            Ok(status) => OGNPacket::Status(Box::new(status)),
+            Err(_) => OGNPacket::Unparsed(line.to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ServerComment {
+    // the answer to our login, e.g. `# logresp N0CALL unverified, server GLIDERN2`
+    LogResp {
+        callsign: String,
+        verified: bool,
+        server: String,
+    },
+    // sent every 20 seconds by aprsc, e.g.
+    // `# aprsc 2.1.5-g8af3cdc 19 Feb 2023 11:18:57 GMT GLIDERN2 51.68.189.96:14580`
+    Keepalive {
+        software: String,
+        time: DateTime<Utc>,
+        server: String,
+    },
+    Other(String),
+}
+
+impl ServerComment {
+    pub fn from_line(line: &str) -> Self {
+        Self::parse_logresp(line)
+            .or_else(|| Self::parse_keepalive(line))
+            .unwrap_or_else(|| ServerComment::Other(line.to_owned()))
+    }
+
+    fn parse_logresp(line: &str) -> Option<Self> {
+        let rest = line.strip_prefix("# logresp ")?;
+        let (callsign, rest) = rest.split_once(' ')?;
+        let (status, server) = rest.split_once(", server ")?;
+        Some(ServerComment::LogResp {
+            callsign: callsign.to_owned(),
+            verified: status == "verified",
+            server: server.trim().to_owned(),
+        })
+    }
+
+    fn parse_keepalive(line: &str) -> Option<Self> {
+        let rest = line.strip_prefix("# ")?;
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        if words.len() < 8 || words[6] != "GMT" {
+            return None;
+        }
+        let time = NaiveDateTime::parse_from_str(&words[2..6].join(" "), "%d %b %Y %H:%M:%S").ok()?;
+        Some(ServerComment::Keepalive {
+            software: format!("{} {}", words[0], words[1]),
+            time: time.and_utc(),
+            server: words[7].to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn server_comments_are_recognized() {
+        assert_eq!(
+            ServerComment::from_line("# logresp N0CALL unverified, server GLIDERN2"),
+            ServerComment::LogResp {
+                callsign: "N0CALL".to_owned(),
+                verified: false,
+                server: "GLIDERN2".to_owned()
+            }
+        );
+        assert_eq!(
+            ServerComment::from_line(
+                "# aprsc 2.1.5-g8af3cdc 19 Feb 2023 11:18:57 GMT GLIDERN2 51.68.189.96:14580"
+            ),
+            ServerComment::Keepalive {
+                software: "aprsc 2.1.5-g8af3cdc".to_owned(),
+                time: Utc.with_ymd_and_hms(2023, 2, 19, 11, 18, 57).unwrap(),
+                server: "GLIDERN2".to_owned()
+            }
+        );
+        assert_eq!(
+            ServerComment::from_line("# aprsc 2.1.5-g8af3cdc"),
+            ServerComment::Other("# aprsc 2.1.5-g8af3cdc".to_owned())
+        );
+    }
+
+    #[test]
+    fn lines_are_classified() {
+        let status = "FLRDF153A>APRS,qAS,RHST:/154006h4858.10N\\00820.89E^301/050/A=001145 !W71! id22DF153A +119fpm +1.8rot 17.2dB 0e -2.0kHz gps2x2";
+        assert!(matches!(OGNPacket::from_line(status, None), OGNPacket::Status(_)));
+        assert!(matches!(OGNPacket::from_line("# aprsc 2.1.5", None), OGNPacket::Comment(_)));
+        assert_eq!(
+            OGNPacket::from_line("not aprs", None),
+            OGNPacket::Unparsed("not aprs".to_owned())
+        );
+    }
+}
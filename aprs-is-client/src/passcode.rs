@@ -0,0 +1,25 @@
+// The APRS-IS passcode of a callsign, which servers require for a verified (read-write) login.
+// The SSID is not part of the hash, so `N0CALL-1` has the same passcode as `N0CALL`.
+pub fn passcode(callsign: &str) -> i16 {
+    let call = callsign.split('-').next().unwrap_or_default().to_ascii_uppercase();
+
+    let mut hash: u16 = 0x73e2;
+    for pair in call.as_bytes().chunks(2) {
+        hash ^= (pair[0] as u16) << 8;
+        if let Some(&low) = pair.get(1) {
+            hash ^= low as u16;
+        }
+    }
+    (hash & 0x7fff) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passcode_matches_known_values() {
+        assert_eq!(passcode("N0CALL"), 13023);
+        assert_eq!(passcode("n0call-10"), 13023);
+    }
+}
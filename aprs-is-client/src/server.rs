@@ -0,0 +1,179 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use log::info;
+
+use crate::client::{ClientConfig, Passcode};
+use crate::filter::Filter;
+use crate::passcode::passcode;
+
+// how long a client may take to send its login.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The server side of an APRS-IS connection: the banner, the login and its response, and the
+// `#filter` commands the client sends later on. Anything else clients send is ignored.
+//
+// A login with a filter we can't parse is refused, and so is a `#filter` command, which keeps the
+// last filter; a client never gets more than it asked for.
+pub struct ServerSession {
+    pub callsign: String,
+    pub verified: bool,
+    // the client's current filter.
+    pub filter: watch::Receiver<Filter>,
+    // comments for the client, e.g. about a refused `#filter`. Closed once the client closed the
+    // connection.
+    pub replies: mpsc::Receiver<String>,
+    pub writer: OwnedWriteHalf,
+    pub keepalive: Keepalive,
+    commands: JoinHandle<()>,
+}
+
+// what a server sends to keep idle connections alive.
+pub struct Keepalive {
+    software: String,
+    server_name: String,
+    local_addr: SocketAddr,
+}
+
+impl Keepalive {
+    // like aprsc's, e.g. `# aprsc 2.1.5-g8af3cdc 19 Feb 2023 11:18:57 GMT GLIDERN2 51.68.189.96:14580`.
+    pub fn line(&self) -> String {
+        format!(
+            "# {} {} {} {}",
+            self.software,
+            Utc::now().format("%d %b %Y %H:%M:%S GMT"),
+            self.server_name,
+            self.local_addr
+        )
+    }
+}
+
+impl ServerSession {
+    // Sends the banner and waits for the login. None if the client didn't log in properly, it was
+    // told why then.
+    pub async fn accept(stream: TcpStream, software: &str, server_name: &str) -> io::Result<Option<Self>> {
+        let local_addr = stream.local_addr()?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader).lines();
+        send(&mut writer, &format!("# {}", software)).await?;
+
+        // the first line must be the login.
+        let login = match timeout(LOGIN_TIMEOUT, reader.next_line()).await {
+            Ok(Ok(Some(login))) => login,
+            _ => return Ok(None),
+        };
+        let client = match ClientConfig::from_login_line("", &login) {
+            Ok(client) if login.starts_with("user ") => client,
+            _ => {
+                send(&mut writer, "# invalid login").await?;
+                return Ok(None);
+            }
+        };
+        let filter = match Filter::parse(client.filter.as_deref().unwrap_or_default()) {
+            Ok(filter) => filter,
+            Err(err) => {
+                send(&mut writer, &format!("# invalid filter: {}", err)).await?;
+                return Ok(None);
+            }
+        };
+        let verified = client.passcode == Passcode::Given(passcode(&client.callsign));
+        let status = if verified { "verified" } else { "unverified" };
+        send(&mut writer, &format!("# logresp {} {}, server {}", client.callsign, status, server_name)).await?;
+        info!("{} logged in with filter {:?}", client.callsign, client.filter);
+
+        let (filter_tx, filter_rx) = watch::channel(filter);
+        let (replies_tx, replies_rx) = mpsc::channel(8);
+        let commands = tokio::spawn(async move {
+            while let Ok(Some(line)) = reader.next_line().await {
+                let Some(filter) = line.strip_prefix("#filter") else { continue };
+                match Filter::parse(filter) {
+                    Ok(filter) => {
+                        filter_tx.send_replace(filter);
+                    }
+                    Err(err) => {
+                        if replies_tx.send(format!("# invalid filter: {}", err)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Some(ServerSession {
+            callsign: client.callsign,
+            verified,
+            filter: filter_rx,
+            replies: replies_rx,
+            writer,
+            keepalive: Keepalive { software: software.to_owned(), server_name: server_name.to_owned(), local_addr },
+            commands,
+        }))
+    }
+
+    pub async fn send(&mut self, line: &str) -> io::Result<()> {
+        send(&mut self.writer, line).await
+    }
+}
+
+impl Drop for ServerSession {
+    fn drop(&mut self) {
+        self.commands.abort();
+    }
+}
+
+pub async fn send(writer: &mut OwnedWriteHalf, line: &str) -> io::Result<()> {
+    writer.write_all(format!("{}\r\n", line).as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::Lines;
+    use tokio::net::tcp::OwnedReadHalf;
+    use tokio::net::TcpListener;
+
+    // a session for each login sent, with what the client read until then.
+    async fn login(line: &str) -> (Option<ServerSession>, Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (reader, mut writer) = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap().into_split();
+        send(&mut writer, line).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let session = ServerSession::accept(stream, "test-server 1.0", "TEST").await.unwrap();
+        let mut reader = BufReader::new(reader).lines();
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), "# test-server 1.0");
+        (session, reader, writer)
+    }
+
+    #[tokio::test]
+    async fn invalid_filters_are_refused() {
+        let (session, mut reader, _) = login("user N0CALL pass -1 vers test 1.0 filter r/48.9").await;
+        assert!(session.is_none());
+        assert!(reader.next_line().await.unwrap().unwrap().starts_with("# invalid filter: "));
+
+        let (session, mut reader, mut writer) = login("user N0CALL pass -1 vers test 1.0 filter p/FLR").await;
+        let mut session = session.unwrap();
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), "# logresp N0CALL unverified, server TEST");
+        send(&mut writer, "#filter x/1").await.unwrap();
+        assert!(session.replies.recv().await.unwrap().starts_with("# invalid filter: "));
+        assert!(session.filter.borrow().matches("FLRDF153A>APRS,qAS,RHST:/154006h"));
+        assert!(!session.filter.borrow().matches("ICA3E6DBA>APRS,qAS,RHST:/154006h"));
+
+        send(&mut writer, "#filter p/ICA").await.unwrap();
+        session.filter.changed().await.unwrap();
+        assert!(session.filter.borrow().matches("ICA3E6DBA>APRS,qAS,RHST:/154006h"));
+
+        // the replies close with the connection.
+        drop(writer);
+        assert_eq!(session.replies.recv().await, None);
+    }
+}
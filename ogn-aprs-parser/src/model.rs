@@ -1,3 +1,5 @@
 pub mod ogn_status_message;
+pub mod ogn_receiver_beacon;
 pub mod ogn_object_position;
-pub mod ogn_object_velocity;
\ No newline at end of file
+pub mod ogn_object_velocity;
+pub mod ogn_precision;
\ No newline at end of file
@@ -3,7 +3,8 @@ use crate::parser::{
     extensions::position_precision::ParsedPositionPrecision, position::ParsedPosition,
 };
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OGNObjectPosition {
     pub latitude: f32,
     pub longitude: f32,
@@ -1,6 +1,7 @@
 use crate::ogn::utils::{fpm_to_m_s, knots_to_m_s, turn_rate_to_rad_s};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OGNObjectVelocity {
     // Velocity in m/s
     pub horizontal: f32,
@@ -15,14 +16,8 @@ impl OGNObjectVelocity {
     pub fn new(speed: u32, altitude_rate: Option<i32>, rotation_rate: Option<f32>) -> Self {
         OGNObjectVelocity {
             horizontal: knots_to_m_s(speed as f32),
-            vertical: match altitude_rate {
-                Some(rate) => Some(fpm_to_m_s(rate as f32)),
-                None => None,
-            },
-            rotation: match rotation_rate {
-                Some(rate) => Some(turn_rate_to_rad_s(rate)),
-                None => None,
-            },
+            vertical: altitude_rate.map(|rate| fpm_to_m_s(rate as f32)),
+            rotation: rotation_rate.map(turn_rate_to_rad_s),
         }
     }
 }
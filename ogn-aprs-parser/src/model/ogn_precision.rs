@@ -0,0 +1,37 @@
+use crate::parser::extensions::{
+    gps_resolution::ParsedGPSResolution, position_precision::ParsedPositionPrecision,
+};
+
+// GPS resolution as reported by the tracker, in metres.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OGNGPSResolution {
+    pub horizontal: u32,
+    pub vertical: u32,
+}
+
+impl OGNGPSResolution {
+    pub fn from(parsed: &ParsedGPSResolution) -> Self {
+        OGNGPSResolution {
+            horizontal: parsed.horizontal,
+            vertical: parsed.vertical,
+        }
+    }
+}
+
+// Additional position precision, in minutes. Already included in the decoded position.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OGNPositionPrecision {
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+impl OGNPositionPrecision {
+    pub fn from(parsed: &ParsedPositionPrecision) -> Self {
+        OGNPositionPrecision {
+            latitude: parsed.latitude,
+            longitude: parsed.longitude,
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use anyhow::Result;
+
+use super::ogn_object_position::OGNObjectPosition;
+use crate::ogn::utils::parsed_time_to_datetime;
+use crate::parser::parse::{parse_receiver_beacon_str, ParsedReceiverBeacon};
+
+// The position a receiver reports about itself. These aren't aircraft, so they don't parse as
+// `OGNStatusMessage`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OGNReceiverBeacon {
+    pub timestamp: DateTime<Utc>,
+    pub aprs_callsign: String,
+    pub aprs_path: String,
+    pub position: OGNObjectPosition,
+}
+
+impl OGNReceiverBeacon {
+    pub fn from_str(string: &str, date: Option<DateTime<Utc>>) -> Result<Self> {
+        Ok(OGNReceiverBeacon::from_parsed(parse_receiver_beacon_str(string)?, date))
+    }
+    // optionally accepts a date to process historical messages, otherwise assumes today.
+    pub fn from_parsed(parsed: ParsedReceiverBeacon, date: Option<DateTime<Utc>>) -> Self {
+        OGNReceiverBeacon {
+            timestamp: parsed_time_to_datetime(parsed.time, date),
+            aprs_callsign: parsed.callsign.to_owned(),
+            aprs_path: parsed.path.to_owned(),
+            position: OGNObjectPosition::from(&parsed.position, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    #[test]
+    fn creating_receiver_beacon_from_string_works() {
+        let test = "Bad_Rappe>OGNSDR,TCPIP*,qAC,GLIDERN2:/154005h4858.00NI00820.00E&/A=000500";
+        let date = Utc.with_ymd_and_hms(2023, 2, 19, 0, 0, 0).unwrap();
+        let beacon = OGNReceiverBeacon::from_str(test, Some(date)).unwrap();
+        assert_eq!(beacon.aprs_callsign, "Bad_Rappe");
+        assert_eq!(beacon.timestamp, Utc.with_ymd_and_hms(2023, 2, 19, 15, 40, 5).unwrap());
+        assert!((beacon.position.latitude - 48.966667).abs() < 0.0001);
+        assert!((beacon.position.altitude - 152.4).abs() < 0.01);
+    }
+}
@@ -1,15 +1,19 @@
 use chrono::{DateTime, Utc};
-use chrono::prelude::*;
 use anyhow::Result;
 
-use super::{ogn_object_position::OGNObjectPosition, ogn_object_velocity::OGNObjectVelocity};
+use super::{
+    ogn_object_position::OGNObjectPosition,
+    ogn_object_velocity::OGNObjectVelocity,
+    ogn_precision::{OGNGPSResolution, OGNPositionPrecision},
+};
 use crate::ogn::{
     aprs_message_types::APRSMessageType, ogn_address_type::AddressType,
     ogn_aircraft_types::AircraftType, ogn_flags::OGNFlags, utils::parsed_time_to_datetime,
 };
 use crate::parser::parse::{parse_str, ParsedAPRSMessage};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OGNStatusMessage {
     pub aircraft_id: Option<String>,
     pub timestamp: DateTime<Utc>,
@@ -22,6 +26,10 @@ pub struct OGNStatusMessage {
     pub ogn_flags: Option<OGNFlags>,
     pub address_type: Option<AddressType>,
     pub reception: Option<f32>,
+    pub bit_errors: Option<u32>,
+    pub frequency_offset: Option<f32>,
+    pub gps_resolution: Option<OGNGPSResolution>,
+    pub position_precision: Option<OGNPositionPrecision>,
 }
 
 impl OGNStatusMessage {
@@ -47,6 +55,11 @@ impl OGNStatusMessage {
                     None,
                 )
             };
+        let position_precision = parsed
+            .extensions
+            .position_precision
+            .as_ref()
+            .map(OGNPositionPrecision::from);
         OGNStatusMessage {
             aprs_callsign: parsed.callsign.to_owned(),
             aprs_path: parsed.path.to_owned(),
@@ -65,7 +78,15 @@ impl OGNStatusMessage {
             aircraft_type,
             ogn_flags,
             address_type,
-            reception: parsed.extensions.reception
+            reception: parsed.extensions.reception,
+            bit_errors: parsed.extensions.bit_errors,
+            frequency_offset: parsed.extensions.frequency_offset,
+            gps_resolution: parsed
+                .extensions
+                .gps_resolution
+                .as_ref()
+                .map(OGNGPSResolution::from),
+            position_precision,
         }
     }
 }
@@ -73,6 +94,7 @@ impl OGNStatusMessage {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::prelude::*;
 
     #[test]
     fn creating_status_message_from_string_works() {
@@ -94,12 +116,16 @@ mod tests {
                 aprs_callsign: "ICA3E6DBA".to_owned(),
                 aprs_path: "APRS,qAS,Schwend".to_owned(),
                 aprs_type: APRSMessageType::PositionWithTimestamp,
-                position: OGNObjectPosition{ latitude: 48.54095, longitude: 8.540916, heading: 206.0, altitude: 1067.7144 },
+                position: OGNObjectPosition{ latitude: 48.54095, longitude: 8.06425, heading: 206.0, altitude: 1067.7144 },
                 velocity: OGNObjectVelocity { horizontal: 41.155556, vertical: Some(-1.60528), rotation: Some(0.0052359877) },
                 aircraft_type: AircraftType::PoweredAircraft,
                 ogn_flags: Some(OGNFlags{ stealth_mode: false, no_tracking_mode: false }),
                 address_type: Some(AddressType::ICAO),
-                reception: Some(9.8)
+                reception: Some(9.8),
+                bit_errors: Some(6),
+                frequency_offset: Some(-4.5),
+                gps_resolution: Some(OGNGPSResolution { horizontal: 2, vertical: 2 }),
+                position_precision: Some(OGNPositionPrecision { latitude: 0.007, longitude: 0.005 })
             }
         );
     }
@@ -1,6 +1,7 @@
 use crate::parser::position::ParsedSymbol;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AircraftType {
     Other,
     Glider,
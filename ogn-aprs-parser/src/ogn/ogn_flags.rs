@@ -1,4 +1,5 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OGNFlags {
     pub stealth_mode: bool,     // should never be true
     pub no_tracking_mode: bool, // request from airplane not to be tracked
@@ -29,9 +29,9 @@ pub fn parsed_time_to_datetime(
         date.year(),
         date.month(),
         date.day(),
-        parsed_time.elements.0.try_into().unwrap_or(0),
-        parsed_time.elements.1.try_into().unwrap_or(0),
-        parsed_time.elements.2.try_into().unwrap_or(0),
+        parsed_time.elements.0,
+        parsed_time.elements.1,
+        parsed_time.elements.2,
     )
     .unwrap()
 }
@@ -79,6 +79,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::excessive_precision)]
     fn knots_to_m_s_returns_accurate_result() {
         assert_eq!(knots_to_m_s(1.0), 0.5144444444444);
     }
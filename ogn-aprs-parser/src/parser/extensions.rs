@@ -46,7 +46,7 @@ enum Extensions<'a> {
     Unknown(&'a str),
 }
 
-pub fn parse_extensions(i: &str) -> IResult<&str, ParsedExtensions> {
+pub fn parse_extensions(i: &str) -> IResult<&str, ParsedExtensions<'_>> {
     use Extensions::*;
     let (str, res) = many0(alt((
         map(parse_ext_position_precision, PositionPrecision),
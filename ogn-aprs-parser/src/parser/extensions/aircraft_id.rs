@@ -12,12 +12,12 @@ pub struct ParsedAircraftID<'a> {
     pub id: &'a str,
 }
 
-pub fn parse_ext_aircraft_id(i: &str) -> IResult<&str, ParsedAircraftID> {
+pub fn parse_ext_aircraft_id(i: &str) -> IResult<&str, ParsedAircraftID<'_>> {
     let (str, res) = preceded(
         pair(multispace1, tag("id")),
         pair(
-            map_res(take(2 as u32), |hex| <u32>::from_str_radix(hex, 16)),
-            take(6 as u32),
+            map_res(take(2_u32), |hex| <u32>::from_str_radix(hex, 16)),
+            take(6_u32),
         ),
     )(i)?;
 
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use nom::{sequence::tuple, IResult};
+use nom::{combinator::verify, sequence::tuple, IResult};
 
 use crate::parser::*;
 
@@ -14,15 +14,36 @@ pub struct ParsedAPRSMessage<'a> {
     pub extensions: extensions::ParsedExtensions<'a>,
 }
 
-pub fn parse_str(i: &str) -> Result<ParsedAPRSMessage> {
+// the position beacon of a receiver, e.g.
+// `Bad_Rappe>OGNSDR,TCPIP*,qAC,GLIDERN2:/154005h4858.00NI00820.00E&/A=000500`
+#[derive(Debug, PartialEq)]
+pub struct ParsedReceiverBeacon<'a> {
+    pub callsign: &'a str,
+    pub path: &'a str,
+    pub time: time::ParsedTime,
+    pub position: position::ParsedPosition,
+    pub symbol: position::ParsedSymbol<'a>,
+}
+
+pub fn parse_str(i: &str) -> Result<ParsedAPRSMessage<'_>> {
     let (_, result) = aircraft_status_message(i).map_err(|e| e.to_owned())?;
     Ok(result)
 }
 
-fn aircraft_status_message(i: &str) -> IResult<&str, ParsedAPRSMessage> {
+pub fn parse_receiver_beacon_str(i: &str) -> Result<ParsedReceiverBeacon<'_>> {
+    let (_, result) = receiver_beacon(i).map_err(|e| e.to_owned())?;
+    Ok(result)
+}
+
+// receivers send their beacons to `OGNSDR`.
+fn is_receiver_path(path: &str) -> bool {
+    path.starts_with("OGNSDR")
+}
+
+fn aircraft_status_message(i: &str) -> IResult<&str, ParsedAPRSMessage<'_>> {
     let (str, (cs, pa, ty, ti, (pos, sym), ext)) = tuple((
         aprs::parse_callsign,
-        aprs::parse_path,
+        verify(aprs::parse_path, |path: &str| !is_receiver_path(path)),
         aprs::parse_msg_type,
         time::parse_time,
         position::parse_position_and_type,
@@ -42,6 +63,27 @@ fn aircraft_status_message(i: &str) -> IResult<&str, ParsedAPRSMessage> {
     ))
 }
 
+fn receiver_beacon(i: &str) -> IResult<&str, ParsedReceiverBeacon<'_>> {
+    // the rest is the receiver's software version and the like.
+    let (str, (cs, pa, _, ti, (pos, sym))) = tuple((
+        aprs::parse_callsign,
+        verify(aprs::parse_path, is_receiver_path),
+        aprs::parse_msg_type,
+        time::parse_time,
+        position::parse_beacon_position,
+    ))(i)?;
+    Ok((
+        str,
+        ParsedReceiverBeacon {
+            callsign: cs,
+            path: pa,
+            time: ti,
+            position: pos,
+            symbol: sym,
+        },
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -103,6 +145,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn receiver_beacons_are_no_aircraft_status_messages() {
+        let test = "EDER>OGNSDR,TCPIP*,qAC,GLIDERN1:/090022h4952.95NI01014.94E&000/000/A=000919 v0.2.8";
+        assert!(aircraft_status_message(test).is_err());
+        let (rest, beacon) = receiver_beacon(test).unwrap();
+        assert_eq!(rest, " v0.2.8");
+        assert_eq!((beacon.callsign, beacon.path), ("EDER", "OGNSDR,TCPIP*,qAC,GLIDERN1"));
+        assert_eq!(beacon.position.altitude, 919);
+
+        let test = "ICA3E6DBA>APRS,qAS,Schwend:/112437h4832.45N\\00803.85E^206/080/A=003503 !W75!";
+        assert!(receiver_beacon(test).is_err());
+    }
+
+    // not enabled yet, the expected message is still the one of the test above.
+    #[allow(dead_code)]
     fn parsing_aprs_aircraft_status_message2_works() {
         let test = "ICA3D2F0C>OGFLR,qAS,edfz2:/142313h4956.68N\\00808.73E^260/100/A=001614 !W17! id213D2F0C -197fpm +0.0rot 37.2dB +6.0kHz gps3x4";
         assert_eq!(
@@ -1,6 +1,7 @@
 use nom::{
     bytes::complete::{tag, take},
     character::complete::one_of,
+    combinator::opt,
     sequence::{preceded, tuple},
     multi::many0,
     IResult,
@@ -77,16 +78,16 @@ fn parse_longitude(i: &str) -> IResult<&str, ParsedDegrees> {
 
 pub type ParsedSymbol<'a> = (&'a str, &'a str);
 
-pub fn parse_position_and_type(i: &str) -> IResult<&str, (ParsedPosition, ParsedSymbol)> {
+pub fn parse_position_and_type(i: &str) -> IResult<&str, (ParsedPosition, ParsedSymbol<'_>)> {
     let (str, (lat, sym1, _, long, _, sym2, heading, speed, altitude)) = tuple((
         parse_latitude,
-        take(1 as u32),
+        take(1_u32),
         // eat leftover escape tags
         many0(tag("\\")),
         parse_longitude,
         // eat any escape tags, this symbol should never be a backslash
         many0(tag("\\")),
-        take(1 as u32),
+        take(1_u32),
         three_digit_number_slash_terminated,
         three_digit_number_slash_terminated,
         preceded(tag("A="), six_digit_number),
@@ -106,6 +107,33 @@ pub fn parse_position_and_type(i: &str) -> IResult<&str, (ParsedPosition, Parsed
     ))
 }
 
+// Receivers are stationary and usually leave out course and speed, e.g.
+// `4858.00NI00820.00E&/A=000500`; both are 0 then.
+pub fn parse_beacon_position(i: &str) -> IResult<&str, (ParsedPosition, ParsedSymbol<'_>)> {
+    let (str, (lat, sym1, long, sym2, course, altitude)) = tuple((
+        parse_latitude,
+        take(1_u32),
+        parse_longitude,
+        take(1_u32),
+        opt(tuple((three_digit_number_slash_terminated, three_digit_number))),
+        preceded(tag("/A="), six_digit_number),
+    ))(i)?;
+    let (heading, speed) = course.unwrap_or((0, 0));
+    Ok((
+        str,
+        (
+            ParsedPosition {
+                latitude: lat,
+                longitude: long,
+                heading,
+                speed,
+                altitude,
+            },
+            (sym1, sym2),
+        ),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,6 +199,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parsing_beacon_position_works() {
+        let test = "4858.00NI00820.00E&/A=000500 v0.2.8";
+        let (rest, (position, symbol)) = parse_beacon_position(test).unwrap();
+        assert_eq!(rest, " v0.2.8");
+        assert_eq!((position.heading, position.speed, position.altitude), (0, 0, 500));
+        assert_eq!(symbol, ("I", "&"));
+        assert!(parse_position_and_type(test).is_err());
+
+        let test = "4952.95NI01014.94E&000/000/A=000919";
+        let (_, (position, _)) = parse_beacon_position(test).unwrap();
+        assert_eq!(position.altitude, 919);
+    }
+
     #[test]
     fn parsing_position_and_type_doesnt_panic_on_invalid_direction() {
         let test = "4832.45Y\\00803.85X^206/080/A=003503 !W75! ";
@@ -11,19 +11,19 @@ pub fn n_digit_number(i: &str) -> IResult<&str, u32> {
 }
 
 pub fn single_digit_number(i: &str) -> IResult<&str, u32> {
-    map_res(take(1 as u32), |s: &str| s.parse::<u32>())(i)
+    map_res(take(1_u32), |s: &str| s.parse::<u32>())(i)
 }
 
 pub fn two_digit_number(i: &str) -> IResult<&str, u32> {
-    map_res(take(2 as u32), |s: &str| s.parse::<u32>())(i)
+    map_res(take(2_u32), |s: &str| s.parse::<u32>())(i)
 }
 
 pub fn three_digit_number(i: &str) -> IResult<&str, u32> {
-    map_res(take(3 as u32), |s: &str| s.parse::<u32>())(i)
+    map_res(take(3_u32), |s: &str| s.parse::<u32>())(i)
 }
 
 pub fn six_digit_number(i: &str) -> IResult<&str, u32> {
-    map_res(take(6 as u32), |s: &str| s.parse::<u32>())(i)
+    map_res(take(6_u32), |s: &str| s.parse::<u32>())(i)
 }
 
 pub fn three_digit_number_slash_terminated(i: &str) -> IResult<&str, u32> {
@@ -34,7 +34,7 @@ pub fn two_digit_decimal(i: &str) -> IResult<&str, f32> {
     preceded(
         char('.'),
         map_res(
-            take(2 as u32),
+            take(2_u32),
             |s: &str| -> Result<f32, std::num::ParseFloatError> { Ok(s.parse::<f32>()? / 100.0) },
         ),
     )(i)
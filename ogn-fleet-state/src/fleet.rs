@@ -0,0 +1,279 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use ogn_aprs_parser::model::ogn_status_message::OGNStatusMessage;
+
+use crate::geo::{distance_km, BoundingBox};
+
+// bumped whenever the snapshot format changes; older snapshots are ignored then.
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub struct FleetConfig {
+    // positions kept per aircraft, oldest are dropped first.
+    pub history_len: usize,
+    // aircraft not heard for this long are stale, e.g. landed or out of receiver range.
+    pub stale_after: Duration,
+    // and are forgotten by `expire` after this long.
+    pub expire_after: Duration,
+}
+
+impl Default for FleetConfig {
+    fn default() -> Self {
+        FleetConfig {
+            history_len: 100,
+            stale_after: Duration::minutes(5),
+            expire_after: Duration::hours(24),
+        }
+    }
+}
+
+// A position from an aircraft's history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackPoint {
+    pub timestamp: DateTime<Utc>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Aircraft {
+    // the aircraft id, or the callsign if the messages carry none.
+    pub address: String,
+    pub last: OGNStatusMessage,
+    // when the last message was received, which can be later than its timestamp.
+    pub last_heard: DateTime<Utc>,
+    pub history: VecDeque<TrackPoint>,
+}
+
+impl Aircraft {
+    pub fn latitude(&self) -> f64 {
+        self.last.position.latitude as f64
+    }
+
+    pub fn longitude(&self) -> f64 {
+        self.last.position.longitude as f64
+    }
+
+    pub fn age(&self, now: DateTime<Utc>) -> Duration {
+        now - self.last_heard
+    }
+}
+
+// The latest known state of every aircraft. Queries scan all aircraft, which is plenty fast
+// for the few thousand the whole OGN sees at once.
+pub struct FleetState {
+    config: FleetConfig,
+    aircraft: HashMap<String, Aircraft>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    saved: DateTime<Utc>,
+    aircraft: Vec<Aircraft>,
+}
+
+impl FleetState {
+    pub fn new(config: FleetConfig) -> Self {
+        FleetState { config, aircraft: HashMap::new() }
+    }
+
+    pub fn config(&self) -> &FleetConfig {
+        &self.config
+    }
+
+    // remember a message received at `received`. Messages older than the latest one of the same
+    // aircraft, e.g. from a slower receiver, are ignored; returns whether the message was applied.
+    pub fn update(&mut self, msg: OGNStatusMessage, received: DateTime<Utc>) -> bool {
+        let address = msg.aircraft_id.clone().unwrap_or_else(|| msg.aprs_callsign.clone());
+        let point = TrackPoint {
+            timestamp: msg.timestamp,
+            latitude: msg.position.latitude as f64,
+            longitude: msg.position.longitude as f64,
+            altitude: msg.position.altitude as f64,
+        };
+
+        match self.aircraft.get_mut(&address) {
+            Some(aircraft) if msg.timestamp < aircraft.last.timestamp => return false,
+            Some(aircraft) => {
+                aircraft.last = msg;
+                aircraft.last_heard = received;
+                if aircraft.history.back().is_none_or(|last| last.timestamp != point.timestamp) {
+                    aircraft.history.push_back(point);
+                }
+                while aircraft.history.len() > self.config.history_len {
+                    aircraft.history.pop_front();
+                }
+            }
+            None => {
+                let history = VecDeque::from([point]);
+                self.aircraft.insert(address.clone(), Aircraft { address, last: msg, last_heard: received, history });
+            }
+        }
+        true
+    }
+
+    pub fn get(&self, address: &str) -> Option<&Aircraft> {
+        self.aircraft.get(address)
+    }
+
+    pub fn aircraft(&self) -> impl Iterator<Item = &Aircraft> {
+        self.aircraft.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.aircraft.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aircraft.is_empty()
+    }
+
+    pub fn is_stale(&self, aircraft: &Aircraft, now: DateTime<Utc>) -> bool {
+        aircraft.age(now) > self.config.stale_after
+    }
+
+    // the `count` aircraft closest to a position, with their distance in km, closest first.
+    pub fn nearest(&self, latitude: f64, longitude: f64, count: usize) -> Vec<(&Aircraft, f64)> {
+        let mut by_distance: Vec<(&Aircraft, f64)> = self
+            .aircraft
+            .values()
+            .map(|aircraft| (aircraft, distance_km((latitude, longitude), (aircraft.latitude(), aircraft.longitude()))))
+            .collect();
+        by_distance.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        by_distance.truncate(count);
+        by_distance
+    }
+
+    pub fn within(&self, bbox: &BoundingBox) -> Vec<&Aircraft> {
+        self.aircraft
+            .values()
+            .filter(|aircraft| bbox.contains(aircraft.latitude(), aircraft.longitude()))
+            .collect()
+    }
+
+    // aircraft whose last message is older than `age`, longest silent first.
+    pub fn not_heard_for(&self, age: Duration, now: DateTime<Utc>) -> Vec<&Aircraft> {
+        let mut silent: Vec<&Aircraft> = self.aircraft.values().filter(|aircraft| aircraft.age(now) > age).collect();
+        silent.sort_by_key(|aircraft| aircraft.last_heard);
+        silent
+    }
+
+    // forget aircraft not heard for `expire_after`, returns how many were removed.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> usize {
+        let before = self.aircraft.len();
+        let expire_after = self.config.expire_after;
+        self.aircraft.retain(|_, aircraft| aircraft.age(now) <= expire_after);
+        before - self.aircraft.len()
+    }
+
+    // write the whole state as JSON. The snapshot is written next to `path` and renamed,
+    // so a crash while saving leaves the previous snapshot intact.
+    pub fn save(&self, path: &Path, now: DateTime<Utc>) -> Result<()> {
+        let snapshot = Snapshot { version: SNAPSHOT_VERSION, saved: now, aircraft: self.aircraft.values().cloned().collect() };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(&snapshot)?).with_context(|| format!("writing {:?}", tmp))?;
+        fs::rename(&tmp, path).with_context(|| format!("renaming {:?} to {:?}", tmp, path))?;
+        Ok(())
+    }
+
+    // restore a snapshot written by `save`, dropping aircraft that expired since.
+    pub fn load(path: &Path, config: FleetConfig, now: DateTime<Utc>) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("reading {:?}", path))?;
+        let snapshot: Snapshot = serde_json::from_slice(&data)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            bail!("unsupported snapshot version {}", snapshot.version);
+        }
+        let aircraft = snapshot.aircraft.into_iter().map(|aircraft| (aircraft.address.clone(), aircraft)).collect();
+        let mut state = FleetState { config, aircraft };
+        state.expire(now);
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn msg(id: &str, time: &str, position: &str) -> OGNStatusMessage {
+        let line = format!("FLR{}>APRS,qAS,RHST:/{}h{}^301/050/A=001145 !W71! id22{}", id, time, position, id);
+        let date = Utc.with_ymd_and_hms(2023, 2, 19, 0, 0, 0).unwrap();
+        OGNStatusMessage::from_str(&line, Some(date)).unwrap()
+    }
+
+    fn at(h: u32, m: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 2, 19, h, m, s).unwrap()
+    }
+
+    // two gliders near Bad Rappenau, one in Munich.
+    fn fleet() -> FleetState {
+        let mut fleet = FleetState::new(FleetConfig { history_len: 2, ..FleetConfig::default() });
+        fleet.update(msg("DF153A", "154006", "4858.10N\\00820.89E"), at(15, 40, 6));
+        fleet.update(msg("DD1234", "153000", "4900.00N\\00830.00E"), at(15, 30, 0));
+        fleet.update(msg("DD5678", "154000", "4808.00N\\01134.00E"), at(15, 40, 0));
+        fleet
+    }
+
+    #[test]
+    fn keeps_latest_message_and_bounded_history() {
+        let mut fleet = fleet();
+        fleet.update(msg("DF153A", "154010", "4858.20N\\00821.00E"), at(15, 40, 10));
+        fleet.update(msg("DF153A", "154020", "4858.30N\\00821.10E"), at(15, 40, 20));
+        // late, from another receiver.
+        assert!(!fleet.update(msg("DF153A", "154008", "4858.15N\\00820.95E"), at(15, 40, 21)));
+
+        let glider = fleet.get("DF153A").unwrap();
+        assert_eq!(glider.last.timestamp, at(15, 40, 20));
+        assert_eq!(glider.last_heard, at(15, 40, 20));
+        let times: Vec<_> = glider.history.iter().map(|point| point.timestamp).collect();
+        assert_eq!(times, [at(15, 40, 10), at(15, 40, 20)]);
+    }
+
+    #[test]
+    fn answers_spatial_and_age_queries() {
+        let fleet = fleet();
+        let nearest = fleet.nearest(48.97, 8.35, 2);
+        let addresses: Vec<_> = nearest.iter().map(|(aircraft, _)| aircraft.address.as_str()).collect();
+        assert_eq!(addresses, ["DF153A", "DD1234"]);
+        assert!(nearest[0].1 < 1.0);
+
+        let bbox = BoundingBox { south: 48.5, west: 8.0, north: 49.5, east: 9.0 };
+        let mut within: Vec<_> = fleet.within(&bbox).iter().map(|aircraft| aircraft.address.clone()).collect();
+        within.sort();
+        assert_eq!(within, ["DD1234", "DF153A"]);
+
+        let now = at(15, 41, 0);
+        let silent: Vec<_> = fleet.not_heard_for(Duration::minutes(5), now).iter().map(|a| a.address.clone()).collect();
+        assert_eq!(silent, ["DD1234"]);
+        assert!(fleet.is_stale(fleet.get("DD1234").unwrap(), now));
+        assert!(!fleet.is_stale(fleet.get("DF153A").unwrap(), now));
+    }
+
+    #[test]
+    fn expires_and_survives_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fleet.json");
+        let mut fleet = fleet();
+        fleet.save(&path, at(15, 41, 0)).unwrap();
+
+        let config = FleetConfig { expire_after: Duration::minutes(15), ..FleetConfig::default() };
+        let restored = FleetState::load(&path, config, at(15, 50, 0)).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.get("DF153A"), fleet.get("DF153A"));
+        assert!(restored.get("DD1234").is_none());
+
+        assert_eq!(fleet.expire(at(16, 0, 0)), 0);
+        fleet.config.expire_after = Duration::minutes(10);
+        assert_eq!(fleet.expire(at(15, 50, 3)), 2);
+        assert!(fleet.get("DF153A").is_some());
+    }
+}
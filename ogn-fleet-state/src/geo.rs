@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+// great circle distance between two positions in degrees.
+pub fn distance_km((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (dlat, dlon) = ((lat2 - lat1).to_radians(), (lon2 - lon1).to_radians());
+    let a = (dlat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+// An area between two latitudes and two longitudes. A box with `west` > `east` crosses the
+// antimeridian, e.g. west 170, east -170.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+impl BoundingBox {
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        let in_latitude = self.south <= latitude && latitude <= self.north;
+        let in_longitude = if self.west <= self.east {
+            self.west <= longitude && longitude <= self.east
+        } else {
+            longitude >= self.west || longitude <= self.east
+        };
+        in_latitude && in_longitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_are_great_circle() {
+        // Frankfurt to Munich is about 300 km.
+        let distance = distance_km((50.11, 8.68), (48.14, 11.58));
+        assert!((distance - 300.0).abs() < 5.0, "{}", distance);
+        assert_eq!(distance_km((48.0, 8.0), (48.0, 8.0)), 0.0);
+    }
+
+    #[test]
+    fn bounding_boxes_may_cross_the_antimeridian() {
+        let germany = BoundingBox { south: 47.0, west: 5.5, north: 55.0, east: 15.5 };
+        assert!(germany.contains(48.97, 8.35));
+        assert!(!germany.contains(48.97, 20.0));
+
+        let pacific = BoundingBox { south: -20.0, west: 170.0, north: 0.0, east: -170.0 };
+        assert!(pacific.contains(-10.0, 175.0));
+        assert!(pacific.contains(-10.0, -175.0));
+        assert!(!pacific.contains(-10.0, 0.0));
+    }
+}
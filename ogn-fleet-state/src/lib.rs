@@ -0,0 +1,5 @@
+pub mod fleet;
+pub mod geo;
+
+pub use fleet::{Aircraft, FleetConfig, FleetState, TrackPoint};
+pub use geo::BoundingBox;